@@ -1,5 +1,6 @@
 use super::bindings::greentic::http::client;
 use super::bindings::greentic::secrets_store::secrets_store;
+use super::mail::GraphError;
 use super::{AuthUserRefV1, ProviderConfig};
 use serde_json::Value;
 use urlencoding::encode as url_encode;
@@ -12,11 +13,11 @@ const MS_GRAPH_CLIENT_SECRET_KEY: &str = "MS_GRAPH_CLIENT_SECRET";
 pub(crate) fn acquire_graph_token(
     cfg: &ProviderConfig,
     user: &AuthUserRefV1,
-) -> Result<String, String> {
-    let refresh_token = get_secret(&user.token_key)?;
-    let client_id = get_secret(MS_GRAPH_CLIENT_ID_KEY)?;
-    let client_secret = get_secret(MS_GRAPH_CLIENT_SECRET_KEY)?;
-    let endpoint = graph_token_endpoint(cfg, user)?;
+) -> Result<String, GraphError> {
+    let refresh_token = get_secret(&user.token_key).map_err(GraphError::permanent)?;
+    let client_id = get_secret(MS_GRAPH_CLIENT_ID_KEY).map_err(GraphError::permanent)?;
+    let client_secret = get_secret(MS_GRAPH_CLIENT_SECRET_KEY).map_err(GraphError::permanent)?;
+    let endpoint = graph_token_endpoint(cfg, user).map_err(GraphError::permanent)?;
     let scope = cfg.graph_scope.as_deref().unwrap_or(DEFAULT_GRAPH_SCOPE);
     let form = format!(
         "client_id={}&client_secret={}&grant_type=refresh_token&refresh_token={}&scope={}",
@@ -56,7 +57,7 @@ fn get_secret(key: &str) -> Result<String, String> {
     }
 }
 
-fn request_token(url: &str, body: &[u8]) -> Result<String, String> {
+fn request_token(url: &str, body: &[u8]) -> Result<String, GraphError> {
     let request = client::Request {
         method: "POST".into(),
         url: url.to_string(),
@@ -67,16 +68,16 @@ fn request_token(url: &str, body: &[u8]) -> Result<String, String> {
         body: Some(body.to_vec()),
     };
     let resp = client::send(&request, None, None)
-        .map_err(|e| format!("token exchange error: {}", e.message))?;
+        .map_err(|e| GraphError::transport(format!("token exchange error: {}", e.message)))?;
+    let body = resp.body.unwrap_or_default();
     if resp.status < 200 || resp.status >= 300 {
-        return Err(format!("token endpoint returned status {}", resp.status));
+        return Err(GraphError::from_token_response(resp.status, &body));
     }
-    let body = resp.body.unwrap_or_default();
-    let parsed: Value =
-        serde_json::from_slice(&body).map_err(|e| format!("invalid token response: {e}"))?;
+    let parsed: Value = serde_json::from_slice(&body)
+        .map_err(|e| GraphError::permanent(format!("invalid token response: {e}")))?;
     parsed
         .get("access_token")
         .and_then(Value::as_str)
         .map(|token| token.to_string())
-        .ok_or_else(|| "token response missing access_token".to_string())
+        .ok_or_else(|| GraphError::permanent("token response missing access_token".to_string()))
 }
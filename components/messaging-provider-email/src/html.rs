@@ -0,0 +1,439 @@
+/// Renders the markdown subset used in channel messages (headings, paragraphs,
+/// lists, fenced code, emphasis, inline code, links and images) as an HTML
+/// document body. Raw HTML in the source is escaped, never passed through.
+pub(crate) fn markdown_to_html(markdown: &str) -> String {
+    let mut out = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut list: Option<&'static str> = None;
+    let mut code: Option<String> = None;
+
+    for line in markdown.lines() {
+        if let Some(block) = code.as_mut() {
+            if line.trim_start().starts_with("```") {
+                out.push_str("<pre><code>");
+                out.push_str(&escape(block.trim_end_matches('\n')));
+                out.push_str("</code></pre>\n");
+                code = None;
+            } else {
+                block.push_str(line);
+                block.push('\n');
+            }
+            continue;
+        }
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            flush_paragraph(&mut out, &mut paragraph);
+            close_list(&mut out, &mut list);
+            code = Some(String::new());
+            continue;
+        }
+        if trimmed.is_empty() {
+            flush_paragraph(&mut out, &mut paragraph);
+            close_list(&mut out, &mut list);
+            continue;
+        }
+        if let Some((level, text)) = heading(trimmed) {
+            flush_paragraph(&mut out, &mut paragraph);
+            close_list(&mut out, &mut list);
+            out.push_str(&format!("<h{level}>{}</h{level}>\n", inline(text)));
+            continue;
+        }
+        if let Some((tag, item)) = list_item(trimmed) {
+            flush_paragraph(&mut out, &mut paragraph);
+            if list != Some(tag) {
+                close_list(&mut out, &mut list);
+                out.push_str(&format!("<{tag}>\n"));
+                list = Some(tag);
+            }
+            out.push_str(&format!("<li>{}</li>\n", inline(item)));
+            continue;
+        }
+        close_list(&mut out, &mut list);
+        paragraph.push(trimmed);
+    }
+    if let Some(block) = code {
+        out.push_str("<pre><code>");
+        out.push_str(&escape(block.trim_end_matches('\n')));
+        out.push_str("</code></pre>\n");
+    }
+    flush_paragraph(&mut out, &mut paragraph);
+    close_list(&mut out, &mut list);
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"></head>\n<body>\n{out}</body>\n</html>\n"
+    )
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+fn flush_paragraph(out: &mut String, paragraph: &mut Vec<&str>) {
+    if paragraph.is_empty() {
+        return;
+    }
+    let lines: Vec<String> = paragraph.iter().map(|line| inline(line)).collect();
+    out.push_str(&format!("<p>{}</p>\n", lines.join("<br>\n")));
+    paragraph.clear();
+}
+
+fn close_list(out: &mut String, list: &mut Option<&'static str>) {
+    if let Some(tag) = list.take() {
+        out.push_str(&format!("</{tag}>\n"));
+    }
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|ch| *ch == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    line[level..]
+        .strip_prefix(' ')
+        .map(|text| (level, text.trim()))
+}
+
+fn list_item(line: &str) -> Option<(&'static str, &str)> {
+    if let Some(item) = line
+        .strip_prefix("- ")
+        .or_else(|| line.strip_prefix("* "))
+        .or_else(|| line.strip_prefix("+ "))
+    {
+        return Some(("ul", item.trim()));
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        let rest = &line[digits..];
+        if let Some(item) = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") ")) {
+            return Some(("ol", item.trim()));
+        }
+    }
+    None
+}
+
+/// Inline spans: `code`, **strong**, *em* / _em_, [links](url) and
+/// ![images](src). Unterminated markers are kept as literal text.
+fn inline(text: &str) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    // `_` only opens emphasis at a word boundary, so snake_case stays literal.
+    let mut after_word = false;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '`'
+            && let Some(end) = rest[1..].find('`')
+        {
+            out.push_str(&format!("<code>{}</code>", escape(&rest[1..1 + end])));
+            rest = &rest[end + 2..];
+            after_word = false;
+            continue;
+        }
+        if (rest.starts_with("**") || (rest.starts_with("__") && !after_word))
+            && let Some(end) = rest[2..].find(&rest[..2])
+            && end > 0
+        {
+            out.push_str(&format!("<strong>{}</strong>", inline(&rest[2..2 + end])));
+            rest = &rest[end + 4..];
+            after_word = false;
+            continue;
+        }
+        if (ch == '*' || (ch == '_' && !after_word))
+            && let Some(end) = rest[1..].find(ch)
+            && end > 0
+        {
+            out.push_str(&format!("<em>{}</em>", inline(&rest[1..1 + end])));
+            rest = &rest[end + 2..];
+            after_word = false;
+            continue;
+        }
+        if ch == '!'
+            && rest[1..].starts_with('[')
+            && let Some((label, url, used)) = link(&rest[1..])
+        {
+            match safe_url(url) {
+                Some(url) => out.push_str(&format!(
+                    "<img src=\"{}\" alt=\"{}\">",
+                    escape(url),
+                    escape(label)
+                )),
+                None => out.push_str(&escape(label)),
+            }
+            rest = &rest[1 + used..];
+            after_word = false;
+            continue;
+        }
+        if ch == '['
+            && let Some((label, url, used)) = link(rest)
+        {
+            match safe_url(url) {
+                Some(url) => out.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape(url),
+                    inline(label)
+                )),
+                None => out.push_str(&inline(label)),
+            }
+            rest = &rest[used..];
+            after_word = false;
+            continue;
+        }
+        out.push_str(&escape(&rest[..ch.len_utf8()]));
+        rest = &rest[ch.len_utf8()..];
+        after_word = ch.is_alphanumeric();
+    }
+    out
+}
+
+/// Parses `[label](url)` at the start of `text`, returning the byte length used.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let label_end = text.find("](")?;
+    let label = &text[1..label_end];
+    let url_start = label_end + 2;
+    let url_len = text[url_start..].find(')')?;
+    let url = text[url_start..url_start + url_len].trim();
+    Some((label, url, url_start + url_len + 1))
+}
+
+/// Only schemes that are safe to render in a mail client become links.
+fn safe_url(url: &str) -> Option<&str> {
+    let lower = url.to_ascii_lowercase();
+    ["https://", "http://", "mailto:", "cid:"]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+        .then_some(url)
+}
+
+/// Markers that open the quoted history or signature in client-generated
+/// reply HTML (Gmail, Apple Mail, Outlook). Everything from the element that
+/// carries one of them onwards is dropped.
+const QUOTE_MARKERS: &[&str] = &[
+    "class=\"gmail_quote",
+    "class=\"gmail_signature",
+    "<blockquote type=\"cite\"",
+    "id=\"appendonsend\"",
+    "id=\"divRplyFwdMsg\"",
+    "id=\"Signature\"",
+    "class=\"moz-cite-prefix\"",
+];
+
+/// Cuts reply HTML at the first quote or signature marker.
+pub(crate) fn strip_quoted_html(html: &str) -> &str {
+    let cut = QUOTE_MARKERS
+        .iter()
+        .filter_map(|marker| html.find(marker))
+        .map(|pos| html[..pos].rfind('<').unwrap_or(pos))
+        .min();
+    match cut {
+        Some(pos) => &html[..pos],
+        None => html,
+    }
+}
+
+/// Converts an HTML body to readable plain text: block elements become line
+/// breaks, list items get a `- ` prefix, whitespace is collapsed outside
+/// `<pre>` and entities are decoded. Head, style and script content is dropped.
+pub(crate) fn html_to_text(html: &str) -> String {
+    let mut out = String::new();
+    let mut rest = html;
+    let mut skip: Option<String> = None;
+    let mut pre = 0usize;
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            if skip.is_none() {
+                push_text(&mut out, rest, pre > 0);
+            }
+            break;
+        };
+        if skip.is_none() {
+            push_text(&mut out, &rest[..start], pre > 0);
+        }
+        rest = &rest[start..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|ch| ch.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if let Some(skipped) = &skip {
+            if closing && *skipped == name {
+                skip = None;
+            }
+            continue;
+        }
+        match name.as_str() {
+            "head" | "style" | "script" | "title" if !closing => skip = Some(name),
+            "br" => out.push('\n'),
+            "pre" => {
+                if closing {
+                    pre = pre.saturating_sub(1);
+                } else {
+                    pre += 1;
+                }
+                paragraph_break(&mut out);
+            }
+            "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "blockquote" | "table" | "ul"
+            | "ol" | "hr" => paragraph_break(&mut out),
+            "li" if !closing => {
+                line_break(&mut out);
+                out.push_str("- ");
+            }
+            "div" | "tr" | "li" => line_break(&mut out),
+            "td" | "th" if closing => out.push(' '),
+            _ => {}
+        }
+    }
+    tidy(&out)
+}
+
+fn push_text(out: &mut String, raw: &str, preformatted: bool) {
+    let text = decode_entities(raw);
+    if preformatted {
+        out.push_str(&text);
+        return;
+    }
+    for ch in text.chars() {
+        if ch.is_whitespace() && ch != '\u{a0}' {
+            if !out.is_empty() && !out.ends_with([' ', '\n']) {
+                out.push(' ');
+            }
+        } else if ch == '\u{a0}' {
+            out.push(' ');
+        } else {
+            out.push(ch);
+        }
+    }
+}
+
+fn line_break(out: &mut String) {
+    let trimmed = out.trim_end_matches(' ').len();
+    out.truncate(trimmed);
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn paragraph_break(out: &mut String) {
+    line_break(out);
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+}
+
+/// Trims trailing spaces per line and collapses runs of blank lines.
+fn tidy(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let ch = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            ch.map(|ch| (ch, end))
+        });
+        match decoded {
+            Some((ch, end)) => {
+                out.push(ch);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_markdown_subset() {
+        let html = markdown_to_html(
+            "# Hello <team>\n\nSee **bold** in snake_case_name and *em* with `x<y`.\nNext line [docs](https://example.com/a?b=1&c=2).\n\n- one\n- two\n\n![logo](cid:logo.png) [bad](javascript:alert(1))\n\n```\nlet a = 1 < 2;\n```",
+        );
+        assert!(html.contains("<h1>Hello &lt;team&gt;</h1>"));
+        assert!(html.contains(
+            "<p>See <strong>bold</strong> in snake_case_name and <em>em</em> with <code>x&lt;y</code>.<br>\nNext line <a href=\"https://example.com/a?b=1&amp;c=2\">docs</a>.</p>"
+        ));
+        assert!(html.contains("<ul>\n<li>one</li>\n<li>two</li>\n</ul>"));
+        assert!(html.contains("<img src=\"cid:logo.png\" alt=\"logo\">"));
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("<pre><code>let a = 1 &lt; 2;</code></pre>"));
+    }
+
+    #[test]
+    fn converts_html_to_text() {
+        let text = html_to_text(
+            "<html><head><style>p{color:red}</style></head><body><p>Hi&nbsp;team,</p>\n<div>Line   one<br>Line two &amp; more</div><ul><li>first</li><li>second</li></ul><pre>a  b\n c</pre><!-- note --><p>Thanks &#8212; Bob</p></body></html>",
+        );
+        assert_eq!(
+            text,
+            "Hi team,\n\nLine one\nLine two & more\n\n- first\n- second\n\na  b\n c\n\nThanks \u{2014} Bob"
+        );
+    }
+
+    #[test]
+    fn strips_quoted_html_blocks() {
+        let gmail = "<div dir=\"ltr\">New reply</div><br><div class=\"gmail_quote\"><div>On Mon, Bob wrote:</div><blockquote>old</blockquote></div>";
+        assert_eq!(html_to_text(strip_quoted_html(gmail)), "New reply");
+        let apple =
+            "<div>Sounds good</div><div><br><blockquote type=\"cite\">earlier</blockquote></div>";
+        assert_eq!(html_to_text(strip_quoted_html(apple)), "Sounds good");
+        let outlook = "<div>Yes</div><div id=\"appendonsend\"></div><hr><div id=\"divRplyFwdMsg\">From: Bob</div>";
+        assert_eq!(html_to_text(strip_quoted_html(outlook)), "Yes");
+    }
+}
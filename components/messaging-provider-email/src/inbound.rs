@@ -0,0 +1,244 @@
+use super::html;
+use greentic_types::Attachment;
+use serde_json::Value;
+
+/// Lines that start a signature in plain-text bodies, compared against the
+/// trimmed, lowercased line (so `--` also covers the RFC 3676 `-- ` delimiter).
+const SIGNATURE_LINES: &[&str] = &[
+    "--",
+    "sent from my iphone",
+    "sent from my ipad",
+    "sent from my android",
+    "sent from outlook",
+    "sent from mail for windows",
+    "get outlook for ios",
+    "get outlook for android",
+];
+
+/// The user's new text from a Graph message: `uniqueBody` (the part Graph
+/// considers new) when selected, else `body`, cleaned by [`clean_content`].
+pub(crate) fn message_text(message: &Value) -> Option<String> {
+    let body = ["uniqueBody", "body"]
+        .iter()
+        .filter_map(|key| message.get(key))
+        .find(|body| {
+            body.get("content")
+                .and_then(Value::as_str)
+                .is_some_and(|content| !content.trim().is_empty())
+        })?;
+    let content = body.get("content").and_then(Value::as_str).unwrap_or("");
+    let is_html = body
+        .get("contentType")
+        .and_then(Value::as_str)
+        .is_some_and(|kind| kind.eq_ignore_ascii_case("html"));
+    clean_content(content, is_html)
+}
+
+/// The user's new text from separate plain-text and HTML bodies, preferring
+/// the plain-text part.
+pub(crate) fn body_text(text: Option<&str>, html: Option<&str>) -> Option<String> {
+    text.and_then(|text| clean_content(text, false))
+        .or_else(|| html.and_then(|html| clean_content(html, true)))
+}
+
+/// Converts HTML to text and removes quoted history and signatures.
+fn clean_content(content: &str, is_html: bool) -> Option<String> {
+    let text = if is_html {
+        html::html_to_text(html::strip_quoted_html(content))
+    } else {
+        content.replace("\r\n", "\n")
+    };
+    let text = strip_reply(&text);
+    (!text.is_empty()).then_some(text)
+}
+
+/// Cuts plain text at the first reply header (`On … wrote:`, Outlook's
+/// `-----Original Message-----` or `From:`/`Sent:` block), a run of `>` quoted
+/// lines or a signature delimiter, and trims what is left.
+pub(crate) fn strip_reply(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut end = lines.len();
+    for (idx, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        let lower = trimmed.to_ascii_lowercase();
+        let next = lines.get(idx + 1).map(|next| next.trim()).unwrap_or("");
+        let quote_header = (lower.starts_with("on ")
+            && (lower.ends_with("wrote:") || next.to_ascii_lowercase().ends_with("wrote:")))
+            || lower.starts_with("-----original message-----")
+            || (trimmed.len() >= 10 && trimmed.chars().all(|ch| ch == '_'))
+            || (lower.starts_with("from:") && outlook_header_block(&lines[idx + 1..]));
+        let signature = SIGNATURE_LINES.contains(&lower.as_str());
+        if quote_header || signature || trimmed.starts_with('>') {
+            end = idx;
+            break;
+        }
+    }
+    lines[..end]
+        .iter()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+fn outlook_header_block(lines: &[&str]) -> bool {
+    lines.iter().take(4).any(|line| {
+        let lower = line.trim().to_ascii_lowercase();
+        lower.starts_with("sent:") || lower.starts_with("date:")
+    })
+}
+
+/// `emailAddress.address` of a Graph recipient.
+pub(crate) fn address(recipient: &Value) -> Option<&str> {
+    recipient
+        .get("emailAddress")
+        .and_then(|ea| ea.get("address"))
+        .and_then(Value::as_str)
+        .filter(|address| !address.is_empty())
+}
+
+/// Comma separated addresses of a Graph recipient list, if any.
+pub(crate) fn address_list(recipients: Option<&Value>) -> Option<String> {
+    let list: Vec<&str> = recipients
+        .and_then(Value::as_array)?
+        .iter()
+        .filter_map(address)
+        .collect();
+    (!list.is_empty()).then(|| list.join(", "))
+}
+
+/// Maps a Graph `/attachments` listing to envelope attachments. File contents
+/// become `data:` URLs, reference attachments keep their source URL; inline
+/// parts (signature logos, embedded images) and attached items are skipped.
+pub(crate) fn attachments(listing: &Value) -> Vec<Attachment> {
+    let Some(items) = listing.get("value").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    items
+        .iter()
+        .filter(|item| {
+            !item
+                .get("isInline")
+                .and_then(Value::as_bool)
+                .unwrap_or(false)
+        })
+        .filter_map(|item| {
+            let kind = item
+                .get("@odata.type")
+                .and_then(Value::as_str)
+                .unwrap_or("#microsoft.graph.fileAttachment");
+            let mime_type = item
+                .get("contentType")
+                .and_then(Value::as_str)
+                .filter(|value| !value.is_empty())
+                .unwrap_or("application/octet-stream")
+                .to_string();
+            let url = match kind {
+                "#microsoft.graph.fileAttachment" => {
+                    let bytes = item.get("contentBytes").and_then(Value::as_str)?;
+                    format!("data:{mime_type};base64,{bytes}")
+                }
+                "#microsoft.graph.referenceAttachment" => {
+                    item.get("sourceUrl").and_then(Value::as_str)?.to_string()
+                }
+                _ => return None,
+            };
+            Some(Attachment {
+                mime_type,
+                url,
+                name: item.get("name").and_then(Value::as_str).map(str::to_string),
+                size_bytes: item.get("size").and_then(Value::as_u64),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strips_quotes_and_signatures() {
+        let gmail = "Sounds good.\n\nOn Mon, Jan 6, 2025 at 10:00 AM Bob <bob@example.com> wrote:\n> earlier";
+        assert_eq!(strip_reply(gmail), "Sounds good.");
+        let wrapped = "Yes\n\nOn Mon, Jan 6, 2025 at 10:00 AM Bob\n<bob@example.com> wrote:\n> old";
+        assert_eq!(strip_reply(wrapped), "Yes");
+        let outlook = "Approved\n\nFrom: Bob <bob@example.com>\nSent: Monday, January 6, 2025 10:00\nTo: Alice\nSubject: Re: plan";
+        assert_eq!(strip_reply(outlook), "Approved");
+        let separator = "Ok\n________________________________\nFrom: Bob";
+        assert_eq!(strip_reply(separator), "Ok");
+        let signature = "Thanks!\n-- \nAlice\nACME Corp";
+        assert_eq!(strip_reply(signature), "Thanks!");
+        let mobile = "On my way\n\nSent from my iPhone";
+        assert_eq!(strip_reply(mobile), "On my way");
+        assert_eq!(
+            strip_reply("From: the team we heard\nthat it works"),
+            "From: the team we heard\nthat it works"
+        );
+    }
+
+    #[test]
+    fn message_text_prefers_unique_body() {
+        let message = json!({
+            "uniqueBody": {"contentType": "html", "content": "<div>Only new</div>"},
+            "body": {"contentType": "html", "content": "<div>Only new</div><div class=\"gmail_quote\">old</div>"}
+        });
+        assert_eq!(message_text(&message).as_deref(), Some("Only new"));
+        let message = json!({
+            "body": {"contentType": "text", "content": "Plain\r\n\r\n> quoted"}
+        });
+        assert_eq!(message_text(&message).as_deref(), Some("Plain"));
+        assert_eq!(message_text(&json!({})), None);
+    }
+
+    #[test]
+    fn maps_graph_attachments() {
+        let listing = json!({
+            "value": [
+                {
+                    "@odata.type": "#microsoft.graph.fileAttachment",
+                    "name": "report.pdf",
+                    "contentType": "application/pdf",
+                    "size": 4,
+                    "isInline": false,
+                    "contentBytes": "JVBERg=="
+                },
+                {
+                    "@odata.type": "#microsoft.graph.fileAttachment",
+                    "name": "logo.png",
+                    "contentType": "image/png",
+                    "isInline": true,
+                    "contentBytes": "AAAA"
+                },
+                {
+                    "@odata.type": "#microsoft.graph.referenceAttachment",
+                    "name": "plan.docx",
+                    "sourceUrl": "https://contoso.sharepoint.com/plan.docx"
+                },
+                {
+                    "@odata.type": "#microsoft.graph.itemAttachment",
+                    "name": "forwarded"
+                }
+            ]
+        });
+        let attachments = attachments(&listing);
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0].url, "data:application/pdf;base64,JVBERg==");
+        assert_eq!(attachments[0].name.as_deref(), Some("report.pdf"));
+        assert_eq!(attachments[0].size_bytes, Some(4));
+        assert_eq!(
+            attachments[1].url,
+            "https://contoso.sharepoint.com/plan.docx"
+        );
+        assert_eq!(
+            address_list(Some(&json!([
+                {"emailAddress": {"address": "a@example.com"}},
+                {"emailAddress": {"name": "no address"}}
+            ])))
+            .as_deref(),
+            Some("a@example.com")
+        );
+    }
+}
@@ -0,0 +1,981 @@
+use super::bindings::greentic::state::state_store;
+use super::inbound;
+use super::mime::Mailbox;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use greentic_types::Attachment;
+use greentic_types::messaging::universal_dto::Header;
+use hmac::{Hmac, Mac};
+use serde_json::{Value, json};
+use sha2::Sha256;
+use urlencoding::decode as url_decode;
+
+/// Mailgun signs `timestamp + token`; deliveries older than this are rejected.
+pub(crate) const MAILGUN_MAX_AGE_SECONDS: i64 = 5 * 60;
+
+/// Inbound-parse services whose webhooks are understood.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ParseProvider {
+    SendGrid,
+    Mailgun,
+    Postmark,
+}
+
+impl ParseProvider {
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            ParseProvider::SendGrid => "sendgrid",
+            ParseProvider::Mailgun => "mailgun",
+            ParseProvider::Postmark => "postmark",
+        }
+    }
+}
+
+/// A decoded inbound-parse request.
+pub(crate) enum ParseRequest {
+    /// multipart/form-data or urlencoded fields (SendGrid, Mailgun).
+    Form(ParseProvider, Vec<FormPart>),
+    /// Postmark's JSON payload.
+    Json(Value),
+}
+
+impl ParseRequest {
+    pub(crate) fn provider(&self) -> ParseProvider {
+        match self {
+            ParseRequest::Form(provider, _) => *provider,
+            ParseRequest::Json(_) => ParseProvider::Postmark,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FormPart {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Normalized inbound email, independent of the service that delivered it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ParsedEmail {
+    pub from: Option<Mailbox>,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    pub subject: String,
+    pub text: Option<String>,
+    pub html: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub references: Option<String>,
+    pub attachments: Vec<Attachment>,
+}
+
+impl ParsedEmail {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .filter(|value| !value.trim().is_empty())
+    }
+
+    /// Message-ID / threading headers come from the raw headers when the
+    /// service does not send them as separate fields.
+    fn fill_thread_headers(&mut self) {
+        if self.message_id.is_none() {
+            self.message_id = self.header("Message-ID").map(|v| v.trim().to_string());
+        }
+        if self.in_reply_to.is_none() {
+            self.in_reply_to = self.header("In-Reply-To").map(|v| v.trim().to_string());
+        }
+        if self.references.is_none() {
+            self.references = self.header("References").map(|v| v.trim().to_string());
+        }
+    }
+
+    /// The user's new text: the service's stripped reply when given, else the
+    /// plain or HTML body with quotes and signatures removed.
+    pub(crate) fn clean_text(&self) -> Option<String> {
+        inbound::body_text(self.text.as_deref(), self.html.as_deref())
+    }
+
+    pub(crate) fn headers_json(&self) -> String {
+        let pairs: Vec<Value> = self
+            .headers
+            .iter()
+            .map(|(name, value)| json!([name, value]))
+            .collect();
+        Value::Array(pairs).to_string()
+    }
+}
+
+/// Recognizes an inbound-parse request by content type and fields. Graph
+/// change notifications (JSON with a `value` array) are not matched.
+pub(crate) fn detect(headers: &[Header], body: &[u8]) -> Result<Option<ParseRequest>, String> {
+    let content_type = header_value(headers, "content-type").unwrap_or("");
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    let parts = match media_type.as_str() {
+        "multipart/form-data" => {
+            let boundary = parameter(content_type, "boundary")
+                .ok_or_else(|| "multipart body without boundary".to_string())?;
+            multipart_parts(body, &boundary)?
+        }
+        "application/x-www-form-urlencoded" => urlencoded_parts(body),
+        "application/json" | "" => {
+            let value: Value = match serde_json::from_slice(body) {
+                Ok(value) => value,
+                Err(_) => return Ok(None),
+            };
+            let postmark = value.get("FromFull").is_some()
+                || (value.get("From").is_some() && value.get("MessageID").is_some());
+            return Ok(postmark.then_some(ParseRequest::Json(value)));
+        }
+        _ => return Ok(None),
+    };
+    let has = |name: &str| parts.iter().any(|part| part.name == name);
+    let provider = if has("signature") && has("token") && has("timestamp") {
+        ParseProvider::Mailgun
+    } else {
+        ParseProvider::SendGrid
+    };
+    Ok(Some(ParseRequest::Form(provider, parts)))
+}
+
+pub(crate) fn parse(request: &ParseRequest) -> Result<ParsedEmail, String> {
+    let mut email = match request {
+        ParseRequest::Form(ParseProvider::Mailgun, parts) => from_mailgun(parts)?,
+        ParseRequest::Form(_, parts) => from_sendgrid(parts)?,
+        ParseRequest::Json(value) => from_postmark(value)?,
+    };
+    email.fill_thread_headers();
+    if email.from.is_none() {
+        return Err("inbound email without sender".into());
+    }
+    Ok(email)
+}
+
+/// SendGrid Inbound Parse: header fields, `headers` as a raw block and files
+/// `attachment1..N` described by `attachment-info`.
+fn from_sendgrid(parts: &[FormPart]) -> Result<ParsedEmail, String> {
+    if field(parts, "email").is_some() && field(parts, "text").is_none() {
+        return Err(
+            "raw MIME posts are not supported; disable \"POST the raw, full MIME message\"".into(),
+        );
+    }
+    let info: Value = field(parts, "attachment-info")
+        .and_then(|info| serde_json::from_str(&info).ok())
+        .unwrap_or(Value::Null);
+    let attachments = parts
+        .iter()
+        .filter(|part| part.filename.is_some())
+        .filter(|part| {
+            // Parts with a Content-ID are inline images referenced by the HTML.
+            info.get(&part.name)
+                .and_then(|meta| meta.get("content-id"))
+                .and_then(Value::as_str)
+                .is_none_or(str::is_empty)
+        })
+        .map(file_attachment)
+        .collect();
+    Ok(ParsedEmail {
+        from: field(parts, "from").and_then(|from| first_mailbox(&from)),
+        to: addresses(field(parts, "to").as_deref()),
+        cc: addresses(field(parts, "cc").as_deref()),
+        subject: field(parts, "subject").unwrap_or_default(),
+        text: field(parts, "text"),
+        html: field(parts, "html"),
+        headers: field(parts, "headers")
+            .map(|block| header_block(&block))
+            .unwrap_or_default(),
+        attachments,
+        ..ParsedEmail::default()
+    })
+}
+
+/// Mailgun routes: `stripped-text` is preferred over `body-plain`, headers come
+/// as `message-headers` JSON and inline files are listed in `content-id-map`.
+fn from_mailgun(parts: &[FormPart]) -> Result<ParsedEmail, String> {
+    let headers: Vec<(String, String)> = field(parts, "message-headers")
+        .and_then(|raw| serde_json::from_str::<Vec<(String, String)>>(&raw).ok())
+        .unwrap_or_default();
+    let inline: Vec<String> = field(parts, "content-id-map")
+        .and_then(|raw| serde_json::from_str::<Value>(&raw).ok())
+        .and_then(|map| {
+            map.as_object().map(|map| {
+                map.values()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+        })
+        .unwrap_or_default();
+    let attachments = parts
+        .iter()
+        .filter(|part| part.filename.is_some() && !inline.contains(&part.name))
+        .map(file_attachment)
+        .collect();
+    let text = field(parts, "stripped-text")
+        .filter(|text| !text.trim().is_empty())
+        .or_else(|| field(parts, "body-plain"));
+    Ok(ParsedEmail {
+        from: field(parts, "from")
+            .or_else(|| field(parts, "sender"))
+            .and_then(|from| first_mailbox(&from)),
+        to: addresses(
+            field(parts, "To")
+                .or_else(|| field(parts, "recipient"))
+                .as_deref(),
+        ),
+        cc: addresses(field(parts, "Cc").as_deref()),
+        subject: field(parts, "subject").unwrap_or_default(),
+        text,
+        html: field(parts, "body-html"),
+        message_id: field(parts, "Message-Id"),
+        in_reply_to: field(parts, "In-Reply-To"),
+        references: field(parts, "References"),
+        headers,
+        attachments,
+    })
+}
+
+/// Postmark inbound JSON. `StrippedTextReply` is preferred when present;
+/// attachments carry base64 `Content` and inline ones a `ContentID`.
+fn from_postmark(value: &Value) -> Result<ParsedEmail, String> {
+    let str_field = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    };
+    let full_list = |key: &str| -> Vec<String> {
+        value
+            .get(key)
+            .and_then(Value::as_array)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| item.get("Email").and_then(Value::as_str))
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let from = value
+        .get("FromFull")
+        .and_then(|full| {
+            full.get("Email")
+                .and_then(Value::as_str)
+                .map(|email| Mailbox {
+                    name: full
+                        .get("Name")
+                        .and_then(Value::as_str)
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_string),
+                    address: email.to_string(),
+                })
+        })
+        .or_else(|| str_field("From").and_then(|from| first_mailbox(&from)));
+    let mut to = full_list("ToFull");
+    if to.is_empty() {
+        to = addresses(str_field("To").as_deref());
+    }
+    let mut cc = full_list("CcFull");
+    if cc.is_empty() {
+        cc = addresses(str_field("Cc").as_deref());
+    }
+    let headers = value
+        .get("Headers")
+        .and_then(Value::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| {
+                    Some((
+                        item.get("Name")?.as_str()?.to_string(),
+                        item.get("Value")?.as_str()?.to_string(),
+                    ))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut attachments = Vec::new();
+    for item in value
+        .get("Attachments")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let inline = item
+            .get("ContentID")
+            .and_then(Value::as_str)
+            .is_some_and(|id| !id.is_empty());
+        let Some(content) = item.get("Content").and_then(Value::as_str) else {
+            continue;
+        };
+        if inline {
+            continue;
+        }
+        let mime_type = item
+            .get("ContentType")
+            .and_then(Value::as_str)
+            .unwrap_or("application/octet-stream");
+        attachments.push(Attachment {
+            mime_type: mime_type.to_string(),
+            url: format!("data:{mime_type};base64,{content}"),
+            name: item.get("Name").and_then(Value::as_str).map(str::to_string),
+            size_bytes: item.get("ContentLength").and_then(Value::as_u64),
+        });
+    }
+    let text = str_field("StrippedTextReply").or_else(|| str_field("TextBody"));
+    Ok(ParsedEmail {
+        from,
+        to,
+        cc,
+        subject: str_field("Subject").unwrap_or_default(),
+        text,
+        html: str_field("HtmlBody"),
+        headers,
+        attachments,
+        ..ParsedEmail::default()
+    })
+}
+
+/// A verified Mailgun token with the timestamp it was signed with.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MailgunToken {
+    pub token: String,
+    pub issued: i64,
+}
+
+/// Mailgun webhook signature: hex HMAC-SHA256 of `timestamp + token` keyed with
+/// the signing key, and a timestamp within [`MAILGUN_MAX_AGE_SECONDS`] of now.
+pub(crate) fn verify_mailgun(
+    parts: &[FormPart],
+    signing_key: &str,
+    now: i64,
+) -> Result<MailgunToken, String> {
+    let timestamp = field(parts, "timestamp").ok_or("missing_timestamp")?;
+    let token = field(parts, "token").ok_or("missing_token")?;
+    let signature = field(parts, "signature").ok_or("missing_signature")?;
+    let issued: i64 = timestamp
+        .trim()
+        .parse()
+        .map_err(|_| "malformed_timestamp")?;
+    if !within_window(issued, now) {
+        return Err("stale_timestamp".into());
+    }
+    let expected = hex_decode(signature.trim()).ok_or("malformed_signature")?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(signing_key.as_bytes()).map_err(|_| "invalid_key")?;
+    mac.update(timestamp.trim().as_bytes());
+    mac.update(token.trim().as_bytes());
+    mac.verify_slice(&expected)
+        .map_err(|_| "signature_mismatch".to_string())?;
+    Ok(MailgunToken {
+        token: token.trim().to_string(),
+        issued,
+    })
+}
+
+fn within_window(issued: i64, now: i64) -> bool {
+    now.abs_diff(issued) <= MAILGUN_MAX_AGE_SECONDS as u64
+}
+
+const MAILGUN_BUCKETS_KEY: &str = "email:mailgun-tokens:buckets";
+
+/// Ledger bucket of a signed timestamp: one per [`MAILGUN_MAX_AGE_SECONDS`].
+fn token_bucket(issued: i64) -> i64 {
+    issued.div_euclid(MAILGUN_MAX_AGE_SECONDS)
+}
+
+fn token_bucket_key(bucket: i64) -> String {
+    format!("email:mailgun-tokens:{bucket}")
+}
+
+/// Splits the known buckets into those that can no longer receive a token
+/// (every timestamp they cover is outside the window) and those still live.
+fn split_expired_buckets(buckets: Vec<i64>, now: i64) -> (Vec<i64>, Vec<i64>) {
+    let oldest = token_bucket(now.saturating_sub(MAILGUN_MAX_AGE_SECONDS));
+    buckets.into_iter().partition(|bucket| *bucket < oldest)
+}
+
+/// Records a verified Mailgun token in the ledger bucket of its signed
+/// timestamp. Returns `false` when the timestamp is outside the window or the
+/// token was already used, i.e. the post is a replay of a captured delivery.
+/// Buckets whose timestamps have all left the window are deleted, so the
+/// ledger only ever holds the tokens that could still be replayed.
+pub(crate) fn claim_mailgun_token(token: &MailgunToken, now: i64) -> Result<bool, String> {
+    if !within_window(token.issued, now) {
+        return Ok(false);
+    }
+    let bucket = token_bucket(token.issued);
+    let key = token_bucket_key(bucket);
+    let mut tokens: Vec<String> = read_json(&key)?.unwrap_or_default();
+    if tokens.contains(&token.token) {
+        return Ok(false);
+    }
+    tokens.push(token.token.clone());
+    write_json(&key, &tokens)?;
+
+    let buckets: Vec<i64> = read_json(MAILGUN_BUCKETS_KEY)?.unwrap_or_default();
+    let (expired, mut live) = split_expired_buckets(buckets, now);
+    for old in &expired {
+        match state_store::delete(&token_bucket_key(*old), None) {
+            Ok(_) => {}
+            Err(err) if is_not_found(&err.code) => {}
+            Err(err) => {
+                return Err(format!(
+                    "state delete error: {} - {}",
+                    err.code, err.message
+                ));
+            }
+        }
+    }
+    let known = live.contains(&bucket);
+    if !known {
+        live.push(bucket);
+    }
+    if !known || !expired.is_empty() {
+        write_json(MAILGUN_BUCKETS_KEY, &live)?;
+    }
+    Ok(true)
+}
+
+fn is_not_found(code: &str) -> bool {
+    code.to_ascii_lowercase().replace('-', "_") == "not_found"
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(key: &str) -> Result<Option<T>, String> {
+    match state_store::read(key, None) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|err| format!("invalid state at {key}: {err}")),
+        Err(err) if is_not_found(&err.code) => Ok(None),
+        Err(err) => Err(format!("state read error: {} - {}", err.code, err.message)),
+    }
+}
+
+fn write_json<T: serde::Serialize>(key: &str, value: &T) -> Result<(), String> {
+    let bytes = serde_json::to_vec(value).map_err(|err| format!("serialize {key}: {err}"))?;
+    state_store::write(key, &bytes, None)
+        .map(|_| ())
+        .map_err(|err| format!("state write error: {} - {}", err.code, err.message))
+}
+
+/// Secrets configured for inbound-parse verification.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct InboundSecrets<'a> {
+    pub mailgun_key: Option<&'a str>,
+    pub shared_secret: Option<&'a str>,
+}
+
+/// Checks a post against the configured secrets. The sender decides which
+/// fields a post carries, so the checks follow the secrets instead: with a
+/// Mailgun key a form post may pass the signature check, with a shared secret
+/// any post may pass the shared-secret check, and with neither every post is
+/// rejected. Returns the Mailgun token of a signed post so the caller can
+/// reject replays within the signature window.
+pub(crate) fn verify(
+    request: &ParseRequest,
+    headers: &[Header],
+    query: Option<&str>,
+    secrets: InboundSecrets<'_>,
+    now: i64,
+) -> Result<Option<MailgunToken>, String> {
+    let mailgun = secrets.mailgun_key.map(|key| match request {
+        ParseRequest::Form(_, parts) => verify_mailgun(parts, key, now),
+        ParseRequest::Json(_) => Err("missing_signature".to_string()),
+    });
+    let shared = secrets
+        .shared_secret
+        .map(|secret| verify_shared_secret(headers, query, secret));
+    match (mailgun, shared) {
+        (Some(Ok(token)), _) => Ok(Some(token)),
+        (_, Some(Ok(()))) => Ok(None),
+        (Some(Err(mailgun)), Some(Err(shared))) => {
+            Err(if request.provider() == ParseProvider::Mailgun {
+                mailgun
+            } else {
+                shared
+            })
+        }
+        (Some(Err(reason)), None) | (None, Some(Err(reason))) => Err(reason),
+        (None, None) => Err("not_configured".to_string()),
+    }
+}
+
+/// Shared-secret check for SendGrid and Postmark, which cannot sign requests:
+/// the secret may be the Basic auth password (or the whole `user:password`),
+/// the `X-Inbound-Secret` header, or the `secret` / `token` query parameter of
+/// the configured webhook URL.
+pub(crate) fn verify_shared_secret(
+    headers: &[Header],
+    query: Option<&str>,
+    secret: &str,
+) -> Result<(), String> {
+    let mut candidates: Vec<String> = Vec::new();
+    if let Some(credentials) = header_value(headers, "authorization")
+        .and_then(|value| {
+            value
+                .strip_prefix("Basic ")
+                .or(value.strip_prefix("basic "))
+        })
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+    {
+        if let Some((_, password)) = credentials.split_once(':') {
+            candidates.push(password.to_string());
+        }
+        candidates.push(credentials);
+    }
+    if let Some(value) = header_value(headers, "x-inbound-secret") {
+        candidates.push(value.to_string());
+    }
+    for (key, value) in urlencoded_pairs(query.unwrap_or("")) {
+        if key == "secret" || key == "token" {
+            candidates.push(value);
+        }
+    }
+    if candidates.is_empty() {
+        return Err("missing_credentials".into());
+    }
+    if candidates
+        .iter()
+        .any(|candidate| constant_time_eq(candidate.as_bytes(), secret.as_bytes()))
+    {
+        Ok(())
+    } else {
+        Err("credentials_mismatch".into())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub(crate) fn field(parts: &[FormPart], name: &str) -> Option<String> {
+    parts
+        .iter()
+        .find(|part| part.name == name && part.filename.is_none())
+        .map(|part| String::from_utf8_lossy(&part.data).into_owned())
+}
+
+fn file_attachment(part: &FormPart) -> Attachment {
+    let mime_type = part
+        .content_type
+        .clone()
+        .unwrap_or_else(|| "application/octet-stream".into());
+    Attachment {
+        url: format!("data:{mime_type};base64,{}", STANDARD.encode(&part.data)),
+        mime_type,
+        name: part.filename.clone(),
+        size_bytes: Some(part.data.len() as u64),
+    }
+}
+
+fn first_mailbox(value: &str) -> Option<Mailbox> {
+    Mailbox::parse_list(value).into_iter().next()
+}
+
+fn addresses(value: Option<&str>) -> Vec<String> {
+    value
+        .map(Mailbox::parse_list)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|mailbox| mailbox.address)
+        .collect()
+}
+
+/// Parses a raw header block, unfolding continuation lines.
+fn header_block(block: &str) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in block.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+fn header_value<'a>(headers: &'a [Header], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case(name))
+        .map(|header| header.value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+fn parameter(content_type: &str, name: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn multipart_parts(body: &[u8], boundary: &str) -> Result<Vec<FormPart>, String> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut segments = split_bytes(body, delimiter.as_bytes()).into_iter();
+    // Anything before the first delimiter is preamble.
+    segments.next();
+    for segment in segments {
+        if segment.starts_with(b"--") {
+            break;
+        }
+        let segment = segment.strip_prefix(b"\r\n").unwrap_or(segment);
+        let segment = segment.strip_suffix(b"\r\n").unwrap_or(segment);
+        let split = find_bytes(segment, b"\r\n\r\n")
+            .ok_or_else(|| "multipart part without headers".to_string())?;
+        let head = String::from_utf8_lossy(&segment[..split]);
+        let data = segment[split + 4..].to_vec();
+        let mut name = None;
+        let mut filename = None;
+        let mut content_type = None;
+        for (key, value) in header_block(&head) {
+            if key.eq_ignore_ascii_case("content-disposition") {
+                name = parameter(&value, "name");
+                filename = parameter(&value, "filename");
+            } else if key.eq_ignore_ascii_case("content-type") {
+                content_type = Some(value);
+            }
+        }
+        let Some(name) = name else {
+            continue;
+        };
+        parts.push(FormPart {
+            name,
+            filename,
+            content_type,
+            data,
+        });
+    }
+    Ok(parts)
+}
+
+fn urlencoded_parts(body: &[u8]) -> Vec<FormPart> {
+    urlencoded_pairs(&String::from_utf8_lossy(body))
+        .into_iter()
+        .map(|(name, value)| FormPart {
+            name,
+            filename: None,
+            content_type: None,
+            data: value.into_bytes(),
+        })
+        .collect()
+}
+
+fn urlencoded_pairs(raw: &str) -> Vec<(String, String)> {
+    raw.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |text: &str| {
+                let text = text.replace('+', " ");
+                url_decode(&text)
+                    .map(|decoded| decoded.into_owned())
+                    .unwrap_or(text)
+            };
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+fn split_bytes<'a>(haystack: &'a [u8], needle: &[u8]) -> Vec<&'a [u8]> {
+    let mut out = Vec::new();
+    let mut rest = haystack;
+    while let Some(pos) = find_bytes(rest, needle) {
+        out.push(&rest[..pos]);
+        rest = &rest[pos + needle.len()..];
+    }
+    out.push(rest);
+    out
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn hex_decode(value: &str) -> Option<Vec<u8>> {
+    if value.is_empty() || !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(value.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "xYzZY";
+
+    fn multipart(fields: &[(&str, &str)], file: Option<(&str, &str, &str, &[u8])>) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+                )
+                .as_bytes(),
+            );
+        }
+        if let Some((name, filename, mime, data)) = file {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"; filename=\"{filename}\"\r\nContent-Type: {mime}\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    fn content_type(value: &str) -> Vec<Header> {
+        vec![Header {
+            name: "Content-Type".into(),
+            value: value.into(),
+        }]
+    }
+
+    #[test]
+    fn parses_sendgrid_multipart() {
+        let body = multipart(
+            &[
+                ("from", "Bob Smith <bob@example.com>"),
+                ("to", "support@inbound.example.com"),
+                ("cc", "carol@example.com"),
+                ("subject", "Re: Order 42"),
+                (
+                    "text",
+                    "Where is it?\n\nOn Mon, Support <support@example.com> wrote:\n> shipped",
+                ),
+                (
+                    "headers",
+                    "Message-ID: <m2@example.com>\nIn-Reply-To: <m1@example.com>\nReferences: <m0@example.com>\n <m1@example.com>",
+                ),
+                ("attachments", "1"),
+                (
+                    "attachment-info",
+                    r#"{"attachment1":{"filename":"invoice.pdf","type":"application/pdf"}}"#,
+                ),
+            ],
+            Some(("attachment1", "invoice.pdf", "application/pdf", b"%PDF")),
+        );
+        let request = detect(
+            &content_type(&format!("multipart/form-data; boundary={BOUNDARY}")),
+            &body,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.provider(), ParseProvider::SendGrid);
+        let email = parse(&request).unwrap();
+        assert_eq!(email.from.as_ref().unwrap().address, "bob@example.com");
+        assert_eq!(email.to, vec!["support@inbound.example.com".to_string()]);
+        assert_eq!(email.cc, vec!["carol@example.com".to_string()]);
+        assert_eq!(email.clean_text().as_deref(), Some("Where is it?"));
+        assert_eq!(email.message_id.as_deref(), Some("<m2@example.com>"));
+        assert_eq!(email.in_reply_to.as_deref(), Some("<m1@example.com>"));
+        assert_eq!(
+            email.references.as_deref(),
+            Some("<m0@example.com> <m1@example.com>")
+        );
+        assert_eq!(email.attachments.len(), 1);
+        assert_eq!(
+            email.attachments[0].url,
+            "data:application/pdf;base64,JVBERg=="
+        );
+    }
+
+    #[test]
+    fn parses_and_verifies_mailgun() {
+        let key = "mailgun-key";
+        let timestamp = "1700000000";
+        let token = "tok-1";
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(format!("{timestamp}{token}").as_bytes());
+        let signature: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let body = format!(
+            "from=Bob+%3Cbob%40example.com%3E&recipient=bot%40mg.example.com&subject=Hi&body-plain=full&stripped-text=Just+this&Message-Id=%3Cmg1%40example.com%3E&timestamp={timestamp}&token={token}&signature={signature}"
+        );
+        let request = detect(
+            &content_type("application/x-www-form-urlencoded"),
+            body.as_bytes(),
+        )
+        .unwrap()
+        .unwrap();
+        let ParseRequest::Form(provider, parts) = &request else {
+            panic!("expected form request");
+        };
+        assert_eq!(*provider, ParseProvider::Mailgun);
+        assert_eq!(
+            verify_mailgun(parts, key, 1_700_000_100),
+            Ok(MailgunToken {
+                token: token.to_string(),
+                issued: 1_700_000_000,
+            })
+        );
+        assert_eq!(
+            verify_mailgun(parts, "other", 1_700_000_100),
+            Err("signature_mismatch".into())
+        );
+        assert_eq!(
+            verify_mailgun(parts, key, 1_700_000_000 + MAILGUN_MAX_AGE_SECONDS + 1),
+            Err("stale_timestamp".into())
+        );
+        let email = parse(&request).unwrap();
+        assert_eq!(email.to, vec!["bot@mg.example.com".to_string()]);
+        assert_eq!(email.clean_text().as_deref(), Some("Just this"));
+        assert_eq!(email.message_id.as_deref(), Some("<mg1@example.com>"));
+    }
+
+    #[test]
+    fn parses_postmark_json() {
+        let body = json!({
+            "FromFull": {"Email": "bob@example.com", "Name": "Bob"},
+            "ToFull": [{"Email": "bot@inbound.postmarkapp.com", "Name": ""}],
+            "CcFull": [],
+            "Subject": "Question",
+            "MessageID": "22c74902-a0c1-4511-804f-341342852c90",
+            "TextBody": "Full body\n\n> quoted",
+            "StrippedTextReply": "",
+            "HtmlBody": "<p>Full body</p>",
+            "Headers": [
+                {"Name": "Message-ID", "Value": "<pm1@example.com>"},
+                {"Name": "In-Reply-To", "Value": "<pm0@example.com>"}
+            ],
+            "Attachments": [
+                {"Name": "a.txt", "Content": "aGk=", "ContentType": "text/plain", "ContentLength": 2},
+                {"Name": "logo.png", "Content": "AAAA", "ContentType": "image/png", "ContentID": "logo@x"}
+            ]
+        });
+        let request = detect(
+            &content_type("application/json"),
+            &serde_json::to_vec(&body).unwrap(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.provider(), ParseProvider::Postmark);
+        let email = parse(&request).unwrap();
+        assert_eq!(email.from.as_ref().unwrap().name.as_deref(), Some("Bob"));
+        assert_eq!(email.clean_text().as_deref(), Some("Full body"));
+        assert_eq!(email.message_id.as_deref(), Some("<pm1@example.com>"));
+        assert_eq!(email.attachments.len(), 1);
+
+        let graph = json!({"value": [{"resource": "x"}]});
+        assert!(
+            detect(
+                &content_type("application/json"),
+                &serde_json::to_vec(&graph).unwrap()
+            )
+            .unwrap()
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn shared_secret_accepts_basic_auth_header_or_query() {
+        let basic = vec![Header {
+            name: "Authorization".into(),
+            value: format!("Basic {}", STANDARD.encode("inbound:s3cret")),
+        }];
+        assert!(verify_shared_secret(&basic, None, "s3cret").is_ok());
+        assert!(verify_shared_secret(&basic, None, "inbound:s3cret").is_ok());
+        assert!(verify_shared_secret(&[], Some("token=s3cret"), "s3cret").is_ok());
+        assert_eq!(
+            verify_shared_secret(&[], Some("token=nope"), "s3cret"),
+            Err("credentials_mismatch".into())
+        );
+        assert_eq!(
+            verify_shared_secret(&[], None, "s3cret"),
+            Err("missing_credentials".into())
+        );
+    }
+
+    #[test]
+    fn verification_follows_configured_secrets() {
+        // A form without Mailgun's fields is not exempt from the Mailgun check.
+        let body = b"from=Mallory+%3Cm%40example.com%3E&subject=Hi&text=forged";
+        let request = detect(&content_type("application/x-www-form-urlencoded"), body)
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.provider(), ParseProvider::SendGrid);
+        let mailgun_only = InboundSecrets {
+            mailgun_key: Some("key-1"),
+            shared_secret: None,
+        };
+        assert_eq!(
+            verify(&request, &[], None, mailgun_only, 0),
+            Err("missing_timestamp".into())
+        );
+        assert_eq!(
+            verify(&request, &[], None, InboundSecrets::default(), 0),
+            Err("not_configured".into())
+        );
+        let both = InboundSecrets {
+            mailgun_key: Some("key-1"),
+            shared_secret: Some("s3cret"),
+        };
+        assert_eq!(
+            verify(&request, &[], Some("secret=s3cret"), both, 0),
+            Ok(None)
+        );
+        assert_eq!(
+            verify(&request, &[], Some("secret=nope"), both, 0),
+            Err("credentials_mismatch".into())
+        );
+        let postmark = ParseRequest::Json(json!({"From": "a@example.com"}));
+        assert_eq!(
+            verify(&postmark, &[], None, mailgun_only, 0),
+            Err("missing_signature".into())
+        );
+    }
+
+    #[test]
+    fn token_buckets_expire_once_outside_the_window() {
+        let now = 1_700_000_000;
+        let oldest_accepted = now - MAILGUN_MAX_AGE_SECONDS;
+        assert!(within_window(oldest_accepted, now));
+        assert!(!within_window(oldest_accepted - 1, now));
+        assert!(!within_window(now + MAILGUN_MAX_AGE_SECONDS + 1, now));
+        let buckets = vec![
+            token_bucket(oldest_accepted) - 1,
+            token_bucket(oldest_accepted),
+            token_bucket(now),
+        ];
+        let (expired, live) = split_expired_buckets(buckets, now);
+        assert_eq!(expired, vec![token_bucket(oldest_accepted) - 1]);
+        assert_eq!(live, vec![token_bucket(oldest_accepted), token_bucket(now)]);
+        assert_eq!(token_bucket(-1), -1);
+    }
+}
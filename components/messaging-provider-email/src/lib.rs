@@ -6,9 +6,9 @@ use greentic_types::messaging::universal_dto::{
     SubscriptionDeleteOutV1, SubscriptionEnsureInV1, SubscriptionEnsureOutV1,
     SubscriptionRenewInV1, SubscriptionRenewOutV1,
 };
+use mime::{Mailbox, MimeAttachment, MimeMessage};
 use serde::Deserialize;
 use serde_json::{Value, json};
-use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
 use urlencoding::decode as url_decode;
 
@@ -21,18 +21,27 @@ mod bindings {
 }
 
 mod auth;
+mod html;
+mod inbound;
+mod inbound_parse;
+mod mail;
+mod mime;
+mod thread;
 
 use bindings::exports::greentic::provider_schema_core::schema_core_api::Guest;
 use bindings::greentic::http::client;
+use bindings::greentic::secrets_store::secrets_store;
 use greentic_types::{
-    Actor, ChannelMessageEnvelope, Destination, EnvId, MessageMetadata, ProviderManifest,
-    TenantCtx, TenantId,
+    Actor, Attachment, ChannelMessageEnvelope, Destination, EnvId, MessageMetadata,
+    ProviderManifest, ReplyScope, TenantCtx, TenantId,
 };
 
 const PROVIDER_TYPE: &str = "messaging.email.smtp";
 const CONFIG_SCHEMA_REF: &str = "schemas/messaging/email/public.config.schema.json";
 const DEFAULT_GRAPH_BASE: &str = "https://graph.microsoft.com/v1.0";
 const GRAPH_MAX_EXPIRATION_MINUTES: u32 = 4230;
+const MAILGUN_SIGNING_KEY: &str = "MAILGUN_WEBHOOK_SIGNING_KEY";
+const INBOUND_SECRET_KEY: &str = "EMAIL_INBOUND_SECRET";
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -47,6 +56,10 @@ struct ProviderConfig {
     #[serde(default)]
     default_to_address: Option<String>,
     #[serde(default)]
+    from_name: Option<String>,
+    #[serde(default)]
+    reply_to_address: Option<String>,
+    #[serde(default)]
     graph_tenant_id: Option<String>,
     #[serde(default)]
     graph_authority: Option<String>,
@@ -101,6 +114,8 @@ impl Guest for Component {
                     "from_address": cfg.from_address,
                     "default_to_address": cfg.default_to_address,
                     "tls_mode": cfg.tls_mode,
+                    "from_name": cfg.from_name,
+                    "reply_to_address": cfg.reply_to_address,
                     "graph_tenant_id": cfg.graph_tenant_id,
                     "graph_authority": cfg.graph_authority,
                     "graph_base_url": cfg.graph_base_url,
@@ -160,42 +175,45 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
         }
     };
 
-    if !envelope.attachments.is_empty() {
-        return json_bytes(&json!({"ok": false, "error": "attachments not supported"}));
-    }
-
     let body = envelope
         .text
         .as_ref()
         .map(|value| value.trim())
         .filter(|value| !value.is_empty())
         .map(ToOwned::to_owned);
-    let body = match body {
-        Some(value) => value,
-        None => return json_bytes(&json!({"ok": false, "error": "text required"})),
-    };
+    if body.is_none() && envelope.attachments.is_empty() {
+        return json_bytes(&json!({"ok": false, "error": "text required"}));
+    }
 
-    let destination = envelope.to.first().cloned().or_else(|| {
-        cfg.default_to_address.clone().map(|addr| Destination {
-            id: addr,
-            kind: Some("email".into()),
-        })
-    });
-    let destination = match destination {
-        Some(dest) => dest,
-        None => return json_bytes(&json!({"ok": false, "error": "destination required"})),
+    let destinations = if envelope.to.is_empty() {
+        cfg.default_to_address
+            .clone()
+            .map(|addr| Destination {
+                id: addr,
+                kind: Some("email".into()),
+            })
+            .into_iter()
+            .collect()
+    } else {
+        envelope.to.clone()
     };
-
-    let dest_id = destination.id.trim();
-    if dest_id.is_empty() {
-        return json_bytes(&json!({"ok": false, "error": "destination id required"}));
+    if destinations.is_empty() {
+        return json_bytes(&json!({"ok": false, "error": "destination required"}));
     }
-    let kind = destination.kind.as_deref().unwrap_or("email");
-    if kind != "email" && !kind.is_empty() {
-        return json_bytes(&json!({
-            "ok": false,
-            "error": format!("unsupported destination kind: {kind}"),
-        }));
+    let mut to = Vec::new();
+    for destination in &destinations {
+        let dest_id = destination.id.trim();
+        if dest_id.is_empty() {
+            return json_bytes(&json!({"ok": false, "error": "destination id required"}));
+        }
+        let kind = destination.kind.as_deref().unwrap_or("email");
+        if kind != "email" && !kind.is_empty() {
+            return json_bytes(&json!({
+                "ok": false,
+                "error": format!("unsupported destination kind: {kind}"),
+            }));
+        }
+        to.push(Mailbox::new(dest_id));
     }
 
     let subject = envelope
@@ -204,32 +222,47 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
         .cloned()
         .unwrap_or_else(|| "email message".to_string());
 
-    let payload = json!({
-        "from": cfg.from_address,
-        "to": dest_id,
-        "subject": subject,
-        "body": body,
-        "host": cfg.host,
-        "port": cfg.port,
-        "username": cfg.username,
-        "tls_mode": cfg.tls_mode,
-    });
-    let hash = hex_sha256(&json_bytes(&payload));
-    let message_id = pseudo_uuid_from_hex(&hash);
-    let provider_message_id = format!("smtp:{hash}");
-
-    json_bytes(&json!({
-        "ok": true,
-        "status": "sent",
-        "provider_type": PROVIDER_TYPE,
-        "message_id": message_id,
-        "provider_message_id": provider_message_id,
-        "payload": payload
-    }))
+    let Some(user) = resolve_auth_user(&parsed, &envelope.metadata) else {
+        return delivery_unavailable();
+    };
+    let message = match compose_message(
+        &cfg,
+        &envelope.metadata,
+        to,
+        &subject,
+        body.as_deref().unwrap_or(""),
+        &envelope.attachments,
+    ) {
+        Ok(message) => message,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let mime = match message.render(true) {
+        Ok(bytes) => bytes,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let token = match auth::acquire_graph_token(&cfg, &user) {
+        Ok(value) => value,
+        Err(err) => return json_bytes(&err.to_json()),
+    };
+    match mail::send_mime(&graph_base_url(&cfg), &token, &user.user_id, &mime) {
+        Ok(sent) => {
+            let session_id = envelope
+                .session_id
+                .starts_with("email:")
+                .then(|| envelope.session_id.clone());
+            let thread = record_sent(&user, session_id, None, &sent, Some(&subject));
+            sent_result("sent", &sent, &thread)
+        }
+        Err(err) => json_bytes(&err.to_json()),
+    }
 }
 
-fn handle_reply(_input_json: &[u8]) -> Vec<u8> {
-    let parsed: Value = match serde_json::from_slice(_input_json) {
+/// Replies in a thread. The target is `reply_to_id`/`thread_id`, else the last
+/// message recorded for `session_id`; recipient and subject default to the
+/// thread's last sender and subject. Without a Graph message to answer, a new
+/// MIME message is sent with `In-Reply-To`/`References` from the thread record.
+fn handle_reply(input_json: &[u8]) -> Vec<u8> {
+    let parsed: Value = match serde_json::from_slice(input_json) {
         Ok(val) => val,
         Err(err) => {
             return json_bytes(&json!({"ok": false, "error": format!("invalid json: {err}")}));
@@ -241,15 +274,37 @@ fn handle_reply(_input_json: &[u8]) -> Vec<u8> {
         Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
     };
 
-    let to = match parsed.get("to").and_then(|v| v.as_str()) {
-        Some(addr) if !addr.is_empty() => addr.to_string(),
-        _ => return json_bytes(&json!({"ok": false, "error": "to required"})),
+    let session_id = parsed
+        .get("session_id")
+        .and_then(Value::as_str)
+        .filter(|value| !value.trim().is_empty())
+        .map(str::to_string);
+    let record = match session_id.as_deref().map(thread::load) {
+        Some(Ok(record)) => record,
+        Some(Err(err)) => {
+            println!("email thread lookup failed: {err}");
+            None
+        }
+        None => None,
     };
-    let subject = parsed
+
+    let to = match parsed
+        .get("to")
+        .and_then(|v| v.as_str())
+        .filter(|addr| !addr.is_empty())
+        .map(str::to_string)
+        .or_else(|| record.as_ref().and_then(|r| r.last_sender.clone()))
+    {
+        Some(addr) => addr,
+        None => return json_bytes(&json!({"ok": false, "error": "to required"})),
+    };
+    let base_subject = parsed
         .get("subject")
         .and_then(|v| v.as_str())
-        .unwrap_or("")
-        .to_string();
+        .filter(|subject| !subject.trim().is_empty())
+        .map(str::to_string)
+        .or_else(|| record.as_ref().and_then(|r| r.subject.clone()));
+    let subject = base_subject.as_deref().map(thread::reply_subject);
     let body = parsed
         .get("body")
         .and_then(|v| v.as_str())
@@ -259,34 +314,255 @@ fn handle_reply(_input_json: &[u8]) -> Vec<u8> {
         .get("reply_to_id")
         .or_else(|| parsed.get("thread_id"))
         .and_then(|v| v.as_str())
-        .unwrap_or("")
-        .to_string();
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .or_else(|| record.as_ref().and_then(|r| r.last_message_id.clone()))
+        .unwrap_or_default();
 
-    let payload = json!({
-        "from": cfg.from_address,
-        "to": to,
-        "subject": subject,
-        "body": body,
-        "in_reply_to": thread_ref,
-        "host": cfg.host,
-        "port": cfg.port,
-        "username": cfg.username,
-        "tls_mode": cfg.tls_mode,
-    });
-    let hash = hex_sha256(&json_bytes(&payload));
-    let message_id = pseudo_uuid_from_hex(&hash);
-    let provider_message_id = format!("smtp-reply:{hash}");
+    let metadata = input_metadata(&parsed);
+    let Some(user) = resolve_auth_user(&parsed, &metadata) else {
+        return delivery_unavailable();
+    };
+    let attachments = input_attachments(&parsed);
+    let mut message = match compose_message(
+        &cfg,
+        &metadata,
+        vec![Mailbox::new(to)],
+        subject.as_deref().unwrap_or("email message"),
+        &body,
+        &attachments,
+    ) {
+        Ok(message) => message,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let outgoing = if thread_ref.is_empty() {
+        if let Some(parent) = record.as_ref().and_then(|r| r.internet_message_id.clone()) {
+            let mut references = record
+                .as_ref()
+                .map(|r| r.references.clone())
+                .unwrap_or_default();
+            references.push(parent.clone());
+            message.headers.push(("In-Reply-To".into(), parent));
+            message
+                .headers
+                .push(("References".into(), references.join(" ")));
+        }
+        message.render(true).map(ReplyDelivery::Mime)
+    } else {
+        mail::reply_draft(&message).map(|mut reply| {
+            if let Some(subject) = &subject {
+                reply["message"]["subject"] = Value::String(subject.clone());
+            }
+            ReplyDelivery::GraphReply(reply)
+        })
+    };
+    let outgoing = match outgoing {
+        Ok(outgoing) => outgoing,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let token = match auth::acquire_graph_token(&cfg, &user) {
+        Ok(value) => value,
+        Err(err) => return json_bytes(&err.to_json()),
+    };
+    let graph_base = graph_base_url(&cfg);
+    let result = match outgoing {
+        ReplyDelivery::GraphReply(reply) => mail::send_reply(
+            &graph_base,
+            &token,
+            &user.user_id,
+            &thread_ref,
+            &reply,
+            &message.attachments,
+        ),
+        ReplyDelivery::Mime(mime) => mail::send_mime(&graph_base, &token, &user.user_id, &mime),
+    };
+    match result {
+        Ok(sent) => {
+            let thread = record_sent(&user, session_id, record, &sent, base_subject.as_deref());
+            sent_result("replied", &sent, &thread)
+        }
+        Err(err) => json_bytes(&err.to_json()),
+    }
+}
+
+/// How a reply goes out: `createReply` on the Graph message being answered, or a
+/// new MIME message threaded through its headers.
+enum ReplyDelivery {
+    GraphReply(Value),
+    Mime(Vec<u8>),
+}
+
+/// Session and persistence outcome of an outbound message.
+struct ThreadOutcome {
+    session_id: Option<String>,
+    error: Option<String>,
+}
 
+/// Records a sent message as the newest in its thread. The session is the one
+/// given by the caller, else the Graph conversation's session. Failures are
+/// reported but do not fail the send: the mail has already been delivered.
+fn record_sent(
+    user: &AuthUserRefV1,
+    session_id: Option<String>,
+    record: Option<thread::ThreadRecord>,
+    sent: &mail::SentMessage,
+    subject: Option<&str>,
+) -> ThreadOutcome {
+    let Some(session_id) = session_id.or_else(|| {
+        sent.conversation_id
+            .as_deref()
+            .map(|conversation| thread::conversation_session(&user.user_id, conversation))
+    }) else {
+        return ThreadOutcome {
+            session_id: None,
+            error: None,
+        };
+    };
+    let record = match record {
+        Some(record) => Ok(record),
+        None => thread::load(&session_id).map(Option::unwrap_or_default),
+    };
+    let error = record
+        .and_then(|mut record| {
+            record.advance(
+                Some(&sent.id),
+                sent.internet_message_id.as_deref(),
+                sent.conversation_id.as_deref(),
+            );
+            if let Some(subject) = subject {
+                record.subject = Some(subject.to_string());
+            }
+            thread::store(&session_id, &record)
+        })
+        .err();
+    ThreadOutcome {
+        session_id: Some(session_id),
+        error,
+    }
+}
+
+/// Builds the outbound MIME message. Envelope metadata may override the
+/// configured sender name (`from_name`) and `reply_to`, and adds `cc`, `bcc`,
+/// an explicit `html` body and `header.<Name>` custom headers. Without `html`
+/// the HTML part is rendered from the markdown text, which doubles as the
+/// plain-text alternative. Attachments named by a `cid:` reference in the HTML
+/// are sent inline.
+fn compose_message(
+    cfg: &ProviderConfig,
+    metadata: &MessageMetadata,
+    to: Vec<Mailbox>,
+    subject: &str,
+    text: &str,
+    attachments: &[Attachment],
+) -> Result<MimeMessage, String> {
+    let html = metadata
+        .get("html")
+        .cloned()
+        .unwrap_or_else(|| html::markdown_to_html(text));
+    let mut files = Vec::with_capacity(attachments.len());
+    for (idx, attachment) in attachments.iter().enumerate() {
+        let filename = attachment
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("attachment-{}", idx + 1));
+        let bytes = mail::attachment_bytes(attachment)
+            .map_err(|err| format!("attachment {filename}: {err}"))?;
+        let content_id = html
+            .contains(&format!("cid:{filename}"))
+            .then(|| filename.clone());
+        files.push(MimeAttachment {
+            filename,
+            mime_type: attachment.mime_type.clone(),
+            bytes,
+            content_id,
+        });
+    }
+    let list = |key: &str| {
+        metadata
+            .get(key)
+            .map(|value| Mailbox::parse_list(value))
+            .unwrap_or_default()
+    };
+    let reply_to = metadata
+        .get("reply_to")
+        .or(cfg.reply_to_address.as_ref())
+        .map(|value| Mailbox::parse_list(value))
+        .unwrap_or_default();
+    let headers = metadata
+        .iter()
+        .filter_map(|(key, value)| {
+            key.strip_prefix("header.")
+                .map(|name| (name.to_string(), value.clone()))
+        })
+        .collect();
+    Ok(MimeMessage {
+        from: Mailbox {
+            name: metadata
+                .get("from_name")
+                .or(cfg.from_name.as_ref())
+                .cloned(),
+            address: cfg.from_address.clone(),
+        },
+        to,
+        cc: list("cc"),
+        bcc: list("bcc"),
+        reply_to,
+        subject: subject.to_string(),
+        text: text.to_string(),
+        html: Some(html),
+        attachments: files,
+        headers,
+        date: Some(Utc::now()),
+    })
+}
+
+/// Graph mailbox to deliver from: an explicit `auth_user`, else a `binding_id`
+/// (`user_id|token_key`, as used for ingest) in the input or envelope metadata.
+fn resolve_auth_user(parsed: &Value, metadata: &MessageMetadata) -> Option<AuthUserRefV1> {
+    if let Some(user) = parsed
+        .get("auth_user")
+        .and_then(|value| serde_json::from_value::<AuthUserRefV1>(value.clone()).ok())
+    {
+        return Some(user);
+    }
+    let binding = parsed
+        .get("binding_id")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or_else(|| metadata.get("binding_id").cloned())
+        .filter(|binding| !binding.trim().is_empty())?;
+    binding_to_user(Some(&binding)).ok()
+}
+
+/// Returned instead of a fake success when no Graph mailbox is available: the
+/// component has no socket access, so it cannot speak SMTP itself.
+fn delivery_unavailable() -> Vec<u8> {
     json_bytes(&json!({
-        "ok": true,
-        "status": "replied",
+        "ok": false,
+        "status": "undeliverable",
         "provider_type": PROVIDER_TYPE,
-        "message_id": message_id,
-        "provider_message_id": provider_message_id,
-        "payload": payload
+        "error": "no Graph mailbox configured (auth_user or binding_id); SMTP delivery is not available from the WASM component",
+        "retryable": false,
     }))
 }
 
+fn sent_result(status: &str, sent: &mail::SentMessage, thread: &ThreadOutcome) -> Vec<u8> {
+    let mut result = json!({
+        "ok": true,
+        "status": status,
+        "provider_type": PROVIDER_TYPE,
+        "message_id": sent.id,
+        "provider_message_id": format!("graph:{}", sent.id),
+        "internet_message_id": sent.internet_message_id,
+        "conversation_id": sent.conversation_id,
+        "session_id": thread.session_id,
+    });
+    if let Some(err) = &thread.error {
+        result["thread_error"] = Value::String(err.clone());
+    }
+    json_bytes(&result)
+}
+
 fn ingest_http(input_json: &[u8]) -> Vec<u8> {
     let http = match serde_json::from_slice::<HttpInV1>(input_json) {
         Ok(value) => value,
@@ -294,11 +570,156 @@ fn ingest_http(input_json: &[u8]) -> Vec<u8> {
     };
     match http.method.to_uppercase().as_str() {
         "GET" => handle_validation(&http),
-        "POST" => handle_graph_notifications(&http),
+        "POST" => {
+            let body = match STANDARD.decode(&http.body_b64) {
+                Ok(bytes) => bytes,
+                Err(err) => return http_out_error(400, &format!("invalid body encoding: {err}")),
+            };
+            match inbound_parse::detect(&http.headers, &body) {
+                Ok(Some(request)) => handle_inbound_parse(&http, &request),
+                Ok(None) => handle_graph_notifications(&http),
+                Err(err) => http_out_error(400, &err),
+            }
+        }
         _ => http_out_error(405, "method not allowed"),
     }
 }
 
+/// Inbound-parse webhooks (SendGrid, Mailgun, Postmark). Mailgun deliveries
+/// are checked against the signing key and SendGrid/Postmark ones against the
+/// shared inbound secret, each only when that secret is provisioned.
+fn handle_inbound_parse(http: &HttpInV1, request: &inbound_parse::ParseRequest) -> Vec<u8> {
+    let (mailgun_key, shared_secret) = match (
+        get_optional_secret(MAILGUN_SIGNING_KEY).transpose(),
+        get_optional_secret(INBOUND_SECRET_KEY).transpose(),
+    ) {
+        (Ok(mailgun_key), Ok(shared_secret)) => (mailgun_key, shared_secret),
+        (Err(err), _) | (_, Err(err)) => return http_out_error(500, &err),
+    };
+    let secrets = inbound_parse::InboundSecrets {
+        mailgun_key: mailgun_key.as_deref(),
+        shared_secret: shared_secret.as_deref(),
+    };
+    let now = Utc::now().timestamp();
+    let token =
+        match inbound_parse::verify(request, &http.headers, http.query.as_deref(), secrets, now) {
+            Ok(token) => token,
+            Err(reason) => return inbound_parse_rejected(request, &reason),
+        };
+    if let Some(token) = token {
+        match inbound_parse::claim_mailgun_token(&token, now) {
+            Ok(true) => {}
+            Ok(false) => return inbound_parse_rejected(request, "replayed_token"),
+            Err(err) => return http_out_error(500, &err),
+        }
+    }
+    let email = match inbound_parse::parse(request) {
+        Ok(email) => email,
+        Err(err) => return http_out_error(400, &err),
+    };
+    let mut envelope = parsed_email_envelope(&email, request.provider(), http.binding_id.as_ref());
+    if let Err(err) = record_inbound(&envelope, None) {
+        envelope.metadata.insert("thread_error".to_string(), err);
+    }
+    let out = HttpOutV1 {
+        status: 200,
+        headers: Vec::new(),
+        body_b64: String::new(),
+        events: vec![envelope],
+    };
+    json_bytes(&out)
+}
+
+fn inbound_parse_rejected(request: &inbound_parse::ParseRequest, reason: &str) -> Vec<u8> {
+    let body = json!({
+        "ok": false,
+        "error": "invalid_signature",
+        "provider": request.provider().label(),
+        "reason": reason,
+    });
+    http_out_error(401, &body.to_string())
+}
+
+/// Envelope for an inbound-parse email, with the same metadata keys as the
+/// Graph path. Without Graph there is no conversation id, so the session is
+/// the MIME thread root within the bound mailbox (else the first recipient).
+/// The sender name and recipients use keys that `send` does not read, so an
+/// envelope passed on to `send` does not inherit them as directives.
+fn parsed_email_envelope(
+    email: &inbound_parse::ParsedEmail,
+    provider: inbound_parse::ParseProvider,
+    binding_id: Option<&String>,
+) -> ChannelMessageEnvelope {
+    let from = email
+        .from
+        .as_ref()
+        .map(|from| from.address.clone())
+        .unwrap_or_default();
+    let mut metadata = MessageMetadata::new();
+    metadata.insert("inbound_provider".to_string(), provider.label().to_string());
+    metadata.insert("subject".to_string(), email.subject.clone());
+    metadata.insert("from".to_string(), from.clone());
+    if let Some(name) = email.from.as_ref().and_then(|from| from.name.clone()) {
+        metadata.insert("sender_name".to_string(), name);
+    }
+    if !email.to.is_empty() {
+        metadata.insert("inbound.to".to_string(), email.to.join(", "));
+    }
+    if !email.cc.is_empty() {
+        metadata.insert("inbound.cc".to_string(), email.cc.join(", "));
+    }
+    for (key, value) in [
+        ("internet_message_id", &email.message_id),
+        ("in_reply_to", &email.in_reply_to),
+        ("references", &email.references),
+    ] {
+        if let Some(value) = value {
+            metadata.insert(key.to_string(), value.clone());
+        }
+    }
+    if !email.headers.is_empty() {
+        metadata.insert("headers".to_string(), email.headers_json());
+    }
+    let mailbox = binding_id
+        .and_then(|binding| binding_to_user(Some(binding)).ok())
+        .map(|user| user.user_id)
+        .or_else(|| email.to.first().cloned())
+        .unwrap_or_default();
+    let message_id = email
+        .message_id
+        .clone()
+        .unwrap_or_else(|| format!("<{}-{}>", provider.label(), Utc::now().timestamp_millis()));
+    let session_id = thread::mime_session(
+        &mailbox,
+        &message_id,
+        email.in_reply_to.as_deref(),
+        email.references.as_deref(),
+    );
+    let id = message_id.trim_start_matches('<').trim_end_matches('>');
+    let text = email.clean_text().unwrap_or_else(|| email.subject.clone());
+    ChannelMessageEnvelope {
+        id: format!("email-{id}"),
+        tenant: TenantCtx::new(default_env(), default_tenant()),
+        channel: "email".to_string(),
+        reply_scope: Some(ReplyScope {
+            conversation: session_id.clone(),
+            thread: None,
+            reply_to: email.message_id.clone(),
+            correlation: None,
+        }),
+        session_id,
+        from: Some(Actor {
+            id: from,
+            kind: Some("user".into()),
+        }),
+        to: Vec::new(),
+        correlation_id: None,
+        text: Some(text),
+        attachments: email.attachments.clone(),
+        metadata,
+    }
+}
+
 fn render_plan(input_json: &[u8]) -> Vec<u8> {
     let plan_in = match serde_json::from_slice::<RenderPlanInV1>(input_json) {
         Ok(value) => value,
@@ -416,6 +837,8 @@ fn send_payload(input_json: &[u8]) -> Vec<u8> {
         "username",
         "from_address",
         "tls_mode",
+        "from_name",
+        "reply_to_address",
         "graph_tenant_id",
         "graph_authority",
         "graph_base_url",
@@ -436,7 +859,7 @@ fn send_payload(input_json: &[u8]) -> Vec<u8> {
     };
     let token = match auth::acquire_graph_token(&cfg, &auth_user) {
         Ok(value) => value,
-        Err(err) => return send_payload_error(&err, true),
+        Err(err) => return send_payload_error(&err.message, err.retryable),
     };
     let mail_body = json!({
         "message": {
@@ -449,8 +872,8 @@ fn send_payload(input_json: &[u8]) -> Vec<u8> {
         "saveToSentItems": false
     });
     let url = format!("{}/me/sendMail", graph_base_url(&cfg));
-    if let Err(err) = graph_post(&token, &url, &mail_body) {
-        return send_payload_error(&err, true);
+    if let Err(err) = mail::graph_call(&token, "POST", &url, Some(&mail_body)) {
+        return send_payload_error(&err.message, err.retryable);
     }
     send_payload_success()
 }
@@ -513,7 +936,7 @@ fn subscription_ensure(input_json: &[u8]) -> Vec<u8> {
     };
     let token = match auth::acquire_graph_token(&cfg, &dto.user) {
         Ok(value) => value,
-        Err(err) => return subscription_error(&err),
+        Err(err) => return subscription_error(&err.message),
     };
     let change_types = if dto.change_types.is_empty() {
         vec!["created".to_string()]
@@ -590,7 +1013,7 @@ fn subscription_renew(input_json: &[u8]) -> Vec<u8> {
     };
     let token = match auth::acquire_graph_token(&cfg, &dto.user) {
         Ok(value) => value,
-        Err(err) => return subscription_error(&err),
+        Err(err) => return subscription_error(&err.message),
     };
     let expiration = target_expiration(dto.expiration_minutes, dto.expiration_target_unix_ms);
     let expiration = clamp_expiration(expiration);
@@ -645,7 +1068,7 @@ fn subscription_delete(input_json: &[u8]) -> Vec<u8> {
     };
     let token = match auth::acquire_graph_token(&cfg, &dto.user) {
         Ok(value) => value,
-        Err(err) => return subscription_error(&err),
+        Err(err) => return subscription_error(&err.message),
     };
     let url = format!(
         "{}/subscriptions/{}",
@@ -804,7 +1227,7 @@ fn handle_graph_notifications(http: &HttpInV1) -> Vec<u8> {
     };
     let token = match auth::acquire_graph_token(&cfg, &user) {
         Ok(value) => value,
-        Err(err) => return http_out_error(500, &err),
+        Err(err) => return http_out_error(500, &err.message),
     };
     let notifications = match parse_graph_notifications(&http.body_b64) {
         Ok(value) => value,
@@ -812,17 +1235,28 @@ fn handle_graph_notifications(http: &HttpInV1) -> Vec<u8> {
     };
     let mut events = Vec::new();
     for (resource, message_id) in notifications {
-        match fetch_graph_message(&token, &cfg, &message_id) {
-            Ok(message) => {
-                events.push(channel_message_envelope(
-                    &message,
-                    &user,
-                    &message_id,
-                    &resource,
-                ));
-            }
+        let message = match fetch_graph_message(&token, &cfg, &message_id) {
+            Ok(message) => message,
             Err(err) => return http_out_error(500, &err),
+        };
+        let attachments = if message
+            .get("hasAttachments")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+        {
+            match fetch_graph_attachments(&token, &cfg, &message_id) {
+                Ok(listing) => inbound::attachments(&listing),
+                Err(err) => return http_out_error(500, &err),
+            }
+        } else {
+            Vec::new()
+        };
+        let mut envelope =
+            channel_message_envelope(&message, &user, &message_id, &resource, attachments);
+        if let Err(err) = record_inbound(&envelope, Some(&message_id)) {
+            envelope.metadata.insert("thread_error".to_string(), err);
         }
+        events.push(envelope);
     }
     let out = HttpOutV1 {
         status: 200,
@@ -904,17 +1338,60 @@ fn fetch_graph_message(
 ) -> Result<Value, String> {
     let base = graph_base_url(cfg);
     let url = format!(
-        "{}/me/messages/{}?$select=subject,bodyPreview,receivedDateTime,from,toRecipients,webLink,internetMessageId",
+        "{}/me/messages/{}?$select=subject,body,uniqueBody,bodyPreview,receivedDateTime,from,toRecipients,ccRecipients,webLink,internetMessageId,internetMessageHeaders,conversationId,hasAttachments",
         base, message_id
     );
     graph_get(token, &url)
 }
 
+fn fetch_graph_attachments(
+    token: &str,
+    cfg: &ProviderConfig,
+    message_id: &str,
+) -> Result<Value, String> {
+    let url = format!(
+        "{}/me/messages/{}/attachments",
+        graph_base_url(cfg),
+        message_id
+    );
+    graph_get(token, &url)
+}
+
+/// Makes an inbound message the newest in its session's thread so that a later
+/// `reply` answers it and defaults to its sender and subject.
+/// `graph_message_id` is `None` for messages that did not come through Graph,
+/// so replies fall back to MIME threading headers.
+fn record_inbound(
+    envelope: &ChannelMessageEnvelope,
+    graph_message_id: Option<&str>,
+) -> Result<(), String> {
+    let mut record = thread::load(&envelope.session_id)?.unwrap_or_default();
+    let metadata = &envelope.metadata;
+    if record.references.is_empty()
+        && let Some(references) = metadata.get("references")
+    {
+        record.references = thread::message_ids(references);
+    }
+    record.advance(
+        graph_message_id,
+        metadata.get("internet_message_id").map(String::as_str),
+        metadata.get("conversation_id").map(String::as_str),
+    );
+    if let Some(from) = metadata.get("from") {
+        record.last_sender = Some(from.clone());
+    }
+    if let Some(subject) = metadata.get("subject") {
+        record.subject = Some(subject.clone());
+    }
+    thread::store(&envelope.session_id, &record)
+}
+
 fn channel_message_envelope(
     message: &Value,
     user: &AuthUserRefV1,
     message_id: &str,
     resource: &str,
+    attachments: Vec<Attachment>,
 ) -> ChannelMessageEnvelope {
     let subject = message
         .get("subject")
@@ -930,12 +1407,7 @@ fn channel_message_envelope(
         .get("receivedDateTime")
         .and_then(Value::as_str)
         .unwrap_or("");
-    let from_address = message
-        .get("from")
-        .and_then(|from| from.get("emailAddress"))
-        .and_then(|ea| ea.get("address"))
-        .and_then(Value::as_str)
-        .unwrap_or("");
+    let from_address = message.get("from").and_then(inbound::address).unwrap_or("");
     let mut metadata = MessageMetadata::new();
     metadata.insert("graph_message_id".to_string(), message_id.to_string());
     metadata.insert("subject".to_string(), subject.clone());
@@ -948,23 +1420,73 @@ fn channel_message_envelope(
     if !from_address.is_empty() {
         metadata.insert("from".to_string(), from_address.to_string());
     }
+    if let Some(to) = inbound::address_list(message.get("toRecipients")) {
+        metadata.insert("inbound.to".to_string(), to);
+    }
+    if let Some(cc) = inbound::address_list(message.get("ccRecipients")) {
+        metadata.insert("inbound.cc".to_string(), cc);
+    }
+    for (key, field) in [
+        ("internet_message_id", "internetMessageId"),
+        ("conversation_id", "conversationId"),
+    ] {
+        if let Some(value) = message.get(field).and_then(Value::as_str) {
+            metadata.insert(key.to_string(), value.to_string());
+        }
+    }
     metadata.insert("resource".to_string(), resource.to_string());
+    // Prefer the cleaned body; fall back to Graph's preview, then the subject.
+    let text = inbound::message_text(message)
+        .or_else(|| metadata.get("body_preview").cloned())
+        .unwrap_or_else(|| subject.clone());
+    if let Some(headers) = message
+        .get("internetMessageHeaders")
+        .and_then(Value::as_array)
+    {
+        for header in headers {
+            let name = header.get("name").and_then(Value::as_str).unwrap_or("");
+            let value = header.get("value").and_then(Value::as_str).unwrap_or("");
+            for (key, wanted) in [("in_reply_to", "In-Reply-To"), ("references", "References")] {
+                if name.eq_ignore_ascii_case(wanted) && !value.is_empty() {
+                    metadata.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+    }
+    // All messages of a Graph conversation share one session; without a
+    // conversation id the thread root from the MIME headers is used.
+    let conversation_id = metadata.get("conversation_id").cloned();
+    let session_id = match (&conversation_id, metadata.get("internet_message_id")) {
+        (Some(conversation), _) => thread::conversation_session(&user.user_id, conversation),
+        (None, Some(internet_id)) => thread::mime_session(
+            &user.user_id,
+            internet_id,
+            metadata.get("in_reply_to").map(String::as_str),
+            metadata.get("references").map(String::as_str),
+        ),
+        (None, None) => message_id.to_string(),
+    };
     let env = default_env();
     let tenant = default_tenant();
     ChannelMessageEnvelope {
         id: format!("email-{message_id}"),
         tenant: TenantCtx::new(env, tenant),
         channel: "email".to_string(),
-        session_id: message_id.to_string(),
-        reply_scope: None,
+        reply_scope: Some(ReplyScope {
+            conversation: session_id.clone(),
+            thread: conversation_id,
+            reply_to: Some(message_id.to_string()),
+            correlation: None,
+        }),
+        session_id,
         from: Some(Actor {
             id: user.user_id.clone(),
             kind: Some("user".into()),
         }),
         to: Vec::new(),
         correlation_id: Some(resource.to_string()),
-        text: Some(subject),
-        attachments: Vec::new(),
+        text: Some(text),
+        attachments,
         metadata,
     }
 }
@@ -997,6 +1519,8 @@ fn load_config(input: &Value) -> Result<ProviderConfig, String> {
         "username",
         "from_address",
         "default_to_address",
+        "from_name",
+        "reply_to_address",
         "tls_mode",
     ] {
         if let Some(v) = input.get(key) {
@@ -1028,7 +1552,7 @@ fn build_channel_envelope(parsed: &Value, cfg: &ProviderConfig) -> ChannelMessag
         .get("body")
         .and_then(|v| v.as_str())
         .map(|s| s.to_string());
-    let mut metadata = MessageMetadata::new();
+    let mut metadata = input_metadata(parsed);
     metadata.insert("to".to_string(), to_addr.clone());
     metadata.insert("subject".to_string(), subject.clone());
     ChannelMessageEnvelope {
@@ -1044,35 +1568,53 @@ fn build_channel_envelope(parsed: &Value, cfg: &ProviderConfig) -> ChannelMessag
         }],
         correlation_id: None,
         text: body_text,
-        attachments: Vec::new(),
+        attachments: input_attachments(parsed),
         metadata,
     }
 }
 
-fn hex_sha256(bytes: &[u8]) -> String {
-    let digest = Sha256::digest(bytes);
-    let mut out = String::with_capacity(digest.len() * 2);
-    for byte in digest {
-        use std::fmt::Write;
-        let _ = write!(&mut out, "{:02x}", byte);
+/// Message options given as top-level input fields, in envelope metadata form:
+/// `cc`, `bcc`, `reply_to`, `from_name`, `html`, `binding_id` and a `headers`
+/// object whose entries become `header.<Name>`.
+fn input_metadata(parsed: &Value) -> MessageMetadata {
+    let mut metadata = MessageMetadata::new();
+    for key in ["cc", "bcc", "reply_to", "from_name", "html", "binding_id"] {
+        let value = match parsed.get(key) {
+            Some(Value::String(value)) => value.clone(),
+            Some(Value::Array(items)) => items
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(", "),
+            _ => continue,
+        };
+        metadata.insert(key.to_string(), value);
     }
-    out
+    if let Some(headers) = parsed.get("headers").and_then(Value::as_object) {
+        for (name, value) in headers {
+            if let Some(value) = value.as_str() {
+                metadata.insert(format!("header.{name}"), value.to_string());
+            }
+        }
+    }
+    metadata
 }
 
-fn pseudo_uuid_from_hex(hex: &str) -> String {
-    let padded = if hex.len() < 32 {
-        format!("{hex:0<32}")
-    } else {
-        hex[..32].to_string()
-    };
-    format!(
-        "{}-{}-{}-{}-{}",
-        &padded[0..8],
-        &padded[8..12],
-        &padded[12..16],
-        &padded[16..20],
-        &padded[20..32]
-    )
+fn input_attachments(parsed: &Value) -> Vec<Attachment> {
+    parsed
+        .get("attachments")
+        .and_then(|value| serde_json::from_value(value.clone()).ok())
+        .unwrap_or_default()
+}
+
+fn get_optional_secret(key: &str) -> Option<Result<String, String>> {
+    match secrets_store::get(key) {
+        Ok(Some(bytes)) => {
+            Some(String::from_utf8(bytes).map_err(|_| "secret not valid utf-8".into()))
+        }
+        Ok(None) => None,
+        Err(e) => Some(Err(format!("secret store error: {e:?}"))),
+    }
 }
 
 fn json_bytes<T: serde::Serialize>(value: &T) -> Vec<u8> {
@@ -1109,4 +1651,45 @@ mod tests {
         assert_eq!(cfg.host, "a");
         assert_eq!(cfg.port, 25);
     }
+
+    #[test]
+    fn parsed_envelope_metadata_is_not_read_as_send_options() {
+        let email = inbound_parse::ParsedEmail {
+            from: Some(Mailbox {
+                name: Some("Alice".into()),
+                address: "alice@example.com".into(),
+            }),
+            to: vec!["support@example.com".into()],
+            cc: vec!["carol@example.com".into()],
+            subject: "Help".into(),
+            message_id: Some("<m1@example.com>".into()),
+            ..Default::default()
+        };
+        let envelope = parsed_email_envelope(&email, inbound_parse::ParseProvider::SendGrid, None);
+        assert_eq!(envelope.metadata.get("sender_name").unwrap(), "Alice");
+        assert_eq!(
+            envelope.metadata.get("inbound.to").unwrap(),
+            "support@example.com"
+        );
+        assert_eq!(
+            envelope.metadata.get("inbound.cc").unwrap(),
+            "carol@example.com"
+        );
+
+        let cfg = parse_config_bytes(
+            br#"{"host":"smtp","username":"u","from_address":"bot@example.com","from_name":"Bot"}"#,
+        )
+        .unwrap();
+        let message = compose_message(
+            &cfg,
+            &envelope.metadata,
+            vec![Mailbox::new("alice@example.com")],
+            "Re: Help",
+            "hi",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(message.from.name.as_deref(), Some("Bot"));
+        assert!(message.cc.is_empty());
+    }
 }
@@ -0,0 +1,519 @@
+use super::bindings::greentic::http::client;
+use super::mime::{Mailbox, MimeAttachment, MimeMessage};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use greentic_types::Attachment;
+use serde_json::{Value, json};
+
+/// Graph message ids change when a draft moves to Sent Items unless immutable
+/// ids are requested.
+const IMMUTABLE_ID_PREFER: &str = "IdType=\"ImmutableId\"";
+/// Graph rejects request bodies over 4MB; MIME uploads are base64 encoded.
+const GRAPH_MIME_LIMIT_BYTES: usize = 4 * 1024 * 1024;
+/// Largest file Graph accepts through `POST /messages/{id}/attachments`; bigger
+/// files need an upload session.
+const GRAPH_ATTACHMENT_LIMIT_BYTES: usize = 3 * 1024 * 1024;
+
+/// A failed Graph call, classified so callers can decide whether to retry.
+#[derive(Debug, PartialEq)]
+pub(crate) struct GraphError {
+    pub status: Option<u16>,
+    pub code: Option<String>,
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl GraphError {
+    pub(crate) fn transport(message: String) -> Self {
+        GraphError {
+            status: None,
+            code: None,
+            message,
+            retryable: true,
+        }
+    }
+
+    /// A local failure (missing secret or config) that a retry cannot fix.
+    pub(crate) fn permanent(message: String) -> Self {
+        GraphError {
+            status: None,
+            code: None,
+            message,
+            retryable: false,
+        }
+    }
+
+    /// Classifies a failed token request. The identity platform answers with an
+    /// OAuth error (`{"error": "invalid_client", ...}`) rather than Graph's error
+    /// object; a bad client, secret or refresh token fails with 400/401 every time.
+    pub(crate) fn from_token_response(status: u16, body: &[u8]) -> Self {
+        let parsed: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+        let code = parsed
+            .get("error")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let message = match &code {
+            Some(code) => format!("token endpoint returned {status}: {code}"),
+            None => format!("token endpoint returned {status}"),
+        };
+        GraphError {
+            status: Some(status),
+            retryable: is_retryable(status, code.as_deref()),
+            code,
+            message,
+        }
+    }
+
+    pub(crate) fn from_response(status: u16, body: &[u8]) -> Self {
+        let parsed: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+        let error = parsed.get("error");
+        let code = error
+            .and_then(|err| err.get("code"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let detail = error
+            .and_then(|err| err.get("message"))
+            .and_then(Value::as_str)
+            .filter(|msg| !msg.is_empty());
+        let message = match (&code, detail) {
+            (Some(code), Some(detail)) => {
+                format!("graph request returned {status}: {code}: {detail}")
+            }
+            (Some(code), None) => format!("graph request returned {status}: {code}"),
+            _ => format!("graph request returned {status}"),
+        };
+        GraphError {
+            status: Some(status),
+            retryable: is_retryable(status, code.as_deref()),
+            code,
+            message,
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        json!({
+            "ok": false,
+            "error": self.message,
+            "retryable": self.retryable,
+            "status_code": self.status,
+            "graph_error_code": self.code,
+        })
+    }
+}
+
+/// Throttling, timeouts and server-side failures are transient; auth, validation
+/// and mailbox errors need a config or content change first.
+fn is_retryable(status: u16, code: Option<&str>) -> bool {
+    match status {
+        408 | 429 | 500 | 502 | 503 | 504 => true,
+        _ => matches!(
+            code,
+            Some(
+                "ErrorServerBusy"
+                    | "ServiceUnavailable"
+                    | "MailboxConcurrency"
+                    | "temporarily_unavailable"
+            )
+        ),
+    }
+}
+
+/// Ids Graph assigns to a sent message.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SentMessage {
+    pub id: String,
+    pub internet_message_id: Option<String>,
+    pub conversation_id: Option<String>,
+}
+
+impl SentMessage {
+    fn from_draft(draft: &Value) -> Result<Self, GraphError> {
+        let id = draft
+            .get("id")
+            .and_then(Value::as_str)
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| GraphError {
+                status: None,
+                code: None,
+                message: "graph draft response missing id".into(),
+                retryable: false,
+            })?;
+        let field = |key: &str| draft.get(key).and_then(Value::as_str).map(str::to_string);
+        Ok(SentMessage {
+            id: id.to_string(),
+            internet_message_id: field("internetMessageId"),
+            conversation_id: field("conversationId"),
+        })
+    }
+}
+
+/// Builds the `createReply` body for `message`: recipients, Reply-To, the HTML
+/// body as the comment Graph places above the quoted original, and custom
+/// headers. Graph only accepts `X-` headers on messages, so any other custom
+/// header is an error rather than being dropped. The subject is left to the
+/// caller; attachments are added to the draft by [`send_reply`].
+pub(crate) fn reply_draft(message: &MimeMessage) -> Result<Value, String> {
+    let mut draft = json!({
+        "toRecipients": recipients(&message.to),
+        "ccRecipients": recipients(&message.cc),
+        "bccRecipients": recipients(&message.bcc),
+    });
+    if !message.reply_to.is_empty() {
+        draft["replyTo"] = recipients(&message.reply_to);
+    }
+    let mut headers = Vec::with_capacity(message.headers.len());
+    for (name, value) in &message.headers {
+        if !name.to_ascii_lowercase().starts_with("x-") {
+            return Err(format!(
+                "header {name} cannot be set on a Graph reply; only X- headers are supported"
+            ));
+        }
+        headers.push(json!({ "name": name, "value": value }));
+    }
+    if !headers.is_empty() {
+        draft["internetMessageHeaders"] = Value::Array(headers);
+    }
+    for file in &message.attachments {
+        if file.bytes.len() > GRAPH_ATTACHMENT_LIMIT_BYTES {
+            return Err(format!(
+                "attachment {} is {} bytes; Graph replies accept at most 3MB per attachment",
+                file.filename,
+                file.bytes.len()
+            ));
+        }
+    }
+    let comment = message.html.clone().unwrap_or_else(|| message.text.clone());
+    Ok(json!({ "message": draft, "comment": comment }))
+}
+
+/// Graph `fileAttachment` for a reply draft; inline images keep their Content-ID.
+fn file_attachment(file: &MimeAttachment) -> Value {
+    let mut attachment = json!({
+        "@odata.type": "#microsoft.graph.fileAttachment",
+        "name": file.filename,
+        "contentType": file.mime_type,
+        "contentBytes": STANDARD.encode(&file.bytes),
+    });
+    if let Some(content_id) = &file.content_id {
+        attachment["contentId"] = Value::String(content_id.clone());
+        attachment["isInline"] = Value::Bool(true);
+    }
+    attachment
+}
+
+/// Replies in-thread via `createReply` (which sets In-Reply-To/References and the
+/// conversation), attaches `attachments` to the draft and then sends it. A
+/// draft that cannot be completed is deleted again.
+pub(crate) fn send_reply(
+    graph_base: &str,
+    token: &str,
+    user_id: &str,
+    reply_to_id: &str,
+    reply: &Value,
+    attachments: &[MimeAttachment],
+) -> Result<SentMessage, GraphError> {
+    let mailbox = format!("{graph_base}/users/{}", urlencoding::encode(user_id));
+    let draft = graph_call(
+        token,
+        "POST",
+        &format!(
+            "{mailbox}/messages/{}/createReply",
+            urlencoding::encode(reply_to_id)
+        ),
+        Some(reply),
+    )?;
+    let sent = SentMessage::from_draft(&draft)?;
+    let message = format!("{mailbox}/messages/{}", urlencoding::encode(&sent.id));
+    let delivered = attachments
+        .iter()
+        .try_for_each(|file| {
+            graph_call(
+                token,
+                "POST",
+                &format!("{message}/attachments"),
+                Some(&file_attachment(file)),
+            )
+            .map(|_| ())
+        })
+        .and_then(|()| graph_call(token, "POST", &format!("{message}/send"), None));
+    if let Err(err) = delivered {
+        discard_draft(token, &message, &err);
+        return Err(err);
+    }
+    Ok(sent)
+}
+
+/// Deletes a draft whose delivery failed so it does not linger in Drafts. Only
+/// done when Graph answered the failing call: after a transport error the send
+/// may have gone through, and the immutable id would then name the sent copy.
+fn discard_draft(token: &str, message: &str, err: &GraphError) {
+    if err.status.is_some() {
+        let _ = graph_call(token, "DELETE", message, None);
+    }
+}
+
+/// Delivers a pre-built MIME message. `sendMail` answers 202 without a body, so
+/// the message is created as a draft first (POST /users/{id}/messages, which
+/// accepts base64 MIME as `text/plain`) to learn its Graph ids and then sent
+/// with `/send`, which is the same delivery path.
+pub(crate) fn send_mime(
+    graph_base: &str,
+    token: &str,
+    user_id: &str,
+    mime: &[u8],
+) -> Result<SentMessage, GraphError> {
+    let encoded = STANDARD.encode(mime);
+    if encoded.len() > GRAPH_MIME_LIMIT_BYTES {
+        return Err(GraphError {
+            status: None,
+            code: None,
+            message: format!(
+                "message is {} bytes after encoding; Graph accepts at most 4MB per MIME upload",
+                encoded.len()
+            ),
+            retryable: false,
+        });
+    }
+    let mailbox = format!("{graph_base}/users/{}", urlencoding::encode(user_id));
+    let draft = graph_request(
+        token,
+        "POST",
+        &format!("{mailbox}/messages"),
+        Some(("text/plain", encoded.into_bytes())),
+    )?;
+    let sent = SentMessage::from_draft(&draft)?;
+    let message = format!("{mailbox}/messages/{}", urlencoding::encode(&sent.id));
+    if let Err(err) = graph_call(token, "POST", &format!("{message}/send"), None) {
+        discard_draft(token, &message, &err);
+        return Err(err);
+    }
+    Ok(sent)
+}
+
+/// Loads an envelope attachment's bytes from a `data:` URL or by fetching it.
+pub(crate) fn attachment_bytes(attachment: &Attachment) -> Result<Vec<u8>, String> {
+    let url = attachment.url.trim();
+    if let Some(data) = url.strip_prefix("data:") {
+        let (header, encoded) = data
+            .split_once(',')
+            .ok_or_else(|| "invalid data url".to_string())?;
+        if !header.ends_with(";base64") {
+            return Err("data url must be base64 encoded".into());
+        }
+        return STANDARD
+            .decode(encoded)
+            .map_err(|err| format!("invalid data url: {err}"));
+    }
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(format!("unsupported attachment url: {url}"));
+    }
+    let request = client::Request {
+        method: "GET".into(),
+        url: url.to_string(),
+        headers: Vec::new(),
+        body: None,
+    };
+    let resp = client::send(&request, None, None)
+        .map_err(|err| format!("attachment fetch error: {}", err.message))?;
+    if resp.status < 200 || resp.status >= 300 {
+        return Err(format!(
+            "attachment fetch returned {} for {url}",
+            resp.status
+        ));
+    }
+    Ok(resp.body.unwrap_or_default())
+}
+
+pub(crate) fn recipients(mailboxes: &[Mailbox]) -> Value {
+    Value::Array(
+        mailboxes
+            .iter()
+            .map(|mailbox| match &mailbox.name {
+                Some(name) => {
+                    json!({ "emailAddress": { "name": name, "address": mailbox.address } })
+                }
+                None => json!({ "emailAddress": { "address": mailbox.address } }),
+            })
+            .collect(),
+    )
+}
+
+pub(crate) fn graph_call(
+    token: &str,
+    method: &str,
+    url: &str,
+    body: Option<&Value>,
+) -> Result<Value, GraphError> {
+    let body = match body {
+        Some(value) => Some((
+            "application/json",
+            serde_json::to_vec(value).map_err(|e| GraphError {
+                status: None,
+                code: None,
+                message: format!("invalid graph body: {e}"),
+                retryable: false,
+            })?,
+        )),
+        None => None,
+    };
+    graph_request(token, method, url, body)
+}
+
+fn graph_request(
+    token: &str,
+    method: &str,
+    url: &str,
+    content: Option<(&str, Vec<u8>)>,
+) -> Result<Value, GraphError> {
+    let mut headers = vec![
+        ("Authorization".into(), format!("Bearer {token}")),
+        ("Prefer".into(), IMMUTABLE_ID_PREFER.into()),
+    ];
+    let body = match content {
+        Some((content_type, bytes)) => {
+            headers.push(("Content-Type".into(), content_type.to_string()));
+            Some(bytes)
+        }
+        // Graph rejects body-less POSTs without a Content-Length; send an empty body.
+        None if method == "POST" => Some(Vec::new()),
+        None => None,
+    };
+    let request = client::Request {
+        method: method.into(),
+        url: url.to_string(),
+        headers,
+        body,
+    };
+    let resp = client::send(&request, None, None)
+        .map_err(|e| GraphError::transport(format!("graph request error: {}", e.message)))?;
+    let body = resp.body.unwrap_or_default();
+    if resp.status < 200 || resp.status >= 300 {
+        return Err(GraphError::from_response(resp.status, &body));
+    }
+    if body.is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_slice(&body).map_err(|e| GraphError {
+        status: Some(resp.status),
+        code: None,
+        message: format!("graph response decode failed: {e}"),
+        retryable: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graph_errors_are_classified() {
+        let throttled = GraphError::from_response(
+            429,
+            br#"{"error":{"code":"ApplicationThrottled","message":"slow down"}}"#,
+        );
+        assert!(throttled.retryable);
+        assert_eq!(throttled.code.as_deref(), Some("ApplicationThrottled"));
+        assert!(throttled.message.contains("slow down"));
+
+        let invalid = GraphError::from_response(
+            400,
+            br#"{"error":{"code":"ErrorInvalidRecipients","message":"bad"}}"#,
+        );
+        assert!(!invalid.retryable);
+
+        let busy = GraphError::from_response(409, br#"{"error":{"code":"ErrorServerBusy"}}"#);
+        assert!(busy.retryable);
+
+        let bare = GraphError::from_response(503, b"");
+        assert!(bare.retryable);
+        assert_eq!(bare.message, "graph request returned 503");
+    }
+
+    #[test]
+    fn token_errors_are_classified_by_status() {
+        let bad_client = GraphError::from_token_response(
+            401,
+            br#"{"error":"invalid_client","error_description":"AADSTS7000215"}"#,
+        );
+        assert!(!bad_client.retryable);
+        assert_eq!(bad_client.code.as_deref(), Some("invalid_client"));
+        assert_eq!(
+            bad_client.message,
+            "token endpoint returned 401: invalid_client"
+        );
+        assert!(!GraphError::from_token_response(400, br#"{"error":"invalid_grant"}"#).retryable);
+        assert!(
+            GraphError::from_token_response(400, br#"{"error":"temporarily_unavailable"}"#)
+                .retryable
+        );
+        assert!(GraphError::from_token_response(503, b"").retryable);
+    }
+
+    #[test]
+    fn reply_draft_carries_html_recipients_and_headers() {
+        let mut message = MimeMessage {
+            to: vec![Mailbox::new("a@example.com")],
+            cc: Mailbox::parse_list("\"Doe, Jane\" <j@example.com>"),
+            reply_to: vec![Mailbox::new("support@example.com")],
+            text: "hi".into(),
+            html: Some("<p>hi</p>".into()),
+            headers: vec![("X-Ticket".into(), "42".into())],
+            attachments: vec![MimeAttachment {
+                filename: "logo.png".into(),
+                mime_type: "image/png".into(),
+                bytes: vec![1, 2, 3],
+                content_id: Some("logo.png".into()),
+            }],
+            ..MimeMessage::default()
+        };
+        let draft = reply_draft(&message).unwrap();
+        assert_eq!(draft["comment"], "<p>hi</p>");
+        assert_eq!(
+            draft["message"]["toRecipients"][0]["emailAddress"]["address"],
+            "a@example.com"
+        );
+        assert_eq!(
+            draft["message"]["ccRecipients"][0]["emailAddress"]["name"],
+            "Doe, Jane"
+        );
+        assert_eq!(
+            draft["message"]["replyTo"][0]["emailAddress"]["address"],
+            "support@example.com"
+        );
+        assert_eq!(
+            draft["message"]["internetMessageHeaders"][0]["name"],
+            "X-Ticket"
+        );
+        let attachment = file_attachment(&message.attachments[0]);
+        assert_eq!(attachment["contentBytes"], "AQID");
+        assert_eq!(attachment["isInline"], true);
+
+        message
+            .headers
+            .push(("Auto-Submitted".into(), "auto-replied".into()));
+        assert!(
+            reply_draft(&message)
+                .unwrap_err()
+                .contains("Auto-Submitted")
+        );
+        message.headers.pop();
+        message.attachments[0].bytes = vec![0; GRAPH_ATTACHMENT_LIMIT_BYTES + 1];
+        assert!(reply_draft(&message).unwrap_err().contains("3MB"));
+    }
+
+    #[test]
+    fn draft_ids_are_extracted() {
+        let sent = SentMessage::from_draft(&json!({
+            "id": "AAMk-1",
+            "internetMessageId": "<abc@example.com>",
+            "conversationId": "conv-1"
+        }))
+        .unwrap();
+        assert_eq!(sent.id, "AAMk-1");
+        assert_eq!(
+            sent.internet_message_id.as_deref(),
+            Some("<abc@example.com>")
+        );
+        assert_eq!(sent.conversation_id.as_deref(), Some("conv-1"));
+        assert!(SentMessage::from_draft(&json!({})).is_err());
+    }
+}
@@ -0,0 +1,621 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{DateTime, Utc};
+
+/// RFC 5322 recommends header lines of at most 78 characters.
+const HEADER_LINE_LIMIT: usize = 78;
+/// RFC 2045 caps encoded body lines at 76 characters.
+const BODY_LINE_LIMIT: usize = 76;
+/// An encoded-word may be at most 75 characters; `=?UTF-8?B?` + `?=` leaves 63,
+/// i.e. 15 base64 quads or 45 input bytes.
+const ENCODED_WORD_BYTES: usize = 45;
+/// Headers the builder owns; custom headers may not override them.
+const RESERVED_HEADERS: &[&str] = &[
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "reply-to",
+    "subject",
+    "date",
+    "mime-version",
+    "content-type",
+    "content-transfer-encoding",
+    "content-disposition",
+    "content-id",
+];
+
+/// An address with an optional display name.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Mailbox {
+    pub name: Option<String>,
+    pub address: String,
+}
+
+impl Mailbox {
+    pub(crate) fn new(address: impl Into<String>) -> Self {
+        Mailbox {
+            name: None,
+            address: address.into(),
+        }
+    }
+
+    /// Parses a comma separated list such as `a@example.com, "Doe, Jane" <j@example.com>`.
+    pub(crate) fn parse_list(value: &str) -> Vec<Mailbox> {
+        let mut items = Vec::new();
+        let mut current = String::new();
+        let mut quoted = false;
+        let mut angle = false;
+        for ch in value.chars() {
+            match ch {
+                '"' => quoted = !quoted,
+                '<' if !quoted => angle = true,
+                '>' if !quoted => angle = false,
+                ',' | ';' if !quoted && !angle => {
+                    items.push(std::mem::take(&mut current));
+                    continue;
+                }
+                _ => {}
+            }
+            current.push(ch);
+        }
+        items.push(current);
+        items
+            .iter()
+            .filter_map(|item| Mailbox::parse(item))
+            .collect()
+    }
+
+    fn parse(item: &str) -> Option<Mailbox> {
+        let item = item.trim();
+        if item.is_empty() {
+            return None;
+        }
+        if let (Some(start), Some(end)) = (item.rfind('<'), item.rfind('>'))
+            && start < end
+        {
+            let name = item[..start].trim().trim_matches('"').trim();
+            return Some(Mailbox {
+                name: (!name.is_empty()).then(|| name.replace("\\\"", "\"")),
+                address: item[start + 1..end].trim().to_string(),
+            });
+        }
+        Some(Mailbox::new(item))
+    }
+
+    fn render(&self) -> Result<String, String> {
+        let address = self.address.trim();
+        let valid = address.contains('@')
+            && !address.starts_with('@')
+            && !address.ends_with('@')
+            && !address
+                .chars()
+                .any(|ch| ch.is_whitespace() || ch.is_control() || "<>,;\"".contains(ch));
+        if !valid {
+            return Err(format!("invalid email address: {address}"));
+        }
+        match self.name.as_deref().map(str::trim) {
+            Some(name) if !name.is_empty() => Ok(format!("{} <{address}>", display_name(name))),
+            _ => Ok(address.to_string()),
+        }
+    }
+}
+
+/// A file part. With a `content_id` it is sent inline (referenced from the HTML
+/// as `cid:<content_id>`), otherwise as an attachment.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MimeAttachment {
+    pub filename: String,
+    pub mime_type: String,
+    pub bytes: Vec<u8>,
+    pub content_id: Option<String>,
+}
+
+/// An outbound message rendered as RFC 5322 / RFC 2045 MIME. The output is a
+/// complete message suitable for Graph's MIME upload or an SMTP `DATA` command.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MimeMessage {
+    pub from: Mailbox,
+    pub to: Vec<Mailbox>,
+    pub cc: Vec<Mailbox>,
+    pub bcc: Vec<Mailbox>,
+    pub reply_to: Vec<Mailbox>,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+    pub attachments: Vec<MimeAttachment>,
+    pub headers: Vec<(String, String)>,
+    pub date: Option<DateTime<Utc>>,
+}
+
+impl MimeMessage {
+    /// Envelope recipients (To, Cc and Bcc) for a relay's `RCPT TO`.
+    pub(crate) fn recipients(&self) -> Vec<&str> {
+        self.to
+            .iter()
+            .chain(&self.cc)
+            .chain(&self.bcc)
+            .map(|mailbox| mailbox.address.as_str())
+            .collect()
+    }
+
+    /// Renders the message. Graph strips `Bcc` itself, so it is kept for Graph
+    /// uploads; an SMTP relay must render without it and use [`Self::recipients`].
+    pub(crate) fn render(&self, include_bcc: bool) -> Result<Vec<u8>, String> {
+        if self.recipients().is_empty() {
+            return Err("at least one recipient required".into());
+        }
+        let mut out = String::new();
+        if let Some(date) = self.date {
+            out.push_str(&fold("Date", &date.to_rfc2822()));
+        }
+        out.push_str(&fold("From", &self.from.render()?));
+        for (name, list) in [
+            ("Reply-To", &self.reply_to),
+            ("To", &self.to),
+            ("Cc", &self.cc),
+            ("Bcc", &self.bcc),
+        ] {
+            if list.is_empty() || (name == "Bcc" && !include_bcc) {
+                continue;
+            }
+            let rendered = list
+                .iter()
+                .map(Mailbox::render)
+                .collect::<Result<Vec<_>, _>>()?;
+            out.push_str(&fold(name, &rendered.join(", ")));
+        }
+        out.push_str(&fold("Subject", &encode_words(&self.subject)));
+        for (name, value) in &self.headers {
+            validate_header_name(name)?;
+            let value: String = value
+                .chars()
+                .filter(|ch| *ch != '\r' && *ch != '\n')
+                .collect();
+            out.push_str(&fold(name, &encode_words(value.trim())));
+        }
+        out.push_str("MIME-Version: 1.0\r\n");
+        self.body_part().write(&mut out);
+        Ok(out.into_bytes())
+    }
+
+    /// multipart/mixed( multipart/related( multipart/alternative(text, html),
+    /// inline images ), attachments ), collapsing levels that would be empty.
+    fn body_part(&self) -> Part {
+        let text = Part::text("plain", &self.text);
+        let mut body = match &self.html {
+            Some(html) => Part::multipart("alternative", vec![text, Part::text("html", html)]),
+            None => text,
+        };
+        let (inline, attached): (Vec<_>, Vec<_>) = self
+            .attachments
+            .iter()
+            .partition(|attachment| attachment.content_id.is_some() && self.html.is_some());
+        if !inline.is_empty() {
+            let mut parts = vec![body];
+            parts.extend(inline.into_iter().map(Part::file));
+            body = Part::multipart("related", parts);
+        }
+        if !attached.is_empty() {
+            let mut parts = vec![body];
+            parts.extend(attached.into_iter().map(Part::file));
+            body = Part::multipart("mixed", parts);
+        }
+        body
+    }
+}
+
+struct Part {
+    headers: Vec<(&'static str, String)>,
+    body: PartBody,
+}
+
+enum PartBody {
+    Encoded(String),
+    Multipart { boundary: String, parts: Vec<Part> },
+}
+
+impl Part {
+    fn text(subtype: &str, content: &str) -> Part {
+        Part {
+            headers: vec![
+                ("Content-Type", format!("text/{subtype}; charset=utf-8")),
+                ("Content-Transfer-Encoding", "quoted-printable".into()),
+            ],
+            body: PartBody::Encoded(quoted_printable(content)),
+        }
+    }
+
+    fn file(attachment: &MimeAttachment) -> Part {
+        let filename = sanitize_filename(&attachment.filename);
+        let mime_type = if attachment.mime_type.contains('/') {
+            attachment.mime_type.trim()
+        } else {
+            "application/octet-stream"
+        };
+        let mut headers = vec![
+            (
+                "Content-Type",
+                format!("{mime_type}; {}", parameter("name", &filename)),
+            ),
+            ("Content-Transfer-Encoding", "base64".into()),
+        ];
+        match &attachment.content_id {
+            Some(content_id) => {
+                headers.push((
+                    "Content-ID",
+                    format!("<{}>", content_id.trim_matches(['<', '>'])),
+                ));
+                headers.push((
+                    "Content-Disposition",
+                    format!("inline; {}", parameter("filename", &filename)),
+                ));
+            }
+            None => headers.push((
+                "Content-Disposition",
+                format!("attachment; {}", parameter("filename", &filename)),
+            )),
+        }
+        Part {
+            headers,
+            body: PartBody::Encoded(base64_lines(&attachment.bytes)),
+        }
+    }
+
+    /// Boundaries contain `=_`, which cannot occur in quoted-printable or base64
+    /// output, so they never collide with encoded part content.
+    fn multipart(subtype: &str, parts: Vec<Part>) -> Part {
+        let boundary = format!("=_greentic_{subtype}_7f3c9a");
+        let mut content_type = format!("multipart/{subtype}; boundary=\"{boundary}\"");
+        if subtype == "related" {
+            content_type.push_str("; type=\"multipart/alternative\"");
+        }
+        Part {
+            headers: vec![("Content-Type", content_type)],
+            body: PartBody::Multipart { boundary, parts },
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        for (name, value) in &self.headers {
+            out.push_str(&fold(name, value));
+        }
+        out.push_str("\r\n");
+        match &self.body {
+            PartBody::Encoded(body) => {
+                out.push_str(body);
+                if !body.ends_with("\r\n") {
+                    out.push_str("\r\n");
+                }
+            }
+            PartBody::Multipart { boundary, parts } => {
+                out.push_str("This is a multi-part message in MIME format.\r\n");
+                for part in parts {
+                    out.push_str(&format!("\r\n--{boundary}\r\n"));
+                    part.write(out);
+                }
+                out.push_str(&format!("\r\n--{boundary}--\r\n"));
+            }
+        }
+    }
+}
+
+/// Folds `Name: value` at whitespace so that lines stay within 78 characters.
+/// Runs without whitespace longer than the limit are left intact.
+fn fold(name: &str, value: &str) -> String {
+    let mut out = format!("{name}:");
+    let mut line_len = out.len();
+    let mut line_has_word = false;
+    for word in value.split(' ') {
+        if line_has_word && line_len + 1 + word.len() > HEADER_LINE_LIMIT {
+            out.push_str("\r\n");
+            line_len = 0;
+        }
+        out.push(' ');
+        out.push_str(word);
+        line_len += 1 + word.len();
+        line_has_word = true;
+    }
+    out.push_str("\r\n");
+    out
+}
+
+/// RFC 2047 encoded-words for header text that is not plain printable ASCII.
+/// Words are split on character boundaries and separated by spaces so that the
+/// header can be folded between them.
+fn encode_words(value: &str) -> String {
+    let plain = value.chars().all(|ch| ch == ' ' || ch.is_ascii_graphic()) && !value.contains("=?");
+    if plain {
+        return value.to_string();
+    }
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for ch in value.chars() {
+        if chunk.len() + ch.len_utf8() > ENCODED_WORD_BYTES {
+            words.push(format!("=?UTF-8?B?{}?=", STANDARD.encode(&chunk)));
+            chunk.clear();
+        }
+        chunk.push(ch);
+    }
+    if !chunk.is_empty() {
+        words.push(format!("=?UTF-8?B?{}?=", STANDARD.encode(&chunk)));
+    }
+    words.join(" ")
+}
+
+/// Renders a display name for an address header. Names carrying control
+/// characters (a CR/LF would start a new header) are sent as encoded words,
+/// which never contain them.
+fn display_name(name: &str) -> String {
+    if !name.is_ascii() || name.contains("=?") || name.chars().any(char::is_control) {
+        return encode_words(name);
+    }
+    if name.chars().any(|ch| "()<>[]:;@\\,.\"".contains(ch)) {
+        let escaped = name.replace('\\', "\\\\").replace('"', "\\\"");
+        return format!("\"{escaped}\"");
+    }
+    name.to_string()
+}
+
+fn validate_header_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_graphic() && byte != b':');
+    if !valid {
+        return Err(format!("invalid header name: {name}"));
+    }
+    if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+        return Err(format!("header {name} is set by the message builder"));
+    }
+    Ok(())
+}
+
+/// `name="value"`, or the RFC 2231 `name*=utf-8''...` form for non-ASCII values.
+fn parameter(name: &str, value: &str) -> String {
+    if value.chars().all(|ch| ch == ' ' || ch.is_ascii_graphic()) {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+        return format!("{name}=\"{escaped}\"");
+    }
+    let encoded: String = value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect();
+    format!("{name}*=utf-8''{encoded}")
+}
+
+fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let cleaned: String = base.chars().filter(|ch| !ch.is_control()).collect();
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() {
+        "attachment".into()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+/// Quoted-printable (RFC 2045 §6.7) with CRLF line endings, soft line breaks
+/// at 76 characters and trailing whitespace encoded.
+pub(crate) fn quoted_printable(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / 8);
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            out.push_str("\r\n");
+        }
+        let bytes = line.strip_suffix('\r').unwrap_or(line).as_bytes();
+        let mut column = 0;
+        for (pos, &byte) in bytes.iter().enumerate() {
+            let last = pos + 1 == bytes.len();
+            let literal = match byte {
+                b' ' | b'\t' => !last,
+                b'=' => false,
+                33..=126 => true,
+                _ => false,
+            };
+            let token = if literal {
+                (byte as char).to_string()
+            } else {
+                format!("={byte:02X}")
+            };
+            // Keep room for the `=` of a soft break unless this ends the line.
+            let limit = if last {
+                BODY_LINE_LIMIT
+            } else {
+                BODY_LINE_LIMIT - 1
+            };
+            if column + token.len() > limit {
+                out.push_str("=\r\n");
+                column = 0;
+            }
+            out.push_str(&token);
+            column += token.len();
+        }
+    }
+    out
+}
+
+fn base64_lines(bytes: &[u8]) -> String {
+    let encoded = STANDARD.encode(bytes);
+    let mut out = String::with_capacity(encoded.len() + encoded.len() / BODY_LINE_LIMIT * 2 + 2);
+    for chunk in encoded.as_bytes().chunks(BODY_LINE_LIMIT) {
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> MimeMessage {
+        MimeMessage {
+            from: Mailbox {
+                name: Some("Support Team".into()),
+                address: "support@example.com".into(),
+            },
+            to: vec![Mailbox::new("alice@example.com")],
+            subject: "Hello".into(),
+            text: "hi there".into(),
+            ..MimeMessage::default()
+        }
+    }
+
+    fn render(message: &MimeMessage) -> String {
+        String::from_utf8(message.render(true).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn encodes_and_folds_headers() {
+        assert_eq!(encode_words("plain subject"), "plain subject");
+        assert_eq!(encode_words("Grüße"), "=?UTF-8?B?R3LDvMOfZQ==?=");
+        let long = "ü".repeat(40);
+        for word in encode_words(&long).split(' ') {
+            assert!(word.len() <= 75, "{word}");
+        }
+
+        let folded = fold("Subject", &"word ".repeat(30));
+        assert!(
+            folded
+                .split("\r\n")
+                .all(|line| line.len() <= HEADER_LINE_LIMIT)
+        );
+        assert_eq!(
+            folded.replace("\r\n ", " "),
+            format!("Subject: {}\r\n", "word ".repeat(30))
+        );
+
+        assert_eq!(
+            Mailbox {
+                name: Some("Doe, Jane".into()),
+                address: "jane@example.com".into()
+            }
+            .render()
+            .unwrap(),
+            "\"Doe, Jane\" <jane@example.com>"
+        );
+        assert!(Mailbox::new("not-an-address").render().is_err());
+        assert_eq!(
+            Mailbox::parse_list(
+                "a@example.com, \"Doe, Jane\" <jane@example.com>; Bob <b@example.com>"
+            ),
+            vec![
+                Mailbox::new("a@example.com"),
+                Mailbox {
+                    name: Some("Doe, Jane".into()),
+                    address: "jane@example.com".into()
+                },
+                Mailbox {
+                    name: Some("Bob".into()),
+                    address: "b@example.com".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn control_characters_in_display_names_are_encoded() {
+        let mut message = message();
+        message.from.name = Some("Support\r\nBcc: victim@evil.example".into());
+        message.cc = Mailbox::parse_list("\"X\r\nBcc: a@evil.example\" <cc@example.com>");
+        let rendered = render(&message).replace("\r\n ", " ");
+        assert!(!rendered.contains("\r\nBcc:"), "{rendered}");
+        assert!(rendered.contains(&format!(
+            "From: {} <support@example.com>\r\n",
+            encode_words("Support\r\nBcc: victim@evil.example")
+        )));
+        assert!(rendered.contains(&format!(
+            "Cc: {} <cc@example.com>\r\n",
+            encode_words("X\r\nBcc: a@evil.example")
+        )));
+    }
+
+    #[test]
+    fn quoted_printable_wraps_and_escapes() {
+        assert_eq!(quoted_printable("a=b \nc\td\t"), "a=3Db=20\r\nc\td=09");
+        assert_eq!(quoted_printable("é"), "=C3=A9");
+        let long = quoted_printable(&"x".repeat(200));
+        assert!(long.split("\r\n").all(|line| line.len() <= BODY_LINE_LIMIT));
+        assert_eq!(long.replace("=\r\n", ""), "x".repeat(200));
+    }
+
+    #[test]
+    fn renders_simple_text_message() {
+        let rendered = render(&message());
+        assert!(rendered.starts_with("From: Support Team <support@example.com>\r\nTo: alice@example.com\r\nSubject: Hello\r\n"));
+        assert!(rendered.contains(
+            "MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\nhi there\r\n"
+        ));
+        assert!(!rendered.contains("multipart"));
+    }
+
+    #[test]
+    fn renders_nested_multipart_structure() {
+        let mut msg = message();
+        msg.subject = "Bericht für Q3".into();
+        msg.html = Some("<p>hi <img src=\"cid:logo\"></p>".into());
+        msg.cc = vec![Mailbox::new("carol@example.com")];
+        msg.bcc = vec![Mailbox::new("audit@example.com")];
+        msg.reply_to = vec![Mailbox::new("replies@example.com")];
+        msg.headers = vec![(
+            "X-Campaign".into(),
+            "spring\r\nBcc: evil@example.com".into(),
+        )];
+        msg.attachments = vec![
+            MimeAttachment {
+                filename: "logo.png".into(),
+                mime_type: "image/png".into(),
+                bytes: vec![1, 2, 3],
+                content_id: Some("logo".into()),
+            },
+            MimeAttachment {
+                filename: "résumé.pdf".into(),
+                mime_type: "application/pdf".into(),
+                bytes: b"%PDF".to_vec(),
+                content_id: None,
+            },
+        ];
+        let rendered = render(&msg);
+        assert!(rendered.contains("Subject: =?UTF-8?B?"));
+        assert!(rendered.contains("Reply-To: replies@example.com\r\n"));
+        assert!(rendered.contains("Cc: carol@example.com\r\n"));
+        assert!(rendered.contains("Bcc: audit@example.com\r\n"));
+        assert!(rendered.contains("X-Campaign: springBcc: evil@example.com\r\n"));
+
+        let mixed = rendered.find("multipart/mixed").unwrap();
+        let related = rendered.find("multipart/related").unwrap();
+        let alternative = rendered.find("multipart/alternative;").unwrap();
+        assert!(mixed < related && related < alternative);
+        assert!(
+            rendered.contains(
+                "Content-ID: <logo>\r\nContent-Disposition: inline; filename=\"logo.png\""
+            )
+        );
+        assert!(
+            rendered
+                .contains("Content-Disposition: attachment; filename*=utf-8''r%C3%A9sum%C3%A9.pdf")
+        );
+        assert!(rendered.contains("\r\nJVBERg==\r\n"));
+        assert!(rendered.ends_with("--=_greentic_mixed_7f3c9a--\r\n"));
+
+        let relay = String::from_utf8(msg.render(false).unwrap()).unwrap();
+        assert!(!relay.contains("audit@example.com"));
+        assert_eq!(
+            msg.recipients(),
+            vec![
+                "alice@example.com",
+                "carol@example.com",
+                "audit@example.com"
+            ]
+        );
+
+        msg.headers = vec![("Content-Type".into(), "text/evil".into())];
+        assert!(msg.render(true).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use super::bindings::greentic::state::state_store;
+use serde::{Deserialize, Serialize};
+
+/// Latest message seen or sent in an email thread, persisted per session so a
+/// `reply` can thread without the caller passing `reply_to_id`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub(crate) struct ThreadRecord {
+    /// Graph id of the newest message (target for `createReply`).
+    #[serde(default)]
+    pub last_message_id: Option<String>,
+    /// RFC 5322 Message-ID of the newest message (for `In-Reply-To`).
+    #[serde(default)]
+    pub internet_message_id: Option<String>,
+    /// Message-IDs of the thread, oldest first (for `References`).
+    #[serde(default)]
+    pub references: Vec<String>,
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// Sender of the newest inbound message; the default reply recipient.
+    #[serde(default)]
+    pub last_sender: Option<String>,
+}
+
+impl ThreadRecord {
+    /// Folds a newer message into the record, extending `References`. Messages
+    /// received outside Graph have no Graph id, which clears `last_message_id`
+    /// so a reply is threaded by MIME headers instead of `createReply`. Without a
+    /// new Message-ID the previous one stays the `In-Reply-To` target.
+    pub(crate) fn advance(
+        &mut self,
+        message_id: Option<&str>,
+        internet_message_id: Option<&str>,
+        conversation_id: Option<&str>,
+    ) {
+        self.last_message_id = message_id.map(str::to_string);
+        if let Some(id) = internet_message_id
+            && let Some(previous) = self.internet_message_id.replace(id.to_string())
+            && previous != id
+            && !self.references.contains(&previous)
+        {
+            self.references.push(previous);
+        }
+        if let Some(id) = conversation_id {
+            self.conversation_id = Some(id.to_string());
+        }
+    }
+}
+
+/// Session for a Graph conversation. Conversation ids are scoped to the
+/// mailbox, so the mailbox user is part of the key.
+pub(crate) fn conversation_session(mailbox: &str, conversation_id: &str) -> String {
+    format!("email:{mailbox}:conv:{conversation_id}")
+}
+
+/// Session for messages that only carry MIME headers: the thread root is the
+/// first `References` entry, else `In-Reply-To`, else the message itself.
+pub(crate) fn mime_session(
+    mailbox: &str,
+    message_id: &str,
+    in_reply_to: Option<&str>,
+    references: Option<&str>,
+) -> String {
+    let root = references
+        .and_then(|refs| message_ids(refs).into_iter().next())
+        .or_else(|| in_reply_to.and_then(|id| message_ids(id).into_iter().next()))
+        .unwrap_or_else(|| message_id.trim().to_string());
+    format!(
+        "email:{mailbox}:root:{}",
+        root.trim_start_matches('<').trim_end_matches('>')
+    )
+}
+
+/// Splits a `References` / `In-Reply-To` value into `<id>` tokens.
+pub(crate) fn message_ids(value: &str) -> Vec<String> {
+    value
+        .split(|ch: char| ch.is_whitespace() || ch == ',')
+        .filter(|token| token.starts_with('<') && token.ends_with('>') && token.len() > 2)
+        .map(str::to_string)
+        .collect()
+}
+
+/// `Re: <subject>` with any existing reply prefixes (`Re:`, `RE:`, `Re[2]:`,
+/// `AW:`, `SV:`) collapsed into one.
+pub(crate) fn reply_subject(subject: &str) -> String {
+    let mut rest = subject.trim();
+    loop {
+        let Some((prefix, tail)) = rest.split_once(':') else {
+            break;
+        };
+        let prefix = prefix.trim().to_ascii_lowercase();
+        let base = prefix.split('[').next().unwrap_or("");
+        let counter_ok = prefix
+            .strip_prefix(base)
+            .is_none_or(|counter| counter.is_empty() || counter.ends_with(']'));
+        if matches!(base, "re" | "aw" | "sv") && counter_ok {
+            rest = tail.trim_start();
+        } else {
+            break;
+        }
+    }
+    format!("Re: {rest}")
+}
+
+fn thread_key(session_id: &str) -> String {
+    format!("email:thread:{session_id}")
+}
+
+pub(crate) fn load(session_id: &str) -> Result<Option<ThreadRecord>, String> {
+    match state_store::read(&thread_key(session_id), None) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|err| format!("invalid thread record: {err}")),
+        Err(err) => {
+            let code = err.code.to_ascii_lowercase().replace('-', "_");
+            if code == "not_found" {
+                Ok(None)
+            } else {
+                Err(format!("state read error: {} - {}", err.code, err.message))
+            }
+        }
+    }
+}
+
+pub(crate) fn store(session_id: &str, record: &ThreadRecord) -> Result<(), String> {
+    let bytes = serde_json::to_vec(record).map_err(|err| format!("serialize thread: {err}"))?;
+    state_store::write(&thread_key(session_id), &bytes, None)
+        .map(|_| ())
+        .map_err(|err| format!("state write error: {} - {}", err.code, err.message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_subject_has_single_prefix() {
+        assert_eq!(reply_subject("Quarterly plan"), "Re: Quarterly plan");
+        assert_eq!(reply_subject("Re: Quarterly plan"), "Re: Quarterly plan");
+        assert_eq!(reply_subject("RE: re: Re[2]: AW: plan"), "Re: plan");
+        assert_eq!(reply_subject("Reminder: plan"), "Re: Reminder: plan");
+        assert_eq!(reply_subject(""), "Re: ");
+    }
+
+    #[test]
+    fn sessions_follow_thread_root() {
+        assert_eq!(
+            conversation_session("user-1", "AAQk=="),
+            "email:user-1:conv:AAQk=="
+        );
+        assert_eq!(
+            mime_session("u", "<c@x>", Some("<b@x>"), Some("<a@x> <b@x>")),
+            "email:u:root:a@x"
+        );
+        assert_eq!(
+            mime_session("u", "<c@x>", Some("<b@x>"), None),
+            "email:u:root:b@x"
+        );
+        assert_eq!(mime_session("u", "<c@x>", None, None), "email:u:root:c@x");
+    }
+
+    #[test]
+    fn advance_extends_references() {
+        let mut record = ThreadRecord::default();
+        record.advance(Some("m1"), Some("<a@x>"), Some("conv"));
+        record.advance(Some("m2"), Some("<b@x>"), None);
+        assert_eq!(record.last_message_id.as_deref(), Some("m2"));
+        assert_eq!(record.internet_message_id.as_deref(), Some("<b@x>"));
+        assert_eq!(record.references, vec!["<a@x>".to_string()]);
+        assert_eq!(record.conversation_id.as_deref(), Some("conv"));
+        record.advance(None, Some("<c@x>"), None);
+        assert_eq!(record.last_message_id, None);
+        assert_eq!(record.references.len(), 2);
+    }
+
+    #[test]
+    fn advance_without_message_id_keeps_the_previous_one() {
+        let mut record = ThreadRecord::default();
+        record.advance(Some("m1"), Some("<a@x>"), None);
+        record.advance(Some("m2"), None, None);
+        assert_eq!(record.last_message_id.as_deref(), Some("m2"));
+        assert_eq!(record.internet_message_id.as_deref(), Some("<a@x>"));
+        assert!(record.references.is_empty());
+    }
+}
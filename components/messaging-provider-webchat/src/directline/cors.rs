@@ -0,0 +1,115 @@
+use greentic_types::messaging::universal_dto::Header;
+use serde_json::Value;
+
+const ALLOWED_METHODS: &str = "GET, POST, OPTIONS";
+const DEFAULT_ALLOWED_HEADERS: &str = "Authorization, Content-Type";
+const EXPOSED_HEADERS: &str = "Retry-After";
+const PREFLIGHT_MAX_AGE_SECONDS: u32 = 600;
+
+/// Browser origins allowed to call the Direct Line endpoints, read from the
+/// tenant's `allowed_origins` config. Without an allowlist every origin is
+/// accepted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OriginPolicy {
+    allowed: Option<Vec<String>>,
+}
+
+impl OriginPolicy {
+    pub fn from_config(config: Option<&Value>) -> Self {
+        let allowed = config
+            .and_then(|cfg| cfg.get("allowed_origins"))
+            .and_then(Value::as_array)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(normalize)
+                    .filter(|origin| !origin.is_empty())
+                    .collect()
+            });
+        OriginPolicy { allowed }
+    }
+
+    /// Whether `origin` matches an entry: an exact origin, `*`, or a
+    /// `scheme://*.domain` wildcard covering subdomains (not the domain itself).
+    pub fn allows(&self, origin: &str) -> bool {
+        let Some(allowed) = &self.allowed else {
+            return true;
+        };
+        let origin = normalize(origin);
+        allowed.iter().any(|entry| {
+            if entry == "*" || *entry == origin {
+                return true;
+            }
+            let Some((scheme, domain)) = entry.split_once("://*.") else {
+                return false;
+            };
+            origin
+                .strip_prefix(scheme)
+                .and_then(|rest| rest.strip_prefix("://"))
+                .and_then(|host| host.strip_suffix(domain))
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+        })
+    }
+}
+
+/// Headers added to responses for an allowed cross-origin request.
+pub fn response_headers(origin: &str) -> Vec<Header> {
+    vec![
+        header("Access-Control-Allow-Origin", origin),
+        header("Access-Control-Expose-Headers", EXPOSED_HEADERS),
+        header("Vary", "Origin"),
+    ]
+}
+
+/// Headers answering an allowed preflight. Requested headers are echoed so
+/// Web Chat's custom headers pass.
+pub fn preflight_headers(origin: &str, requested_headers: Option<&str>) -> Vec<Header> {
+    let allowed_headers = requested_headers
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .unwrap_or(DEFAULT_ALLOWED_HEADERS);
+    vec![
+        header("Access-Control-Allow-Origin", origin),
+        header("Access-Control-Allow-Methods", ALLOWED_METHODS),
+        header("Access-Control-Allow-Headers", allowed_headers),
+        header(
+            "Access-Control-Max-Age",
+            &PREFLIGHT_MAX_AGE_SECONDS.to_string(),
+        ),
+        header("Vary", "Origin"),
+    ]
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn normalize(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn matches_exact_and_wildcard_origins() {
+        let policy = OriginPolicy::from_config(Some(&json!({
+            "allowed_origins": ["https://shop.example.com/", "https://*.partner.io"]
+        })));
+        assert!(policy.allows("https://shop.example.com"));
+        assert!(policy.allows("HTTPS://Shop.Example.com"));
+        assert!(!policy.allows("http://shop.example.com"));
+        assert!(policy.allows("https://eu.partner.io"));
+        assert!(!policy.allows("https://partner.io"));
+        assert!(!policy.allows("https://evilpartner.io"));
+        assert!(OriginPolicy::from_config(None).allows("https://anything.test"));
+        let closed = OriginPolicy::from_config(Some(&json!({"allowed_origins": []})));
+        assert!(!closed.allows("https://shop.example.com"));
+    }
+}
@@ -0,0 +1,447 @@
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use super::state::visit_record_keys;
+use super::store::{SecretStore, StateStore};
+
+pub const ENCRYPTION_KEY_SECRET: &str = "state_encryption_key";
+pub const PREVIOUS_ENCRYPTION_KEY_SECRET: &str = "state_previous_encryption_key";
+const ALGORITHM: &str = "XChaCha20-Poly1305";
+/// Read errors for records that exist but cannot be opened start with this,
+/// so a caller can tell them from store failures.
+const UNREADABLE_RECORD: &str = "unreadable state record";
+
+/// Whether an error from [`EncryptedStateStore`] means the record is there but
+/// cannot be opened (unknown key, failed authentication, or plaintext where
+/// encryption is required), as opposed to the store itself failing.
+pub fn is_unreadable_record(err: &str) -> bool {
+    err.starts_with(UNREADABLE_RECORD)
+}
+
+/// Reads `state_encryption_required` from the provider config.
+pub fn encryption_required(config: Option<&Value>) -> bool {
+    config
+        .and_then(|cfg| cfg.get("state_encryption_required"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// A 256-bit state encryption key and the id stored with records it seals.
+struct StateKey {
+    kid: String,
+    cipher: XChaCha20Poly1305,
+}
+
+impl StateKey {
+    /// Accepts 32 raw bytes or their base64 encoding.
+    fn parse(name: &str, secret: &[u8]) -> Result<Self, String> {
+        let bytes = if secret.len() == 32 {
+            secret.to_vec()
+        } else {
+            STANDARD
+                .decode(String::from_utf8_lossy(secret).trim())
+                .map_err(|_| format!("secret {name} must be 32 bytes or their base64"))?
+        };
+        if bytes.len() != 32 {
+            return Err(format!("secret {name} must be 32 bytes or their base64"));
+        }
+        let digest = Sha256::digest(&bytes);
+        let kid = digest[..8]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        Ok(StateKey {
+            kid,
+            cipher: XChaCha20Poly1305::new_from_slice(&bytes).expect("32 byte key"),
+        })
+    }
+}
+
+/// Keys for Direct Line state: records are sealed with the current key and
+/// opened with whichever key their `kid` names.
+pub struct EncryptionKeys {
+    /// Current key first.
+    keys: Vec<StateKey>,
+}
+
+impl EncryptionKeys {
+    /// Reads `state_encryption_key` and, during a rotation,
+    /// `state_previous_encryption_key`. `None` when encryption is not set up.
+    pub fn from_secrets<SE: SecretStore>(secrets: &SE) -> Result<Option<Self>, String> {
+        let Some(current) = non_empty(secrets.get(ENCRYPTION_KEY_SECRET)?) else {
+            return Ok(None);
+        };
+        let mut keys = vec![StateKey::parse(ENCRYPTION_KEY_SECRET, &current)?];
+        if let Some(previous) = non_empty(secrets.get(PREVIOUS_ENCRYPTION_KEY_SECRET)?) {
+            keys.push(StateKey::parse(PREVIOUS_ENCRYPTION_KEY_SECRET, &previous)?);
+        }
+        Ok(Some(EncryptionKeys { keys }))
+    }
+
+    fn seal(&self, key: &str, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let current = &self.keys[0];
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = current
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: key.as_bytes(),
+                },
+            )
+            .map_err(|_| format!("failed to encrypt state record {key}"))?;
+        let record = SealedRecord {
+            enc: ALGORITHM.to_string(),
+            kid: current.kid.clone(),
+            nonce: STANDARD.encode(nonce),
+            ct: STANDARD.encode(ciphertext),
+        };
+        serde_json::to_vec(&record).map_err(|err| err.to_string())
+    }
+
+    fn open(&self, key: &str, record: &SealedRecord) -> Result<Vec<u8>, String> {
+        let state_key = self
+            .keys
+            .iter()
+            .find(|candidate| candidate.kid == record.kid)
+            .ok_or_else(|| {
+                format!(
+                    "{UNREADABLE_RECORD} {key}: sealed with unknown key {}",
+                    record.kid
+                )
+            })?;
+        let nonce = STANDARD
+            .decode(&record.nonce)
+            .ok()
+            .filter(|nonce| nonce.len() == 24)
+            .ok_or_else(|| format!("{UNREADABLE_RECORD} {key}: invalid nonce"))?;
+        let ciphertext = STANDARD
+            .decode(&record.ct)
+            .map_err(|err| format!("{UNREADABLE_RECORD} {key}: invalid ciphertext: {err}"))?;
+        state_key
+            .cipher
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: key.as_bytes(),
+                },
+            )
+            .map_err(|_| format!("{UNREADABLE_RECORD} {key}: failed authentication"))
+    }
+}
+
+/// Stored form of an encrypted value. The state key is bound as associated
+/// data, so a record copied under another key does not open.
+#[derive(Serialize, Deserialize)]
+struct SealedRecord {
+    enc: String,
+    kid: String,
+    nonce: String,
+    ct: String,
+}
+
+/// State store that encrypts values before they reach `inner`. Without keys it
+/// passes values through. Plaintext records written before encryption was
+/// enabled are still read, and are sealed the next time they are written;
+/// records sealed with a previous key likewise move to the current key on
+/// their next write, or when [`reseal_conversations`] walks them. Once
+/// encryption is required, plaintext records are refused and nothing is
+/// stored without a key.
+pub struct EncryptedStateStore<S> {
+    inner: S,
+    keys: Option<EncryptionKeys>,
+    required: bool,
+}
+
+impl<S: StateStore> EncryptedStateStore<S> {
+    pub fn new(inner: S, keys: Option<EncryptionKeys>) -> Self {
+        EncryptedStateStore {
+            inner,
+            keys,
+            required: false,
+        }
+    }
+
+    /// Refuses plaintext records (and operating without a key) when
+    /// `required`, e.g. from `state_encryption_required`.
+    pub fn require_encryption(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    fn missing_key(&self) -> Result<(), String> {
+        if self.required && self.keys.is_none() {
+            return Err(format!(
+                "state encryption is required but {ENCRYPTION_KEY_SECRET} is not set"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Rewrites the record under the current key when it is plaintext or
+    /// sealed with another key. Returns whether it was rewritten.
+    pub fn reseal(&mut self, key: &str) -> Result<bool, String> {
+        let Some(keys) = &self.keys else {
+            return Ok(false);
+        };
+        let Some(bytes) = self.inner.read(key)? else {
+            return Ok(false);
+        };
+        let plaintext = match serde_json::from_slice::<SealedRecord>(&bytes) {
+            Ok(record) if record.enc == ALGORITHM => {
+                if record.kid == keys.keys[0].kid {
+                    return Ok(false);
+                }
+                keys.open(key, &record)?
+            }
+            _ => bytes,
+        };
+        let sealed = keys.seal(key, &plaintext)?;
+        self.inner.write(key, &sealed)?;
+        Ok(true)
+    }
+}
+
+/// Moves every Direct Line conversation record (index, headers, history pages
+/// and uploads) to the current key, so a rotation is complete and
+/// `state_previous_encryption_key` can be removed once this has run. Returns
+/// the number of records rewritten.
+pub fn reseal_conversations<S: StateStore>(
+    store: &mut EncryptedStateStore<S>,
+    now_ms: i64,
+) -> Result<usize, String> {
+    let mut resealed = 0;
+    visit_record_keys(store, now_ms, |store, key| {
+        if store.reseal(key)? {
+            resealed += 1;
+        }
+        Ok(())
+    })?;
+    Ok(resealed)
+}
+
+impl<S: StateStore> StateStore for EncryptedStateStore<S> {
+    fn read(&mut self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        self.missing_key()?;
+        let Some(bytes) = self.inner.read(key)? else {
+            return Ok(None);
+        };
+        let record = match serde_json::from_slice::<SealedRecord>(&bytes) {
+            Ok(record) if record.enc == ALGORITHM => record,
+            _ if self.required => {
+                return Err(format!(
+                    "{UNREADABLE_RECORD} {key}: plaintext while state encryption is required"
+                ));
+            }
+            _ => return Ok(Some(bytes)),
+        };
+        match &self.keys {
+            Some(keys) => keys.open(key, &record).map(Some),
+            None => Err(format!(
+                "{UNREADABLE_RECORD} {key}: encrypted but {ENCRYPTION_KEY_SECRET} is not set"
+            )),
+        }
+    }
+
+    fn write(&mut self, key: &str, value: &[u8]) -> Result<(), String> {
+        self.missing_key()?;
+        match &self.keys {
+            Some(keys) => {
+                let sealed = keys.seal(key, value)?;
+                self.inner.write(key, &sealed)
+            }
+            None => self.inner.write(key, value),
+        }
+    }
+
+    fn delete(&mut self, key: &str) -> Result<(), String> {
+        self.inner.delete(key)
+    }
+}
+
+fn non_empty(value: Option<Vec<u8>>) -> Option<Vec<u8>> {
+    value.filter(|bytes| !bytes.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MemoryStore(HashMap<String, Vec<u8>>);
+
+    impl StateStore for MemoryStore {
+        fn read(&mut self, key: &str) -> Result<Option<Vec<u8>>, String> {
+            Ok(self.0.get(key).cloned())
+        }
+
+        fn write(&mut self, key: &str, value: &[u8]) -> Result<(), String> {
+            self.0.insert(key.to_string(), value.to_vec());
+            Ok(())
+        }
+
+        fn delete(&mut self, key: &str) -> Result<(), String> {
+            self.0.remove(key);
+            Ok(())
+        }
+    }
+
+    struct Secrets(HashMap<&'static str, Vec<u8>>);
+
+    impl SecretStore for Secrets {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+            Ok(self.0.get(key).cloned())
+        }
+    }
+
+    fn keys(current: &[u8], previous: Option<&[u8]>) -> Option<EncryptionKeys> {
+        let mut secrets = HashMap::new();
+        secrets.insert(ENCRYPTION_KEY_SECRET, current.to_vec());
+        if let Some(previous) = previous {
+            secrets.insert(PREVIOUS_ENCRYPTION_KEY_SECRET, previous.to_vec());
+        }
+        EncryptionKeys::from_secrets(&Secrets(secrets)).unwrap()
+    }
+
+    #[test]
+    fn seals_values_and_opens_them_across_rotation() {
+        let old_key = [1u8; 32];
+        let new_key = STANDARD.encode([2u8; 32]);
+        let mut store = EncryptedStateStore::new(MemoryStore::default(), keys(&old_key, None));
+        store
+            .write("webchat:conv:a", br#"{"text":"secret"}"#)
+            .unwrap();
+        let raw = store.inner.0["webchat:conv:a"].clone();
+        assert!(!String::from_utf8_lossy(&raw).contains("secret"));
+        assert_eq!(
+            store.read("webchat:conv:a").unwrap().unwrap(),
+            br#"{"text":"secret"}"#
+        );
+
+        // Sealed values are bound to their key.
+        store.inner.0.insert("webchat:conv:b".into(), raw);
+        assert!(store.read("webchat:conv:b").is_err());
+
+        // Plaintext written before encryption was enabled still reads.
+        store.inner.0.insert("legacy".into(), b"{\"v\":1}".to_vec());
+        assert_eq!(store.read("legacy").unwrap().unwrap(), b"{\"v\":1}");
+
+        let mut rotated =
+            EncryptedStateStore::new(store.inner, keys(new_key.as_bytes(), Some(&old_key)));
+        assert_eq!(
+            rotated.read("webchat:conv:a").unwrap().unwrap(),
+            br#"{"text":"secret"}"#
+        );
+        let mut dropped = EncryptedStateStore::new(rotated.inner, keys(new_key.as_bytes(), None));
+        assert!(is_unreadable_record(
+            &dropped.read("webchat:conv:a").unwrap_err()
+        ));
+        let mut unkeyed = EncryptedStateStore::new(dropped.inner, None);
+        assert!(unkeyed.read("webchat:conv:a").is_err());
+
+        let short = Secrets(HashMap::from([(ENCRYPTION_KEY_SECRET, b"short".to_vec())]));
+        assert!(EncryptionKeys::from_secrets(&short).is_err());
+    }
+
+    #[test]
+    fn required_encryption_refuses_plaintext() {
+        let mut inner = MemoryStore::default();
+        inner.0.insert("legacy".into(), b"{\"v\":1}".to_vec());
+        let mut store = EncryptedStateStore::new(inner, keys(&[1u8; 32], None));
+        store.write("sealed", b"{}").unwrap();
+        let mut store = store.require_encryption(true);
+        let err = store.read("legacy").unwrap_err();
+        assert!(is_unreadable_record(&err), "{err}");
+        assert_eq!(store.read("sealed").unwrap().unwrap(), b"{}");
+        assert_eq!(store.read("missing").unwrap(), None);
+
+        // Resealing migrates the plaintext record so it reads again.
+        assert!(store.reseal("legacy").unwrap());
+        assert_eq!(store.read("legacy").unwrap().unwrap(), b"{\"v\":1}");
+
+        let mut unkeyed = EncryptedStateStore::new(store.inner, None).require_encryption(true);
+        let err = unkeyed.read("sealed").unwrap_err();
+        assert!(!is_unreadable_record(&err), "{err}");
+        assert!(unkeyed.write("other", b"{}").is_err());
+        assert!(!unkeyed.inner.0.contains_key("other"));
+
+        assert!(encryption_required(Some(
+            &serde_json::json!({"state_encryption_required": true})
+        )));
+        assert!(!encryption_required(None));
+    }
+
+    #[test]
+    fn reseal_completes_a_rotation() {
+        use crate::directline::jwt::DirectLineContext;
+        use crate::directline::state::{
+            Retention, StoredActivity, append_activity, blob_key, conversation_key,
+            create_conversation, load_conversation, read_activities, save_conversation,
+        };
+        use chrono::Utc;
+
+        let old_key = [1u8; 32];
+        let new_key = [2u8; 32];
+        let ctx = DirectLineContext {
+            env: "env".into(),
+            tenant: "tenant".into(),
+            team: None,
+        };
+        let now = Utc::now().timestamp_millis();
+        let mut store = EncryptedStateStore::new(MemoryStore::default(), keys(&old_key, None));
+        let mut conversation =
+            create_conversation(&mut store, &ctx, "c", Retention::default()).unwrap();
+        let key = conversation_key(&ctx, "c");
+        append_activity(
+            &mut store,
+            &key,
+            &mut conversation,
+            StoredActivity {
+                id: "a1".into(),
+                type_: "message".into(),
+                text: Some("secret".into()),
+                from: Some("alice".into()),
+                timestamp: now,
+                watermark: 0,
+                raw: serde_json::Value::Null,
+                blobs: vec!["b1".into()],
+            },
+        )
+        .unwrap();
+        conversation.blobs.push("b1".into());
+        save_conversation(&mut store, &key, &conversation).unwrap();
+        store
+            .write(&blob_key(&ctx, "c", "b1"), br#"{"data":"x"}"#)
+            .unwrap();
+        let records = store.inner.0.len();
+
+        let mut rotated = EncryptedStateStore::new(store.inner, keys(&new_key, Some(&old_key)));
+        assert_eq!(reseal_conversations(&mut rotated, now).unwrap(), records);
+        assert_eq!(reseal_conversations(&mut rotated, now).unwrap(), 0);
+
+        let mut completed = EncryptedStateStore::new(rotated.inner, keys(&new_key, None));
+        let stored = load_conversation(&mut completed, &key).unwrap().unwrap();
+        let (activities, _) = read_activities(&mut completed, &key, &stored, None, 10).unwrap();
+        assert_eq!(activities[0].text.as_deref(), Some("secret"));
+        assert!(
+            completed
+                .read(&blob_key(&ctx, "c", "b1"))
+                .unwrap()
+                .is_some()
+        );
+        let keys_left: Vec<String> = completed.inner.0.keys().cloned().collect();
+        for key in keys_left {
+            assert!(
+                completed.read(&key).is_ok(),
+                "{key} still needs the old key"
+            );
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use serde_json::Value;
+
+#[cfg(not(test))]
+use crate::bindings::greentic::telemetry::logger_api;
+
+use super::jwt::DirectLineContext;
+use super::state::sanitize_team;
+
+pub const DEFAULT_WINDOW_SECONDS: i64 = 60;
+/// Longest accepted rate-limit window (one day).
+pub const MAX_WINDOW_SECONDS: i64 = 24 * 60 * 60;
+pub const DEFAULT_TOKEN_REQUESTS: u32 = 5;
+pub const DEFAULT_USER_ACTIVITIES: u32 = 30;
+pub const DEFAULT_CONVERSATION_ACTIVITIES: u32 = 60;
+
+/// Request budgets per rate-limit window. Token generation is limited per
+/// user; posted activities per user and per conversation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimits {
+    pub window_seconds: i64,
+    pub token_requests: u32,
+    pub user_activities: u32,
+    pub conversation_activities: u32,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            window_seconds: DEFAULT_WINDOW_SECONDS,
+            token_requests: DEFAULT_TOKEN_REQUESTS,
+            user_activities: DEFAULT_USER_ACTIVITIES,
+            conversation_activities: DEFAULT_CONVERSATION_ACTIVITIES,
+        }
+    }
+}
+
+impl RateLimits {
+    /// Reads `rate_limit_window_seconds`, `token_rate_limit`,
+    /// `activity_rate_limit_per_user` and `activity_rate_limit_per_conversation`
+    /// from the provider config, falling back to the defaults. The window is
+    /// capped at [`MAX_WINDOW_SECONDS`].
+    pub fn from_config(config: Option<&Value>) -> Self {
+        let defaults = RateLimits::default();
+        let get = |key: &str| {
+            config
+                .and_then(|cfg| cfg.get(key))
+                .and_then(Value::as_u64)
+                .filter(|value| *value > 0)
+        };
+        let count = |key: &str, default: u32| {
+            get(key)
+                .map(|value| value.min(u32::MAX as u64) as u32)
+                .unwrap_or(default)
+        };
+        RateLimits {
+            window_seconds: get("rate_limit_window_seconds")
+                .map(|value| value.min(MAX_WINDOW_SECONDS as u64) as i64)
+                .unwrap_or(defaults.window_seconds),
+            token_requests: count("token_rate_limit", defaults.token_requests),
+            user_activities: count("activity_rate_limit_per_user", defaults.user_activities),
+            conversation_activities: count(
+                "activity_rate_limit_per_conversation",
+                defaults.conversation_activities,
+            ),
+        }
+    }
+}
+
+/// What a rate limit is counted against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LimitScope {
+    Tokens,
+    UserActivities,
+    ConversationActivities,
+}
+
+impl LimitScope {
+    pub fn label(self) -> &'static str {
+        match self {
+            LimitScope::Tokens => "tokens",
+            LimitScope::UserActivities => "user_activities",
+            LimitScope::ConversationActivities => "conversation_activities",
+        }
+    }
+}
+
+pub fn counter_key(ctx: &DirectLineContext, scope: LimitScope, subject: &str) -> String {
+    format!(
+        "webchat:rate:{}:{}:{}:{}:{}",
+        scope.label(),
+        ctx.env,
+        ctx.tenant,
+        sanitize_team(ctx.team.as_deref()),
+        subject
+    )
+}
+
+/// Telemetry fields describing a refused request.
+pub fn hit_fields(ctx: &DirectLineContext, scope: LimitScope, now: i64) -> Vec<(String, String)> {
+    vec![
+        ("event".to_string(), "rate_limited".to_string()),
+        ("scope".to_string(), scope.label().to_string()),
+        ("env".to_string(), ctx.env.clone()),
+        ("tenant".to_string(), ctx.tenant.clone()),
+        ("team".to_string(), sanitize_team(ctx.team.as_deref())),
+        ("at".to_string(), now.to_string()),
+    ]
+}
+
+/// Reports a refused request through the host telemetry logger. Nothing is
+/// written to state, so a burst of refusals costs no state writes.
+pub fn record_hit(ctx: &DirectLineContext, scope: LimitScope, now: i64) {
+    let fields = hit_fields(ctx, scope, now);
+    #[cfg(test)]
+    {
+        RECORDED_HITS.with(|hits| hits.borrow_mut().push(fields));
+    }
+    #[cfg(not(test))]
+    {
+        let span = logger_api::SpanContext {
+            tenant: ctx.tenant.clone(),
+            session_id: None,
+            flow_id: "directline".into(),
+            node_id: None,
+            provider: "messaging.webchat".into(),
+            start_ms: Some(now.saturating_mul(1000)),
+            end_ms: None,
+        };
+        // Losing a telemetry event must not change the response.
+        let _ = logger_api::log(&span, &fields, None);
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    pub static RECORDED_HITS: std::cell::RefCell<Vec<Vec<(String, String)>>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn from_config_defaults_without_config() {
+        assert_eq!(RateLimits::from_config(None), RateLimits::default());
+        assert_eq!(
+            RateLimits::from_config(Some(&json!({}))),
+            RateLimits::default()
+        );
+    }
+
+    #[test]
+    fn from_config_reads_positive_values() {
+        let limits = RateLimits::from_config(Some(&json!({
+            "rate_limit_window_seconds": 30,
+            "token_rate_limit": 2,
+            "activity_rate_limit_per_user": 10,
+            "activity_rate_limit_per_conversation": 20,
+        })));
+        assert_eq!(
+            limits,
+            RateLimits {
+                window_seconds: 30,
+                token_requests: 2,
+                user_activities: 10,
+                conversation_activities: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn from_config_ignores_zero_invalid_and_clamps_huge_values() {
+        let limits = RateLimits::from_config(Some(&json!({
+            "rate_limit_window_seconds": 0,
+            "token_rate_limit": "5",
+            "activity_rate_limit_per_user": -1,
+            "activity_rate_limit_per_conversation": u64::MAX,
+        })));
+        assert_eq!(limits.window_seconds, DEFAULT_WINDOW_SECONDS);
+        assert_eq!(limits.token_requests, DEFAULT_TOKEN_REQUESTS);
+        assert_eq!(limits.user_activities, DEFAULT_USER_ACTIVITIES);
+        assert_eq!(limits.conversation_activities, u32::MAX);
+
+        let window = RateLimits::from_config(Some(&json!({
+            "rate_limit_window_seconds": u64::MAX,
+        })));
+        assert_eq!(window.window_seconds, MAX_WINDOW_SECONDS);
+    }
+}
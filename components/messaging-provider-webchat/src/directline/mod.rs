@@ -1,5 +1,9 @@
+pub mod cors;
+pub mod encryption;
 pub mod http;
 pub mod jwt;
+pub mod limits;
+pub mod multipart;
 pub mod state;
 pub mod store;
 
@@ -0,0 +1,113 @@
+/// One part of a `multipart/form-data` body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Part {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Boundary parameter of a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.find_map(|param| parameter(param, "boundary"))
+}
+
+pub fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>, String> {
+    let delimiter = format!("--{boundary}");
+    let mut segments = split(body, delimiter.as_bytes()).into_iter();
+    // Anything before the first delimiter is preamble.
+    segments.next();
+    let mut parts = Vec::new();
+    for segment in segments {
+        if segment.starts_with(b"--") {
+            return Ok(parts);
+        }
+        let segment = segment.strip_prefix(b"\r\n").unwrap_or(segment);
+        let segment = segment.strip_suffix(b"\r\n").unwrap_or(segment);
+        let split_at = find(segment, b"\r\n\r\n")
+            .ok_or_else(|| "multipart part without headers".to_string())?;
+        let head = String::from_utf8_lossy(&segment[..split_at]);
+        let mut part = Part {
+            name: None,
+            filename: None,
+            content_type: None,
+            data: segment[split_at + 4..].to_vec(),
+        };
+        for line in head.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            if key.trim().eq_ignore_ascii_case("content-disposition") {
+                for param in value.split(';').skip(1) {
+                    part.name = part.name.take().or_else(|| parameter(param, "name"));
+                    part.filename = part
+                        .filename
+                        .take()
+                        .or_else(|| parameter(param, "filename"));
+                }
+            } else if key.trim().eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.trim().to_string());
+            }
+        }
+        parts.push(part);
+    }
+    Err("multipart body missing closing boundary".into())
+}
+
+fn parameter(param: &str, name: &str) -> Option<String> {
+    let (key, value) = param.split_once('=')?;
+    key.trim()
+        .eq_ignore_ascii_case(name)
+        .then(|| value.trim().trim_matches('"').to_string())
+}
+
+fn split<'a>(haystack: &'a [u8], needle: &[u8]) -> Vec<&'a [u8]> {
+    let mut out = Vec::new();
+    let mut rest = haystack;
+    while let Some(pos) = find(rest, needle) {
+        out.push(&rest[..pos]);
+        rest = &rest[pos + needle.len()..];
+    }
+    out.push(rest);
+    out
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_activity_and_file_parts() {
+        let body = b"--b1\r\nContent-Disposition: form-data; name=\"activity\"\r\nContent-Type: application/vnd.microsoft.activity\r\n\r\n{\"type\":\"message\"}\r\n--b1\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\n\x89PNG\r\n--b1--\r\n";
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"b1\"").as_deref(),
+            Some("b1")
+        );
+        assert_eq!(boundary("application/json"), None);
+        let parts = parse(body, "b1").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name.as_deref(), Some("activity"));
+        assert_eq!(parts[0].data, b"{\"type\":\"message\"}");
+        assert_eq!(parts[1].filename.as_deref(), Some("a.png"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("image/png"));
+        assert_eq!(parts[1].data, b"\x89PNG");
+        assert!(
+            parse(
+                b"--b1\r\nContent-Disposition: form-data; name=\"x\"\r\n\r\ny",
+                "b1"
+            )
+            .is_err()
+        );
+    }
+}
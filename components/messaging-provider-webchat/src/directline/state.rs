@@ -1,7 +1,30 @@
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use uuid::Uuid;
 
 use super::jwt::DirectLineContext;
+use super::store::StateStore;
+
+/// `from.id` of activities the bot sends into a conversation.
+pub const BOT_ID: &str = "bot";
+/// Activities per stored history page; page `n` holds watermarks
+/// `n * PAGE_SIZE .. (n + 1) * PAGE_SIZE`.
+pub const PAGE_SIZE: u64 = 50;
+pub const DEFAULT_MAX_ACTIVITIES: u64 = 1000;
+pub const DEFAULT_MAX_AGE_SECONDS: i64 = 7 * 24 * 60 * 60;
+pub const DEFAULT_IDLE_TTL_SECONDS: i64 = 30 * 24 * 60 * 60;
+/// Conversations are indexed for idle cleanup per creation day (days since the
+/// epoch), spread over `INDEX_SHARDS` buckets by conversation id
+/// (`webchat:conv-index:{day}:{shard}`) so that conversations created at the
+/// same time rarely rewrite the same bucket. The first-day key holds the
+/// oldest day that may still have entries.
+const CONVERSATION_INDEX_KEY: &str = "webchat:conv-index";
+const INDEX_FIRST_DAY_KEY: &str = "webchat:conv-index:first-day";
+const INDEX_SHARDS: u8 = 16;
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct StoredActivity {
@@ -16,12 +39,68 @@ pub struct StoredActivity {
     pub watermark: u64,
     #[serde(default)]
     pub raw: Value,
+    /// Blobs uploaded with this activity, deleted when it leaves the history.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blobs: Vec<String>,
+}
+
+/// How much history a conversation keeps. Older activities are dropped as new
+/// ones are appended.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Retention {
+    pub max_activities: u64,
+    pub max_age_seconds: i64,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            max_activities: DEFAULT_MAX_ACTIVITIES,
+            max_age_seconds: DEFAULT_MAX_AGE_SECONDS,
+        }
+    }
+}
+
+impl Retention {
+    /// Reads `history_max_activities` / `history_max_age_seconds` from the
+    /// provider config, falling back to the defaults.
+    pub fn from_config(config: Option<&Value>) -> Self {
+        let defaults = Retention::default();
+        let get = |key: &str| config.and_then(|cfg| cfg.get(key)).and_then(Value::as_u64);
+        Retention {
+            max_activities: get("history_max_activities")
+                .filter(|value| *value > 0)
+                .unwrap_or(defaults.max_activities),
+            max_age_seconds: get("history_max_age_seconds")
+                .filter(|value| *value > 0)
+                .map(|value| value.min(i64::MAX as u64) as i64)
+                .unwrap_or(defaults.max_age_seconds),
+        }
+    }
 }
 
+/// Conversation header. Activities live in separate pages (see [`page_key`]) so
+/// an append rewrites one page instead of the whole history.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ConversationState {
     pub ctx: DirectLineContext,
     pub next_watermark: u64,
+    /// Oldest watermark still retained.
+    #[serde(default)]
+    pub first_watermark: u64,
+    /// Timestamp (ms) of the activity at `first_watermark`.
+    #[serde(default)]
+    pub first_timestamp: i64,
+    /// Last append (ms), used to find idle conversations.
+    #[serde(default)]
+    pub last_active: i64,
+    #[serde(default)]
+    pub retention: Retention,
+    /// Uploaded blob ids, deleted with the conversation.
+    #[serde(default)]
+    pub blobs: Vec<String>,
+    /// Inline history written before paging; moved into pages on next append.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub activities: Vec<StoredActivity>,
 }
 
@@ -30,6 +109,11 @@ impl ConversationState {
         ConversationState {
             ctx,
             next_watermark: 0,
+            first_watermark: 0,
+            first_timestamp: 0,
+            last_active: Utc::now().timestamp_millis(),
+            retention: Retention::default(),
+            blobs: Vec::new(),
             activities: Vec::new(),
         }
     }
@@ -41,6 +125,18 @@ impl ConversationState {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct IndexEntry {
+    ctx: DirectLineContext,
+    conversation_id: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PurgeReport {
+    pub purged: usize,
+    pub remaining: usize,
+}
+
 pub fn conversation_key(ctx: &DirectLineContext, conversation_id: &str) -> String {
     format!(
         "webchat:conv:{}:{}:{}:{}",
@@ -51,15 +147,487 @@ pub fn conversation_key(ctx: &DirectLineContext, conversation_id: &str) -> Strin
     )
 }
 
+pub fn page_key(conversation_key: &str, page: u64) -> String {
+    format!("{conversation_key}:page:{page}")
+}
+
+/// File uploaded into a conversation, kept base64 encoded so it can be served
+/// as an HTTP body without re-encoding.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StoredBlob {
+    pub content_type: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub data_b64: String,
+}
+
+pub fn blob_key(ctx: &DirectLineContext, conversation_id: &str, blob_id: &str) -> String {
+    conversation_blob_key(&conversation_key(ctx, conversation_id), blob_id)
+}
+
+/// [`blob_key`] for a conversation addressed by its state key.
+fn conversation_blob_key(conversation_key: &str, blob_id: &str) -> String {
+    let scope = conversation_key
+        .strip_prefix("webchat:conv:")
+        .unwrap_or(conversation_key);
+    format!("webchat:blob:{scope}:{blob_id}")
+}
+
+pub fn load_conversation<S: StateStore>(
+    store: &mut S,
+    key: &str,
+) -> Result<Option<ConversationState>, String> {
+    store
+        .read(key)?
+        .map(|bytes| {
+            serde_json::from_slice(&bytes)
+                .map_err(|err| format!("invalid conversation state: {err}"))
+        })
+        .transpose()
+}
+
+pub fn save_conversation<S: StateStore>(
+    store: &mut S,
+    key: &str,
+    conversation: &ConversationState,
+) -> Result<(), String> {
+    let bytes =
+        serde_json::to_vec(conversation).map_err(|err| format!("serialize conversation: {err}"))?;
+    store.write(key, &bytes)
+}
+
+/// Stores a new conversation and registers it for idle cleanup.
+pub fn create_conversation<S: StateStore>(
+    store: &mut S,
+    ctx: &DirectLineContext,
+    conversation_id: &str,
+    retention: Retention,
+) -> Result<ConversationState, String> {
+    let mut conversation = ConversationState::new(ctx.clone());
+    conversation.retention = retention;
+    save_conversation(
+        store,
+        &conversation_key(ctx, conversation_id),
+        &conversation,
+    )?;
+    register_in_index(
+        store,
+        index_day(conversation.last_active),
+        vec![IndexEntry {
+            ctx: ctx.clone(),
+            conversation_id: conversation_id.to_string(),
+        }],
+    )?;
+    Ok(conversation)
+}
+
+/// Appends an activity with the next watermark, applies the conversation's
+/// retention and saves the header. Only the activity's page is rewritten.
+pub fn append_activity<S: StateStore>(
+    store: &mut S,
+    key: &str,
+    conversation: &mut ConversationState,
+    mut activity: StoredActivity,
+) -> Result<StoredActivity, String> {
+    migrate_inline_history(store, key, conversation)?;
+    activity.watermark = conversation.bump_watermark();
+    let page = activity.watermark / PAGE_SIZE;
+    let mut activities = read_page(store, key, page)?;
+    activities.push(activity.clone());
+    write_page(store, key, page, &activities)?;
+    if conversation.first_watermark == activity.watermark {
+        conversation.first_timestamp = activity.timestamp;
+    }
+    conversation.last_active = activity.timestamp;
+    apply_retention(store, key, conversation, activity.timestamp)?;
+    save_conversation(store, key, conversation)?;
+    Ok(activity)
+}
+
+/// Activities from watermark `since` (clamped to the oldest retained), at most
+/// `limit` of them, and the watermark to poll with next.
+pub fn read_activities<S: StateStore>(
+    store: &mut S,
+    key: &str,
+    conversation: &ConversationState,
+    since: Option<u64>,
+    limit: usize,
+) -> Result<(Vec<StoredActivity>, u64), String> {
+    let start = since.unwrap_or(0).max(conversation.first_watermark);
+    let mut out: Vec<StoredActivity> = conversation
+        .activities
+        .iter()
+        .filter(|activity| activity.watermark >= start)
+        .take(limit)
+        .cloned()
+        .collect();
+    let mut page = start / PAGE_SIZE;
+    while out.len() < limit && page * PAGE_SIZE < conversation.next_watermark {
+        let remaining = limit - out.len();
+        out.extend(
+            read_page(store, key, page)?
+                .into_iter()
+                .filter(|activity| activity.watermark >= start)
+                .take(remaining),
+        );
+        page += 1;
+    }
+    let cursor = match out.last() {
+        Some(last) if out.len() >= limit => last.watermark + 1,
+        _ => conversation.next_watermark,
+    };
+    Ok((out, cursor))
+}
+
+/// Appends a bot activity (`message`, `typing`, `event`, `invokeResponse`, ...)
+/// to a stored conversation so clients polling `GET /activities` receive it
+/// with the next watermark. `activity` carries the Direct Line fields to send;
+/// id, sender, conversation and timestamp are filled in here.
+pub fn append_bot_activity<S: StateStore>(
+    store: &mut S,
+    ctx: &DirectLineContext,
+    conversation_id: &str,
+    activity: Value,
+) -> Result<StoredActivity, String> {
+    let key = conversation_key(ctx, conversation_id);
+    let mut conversation = load_conversation(store, &key)?
+        .ok_or_else(|| format!("conversation {conversation_id} not found"))?;
+    let mut raw = match activity {
+        Value::Object(map) => map,
+        _ => return Err("bot activity must be an object".into()),
+    };
+    raw.insert("from".to_string(), json!({"id": BOT_ID, "role": "bot"}));
+    raw.insert("conversation".to_string(), json!({"id": conversation_id}));
+    let activity = StoredActivity {
+        id: Uuid::new_v4().to_string(),
+        type_: raw
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("message")
+            .to_string(),
+        text: raw.get("text").and_then(Value::as_str).map(str::to_string),
+        from: Some(BOT_ID.to_string()),
+        timestamp: Utc::now().timestamp_millis(),
+        watermark: 0,
+        raw: Value::Object(raw),
+        blobs: Vec::new(),
+    };
+    append_activity(store, &key, &mut conversation, activity)
+}
+
+/// Deletes conversations with no activity for `idle_ttl_seconds`, including
+/// their history pages and uploaded blobs. Walks the index buckets from the
+/// oldest one still holding entries up to today, dropping emptied buckets.
+pub fn purge_idle_conversations<S: StateStore>(
+    store: &mut S,
+    now_ms: i64,
+    idle_ttl_seconds: i64,
+) -> Result<PurgeReport, String> {
+    let cutoff = now_ms.saturating_sub(idle_ttl_seconds.saturating_mul(1000));
+    let today = index_day(now_ms);
+    let mut purged = 0;
+
+    // Index written before it was split into day buckets.
+    if let Some(bytes) = store.read(CONVERSATION_INDEX_KEY)? {
+        let legacy: Vec<IndexEntry> = serde_json::from_slice(&bytes)
+            .map_err(|err| format!("invalid conversation index: {err}"))?;
+        let kept = purge_entries(store, legacy, cutoff, &mut purged)?;
+        if !kept.is_empty() {
+            register_in_index(store, today, kept)?;
+        }
+        store.delete(CONVERSATION_INDEX_KEY)?;
+    }
+
+    let Some(first_day) = read_first_day(store)? else {
+        return Ok(PurgeReport {
+            purged,
+            remaining: 0,
+        });
+    };
+    let mut remaining = 0;
+    let mut oldest_kept = None;
+    for day in first_day..=today.max(first_day) {
+        for key in index_bucket_keys(day) {
+            let entries = read_index(store, &key)?;
+            if entries.is_empty() {
+                continue;
+            }
+            let count = entries.len();
+            let kept = purge_entries(store, entries, cutoff, &mut purged)?;
+            if kept.is_empty() {
+                store.delete(&key)?;
+                continue;
+            }
+            if kept.len() != count {
+                write_index(store, &key, &kept)?;
+            }
+            remaining += kept.len();
+            oldest_kept.get_or_insert(day);
+        }
+    }
+    match oldest_kept {
+        Some(day) => write_first_day(store, day)?,
+        None => store.delete(INDEX_FIRST_DAY_KEY)?,
+    }
+    Ok(PurgeReport { purged, remaining })
+}
+
+/// Calls `visit` with the key of every record reachable from the conversation
+/// index: the index itself, conversation headers, history pages and uploads.
+/// Keys may name records that no longer exist.
+pub fn visit_record_keys<S, F>(store: &mut S, now_ms: i64, mut visit: F) -> Result<(), String>
+where
+    S: StateStore,
+    F: FnMut(&mut S, &str) -> Result<(), String>,
+{
+    let mut buckets = vec![CONVERSATION_INDEX_KEY.to_string()];
+    visit(store, INDEX_FIRST_DAY_KEY)?;
+    if let Some(first_day) = read_first_day(store)? {
+        buckets.extend((first_day..=index_day(now_ms).max(first_day)).flat_map(index_bucket_keys));
+    }
+    for bucket in buckets {
+        visit(store, &bucket)?;
+        for entry in read_index(store, &bucket)? {
+            let key = conversation_key(&entry.ctx, &entry.conversation_id);
+            visit(store, &key)?;
+            let Some(conversation) = load_conversation(store, &key)? else {
+                continue;
+            };
+            let last_page = conversation.next_watermark.saturating_sub(1) / PAGE_SIZE;
+            for page in conversation.first_watermark / PAGE_SIZE..=last_page {
+                visit(store, &page_key(&key, page))?;
+            }
+            for blob in &conversation.blobs {
+                visit(store, &blob_key(&entry.ctx, &entry.conversation_id, blob))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the idle conversations among `entries` and returns the others.
+fn purge_entries<S: StateStore>(
+    store: &mut S,
+    entries: Vec<IndexEntry>,
+    cutoff: i64,
+    purged: &mut usize,
+) -> Result<Vec<IndexEntry>, String> {
+    let mut kept = Vec::new();
+    for entry in entries {
+        let key = conversation_key(&entry.ctx, &entry.conversation_id);
+        let Some(conversation) = load_conversation(store, &key)? else {
+            continue;
+        };
+        if conversation.last_active > cutoff {
+            kept.push(entry);
+            continue;
+        }
+        let last_page = conversation.next_watermark.saturating_sub(1) / PAGE_SIZE;
+        for page in conversation.first_watermark / PAGE_SIZE..=last_page {
+            store.delete(&page_key(&key, page))?;
+        }
+        for blob in &conversation.blobs {
+            store.delete(&blob_key(&entry.ctx, &entry.conversation_id, blob))?;
+        }
+        store.delete(&key)?;
+        *purged += 1;
+    }
+    Ok(kept)
+}
+
 pub fn sanitize_team(team: Option<&str>) -> String {
     team.map(|t| t.trim().to_string())
         .filter(|s| !s.is_empty())
         .unwrap_or_else(|| "_".to_string())
 }
 
+fn migrate_inline_history<S: StateStore>(
+    store: &mut S,
+    key: &str,
+    conversation: &mut ConversationState,
+) -> Result<(), String> {
+    if conversation.activities.is_empty() {
+        return Ok(());
+    }
+    let legacy = std::mem::take(&mut conversation.activities);
+    conversation.first_timestamp = legacy[0].timestamp;
+    conversation.first_watermark = legacy[0].watermark;
+    let mut pages: Vec<(u64, Vec<StoredActivity>)> = Vec::new();
+    for activity in legacy {
+        let page = activity.watermark / PAGE_SIZE;
+        match pages.last_mut() {
+            Some((current, items)) if *current == page => items.push(activity),
+            _ => pages.push((page, vec![activity])),
+        }
+    }
+    for (page, items) in pages {
+        write_page(store, key, page, &items)?;
+    }
+    Ok(())
+}
+
+/// Advances `first_watermark` past activities beyond the count or age limit,
+/// deletes pages that no longer hold retained activities and the blobs
+/// uploaded with dropped activities.
+fn apply_retention<S: StateStore>(
+    store: &mut S,
+    key: &str,
+    conversation: &mut ConversationState,
+    now_ms: i64,
+) -> Result<(), String> {
+    let old_first = conversation.first_watermark;
+    let retention = conversation.retention;
+    let mut first = old_first.max(
+        conversation
+            .next_watermark
+            .saturating_sub(retention.max_activities),
+    );
+    let cutoff = now_ms.saturating_sub(retention.max_age_seconds.saturating_mul(1000));
+    let mut first_timestamp = if first == old_first {
+        Some(conversation.first_timestamp)
+    } else {
+        None
+    };
+    // Only read pages when the oldest retained activity may be too old.
+    while first < conversation.next_watermark && first_timestamp.is_none_or(|ts| ts < cutoff) {
+        let page = first / PAGE_SIZE;
+        let retained = read_page(store, key, page)?
+            .into_iter()
+            .find(|activity| activity.watermark >= first && activity.timestamp >= cutoff);
+        match retained {
+            Some(activity) => {
+                first = activity.watermark;
+                first_timestamp = Some(activity.timestamp);
+            }
+            None => {
+                first = ((page + 1) * PAGE_SIZE).min(conversation.next_watermark);
+                first_timestamp = None;
+            }
+        }
+    }
+    if first > old_first && !conversation.blobs.is_empty() {
+        let mut dropped = Vec::new();
+        for page in old_first / PAGE_SIZE..=(first - 1) / PAGE_SIZE {
+            for activity in read_page(store, key, page)? {
+                if (old_first..first).contains(&activity.watermark) {
+                    dropped.extend(activity.blobs);
+                }
+            }
+        }
+        for blob in &dropped {
+            store.delete(&conversation_blob_key(key, blob))?;
+        }
+        conversation.blobs.retain(|blob| !dropped.contains(blob));
+    }
+    for page in old_first / PAGE_SIZE..first / PAGE_SIZE {
+        store.delete(&page_key(key, page))?;
+    }
+    conversation.first_watermark = first;
+    conversation.first_timestamp = first_timestamp.unwrap_or(now_ms);
+    Ok(())
+}
+
+fn read_page<S: StateStore>(
+    store: &mut S,
+    key: &str,
+    page: u64,
+) -> Result<Vec<StoredActivity>, String> {
+    match store.read(&page_key(key, page))? {
+        Some(bytes) => {
+            serde_json::from_slice(&bytes).map_err(|err| format!("invalid history page: {err}"))
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+fn write_page<S: StateStore>(
+    store: &mut S,
+    key: &str,
+    page: u64,
+    activities: &[StoredActivity],
+) -> Result<(), String> {
+    let bytes =
+        serde_json::to_vec(activities).map_err(|err| format!("serialize history page: {err}"))?;
+    store.write(&page_key(key, page), &bytes)
+}
+
+fn index_day(timestamp_ms: i64) -> i64 {
+    timestamp_ms.div_euclid(DAY_MS)
+}
+
+fn index_shard(conversation_id: &str) -> u8 {
+    Sha256::digest(conversation_id.as_bytes())[0] % INDEX_SHARDS
+}
+
+fn index_bucket_key(day: i64, shard: u8) -> String {
+    format!("{CONVERSATION_INDEX_KEY}:{day}:{shard}")
+}
+
+fn index_bucket_keys(day: i64) -> impl Iterator<Item = String> {
+    (0..INDEX_SHARDS).map(move |shard| index_bucket_key(day, shard))
+}
+
+/// Adds entries to their shard buckets of `day`, moving the first-day marker
+/// back when the day is older than any indexed so far.
+fn register_in_index<S: StateStore>(
+    store: &mut S,
+    day: i64,
+    entries: Vec<IndexEntry>,
+) -> Result<(), String> {
+    let mut shards: BTreeMap<u8, Vec<IndexEntry>> = BTreeMap::new();
+    for entry in entries {
+        shards
+            .entry(index_shard(&entry.conversation_id))
+            .or_default()
+            .push(entry);
+    }
+    for (shard, entries) in shards {
+        let key = index_bucket_key(day, shard);
+        let mut bucket = read_index(store, &key)?;
+        bucket.extend(entries);
+        write_index(store, &key, &bucket)?;
+    }
+    if read_first_day(store)?.is_none_or(|first| first > day) {
+        write_first_day(store, day)?;
+    }
+    Ok(())
+}
+
+fn read_index<S: StateStore>(store: &mut S, key: &str) -> Result<Vec<IndexEntry>, String> {
+    match store.read(key)? {
+        Some(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|err| format!("invalid conversation index: {err}")),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn write_index<S: StateStore>(
+    store: &mut S,
+    key: &str,
+    index: &[IndexEntry],
+) -> Result<(), String> {
+    let bytes =
+        serde_json::to_vec(index).map_err(|err| format!("serialize conversation index: {err}"))?;
+    store.write(key, &bytes)
+}
+
+fn read_first_day<S: StateStore>(store: &mut S) -> Result<Option<i64>, String> {
+    match store.read(INDEX_FIRST_DAY_KEY)? {
+        Some(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|err| format!("invalid conversation index marker: {err}")),
+        None => Ok(None),
+    }
+}
+
+fn write_first_day<S: StateStore>(store: &mut S, day: i64) -> Result<(), String> {
+    store.write(INDEX_FIRST_DAY_KEY, day.to_string().as_bytes())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn conversation_key_includes_parts() {
@@ -92,4 +660,284 @@ mod tests {
         assert_eq!(first, 0);
         assert_eq!(state.next_watermark, 1);
     }
+
+    struct MapStore(HashMap<String, Vec<u8>>);
+
+    impl StateStore for MapStore {
+        fn read(&mut self, key: &str) -> Result<Option<Vec<u8>>, String> {
+            Ok(self.0.get(key).cloned())
+        }
+
+        fn write(&mut self, key: &str, value: &[u8]) -> Result<(), String> {
+            self.0.insert(key.to_string(), value.to_vec());
+            Ok(())
+        }
+
+        fn delete(&mut self, key: &str) -> Result<(), String> {
+            self.0.remove(key);
+            Ok(())
+        }
+    }
+
+    fn ctx() -> DirectLineContext {
+        DirectLineContext {
+            env: "env".into(),
+            tenant: "tenant".into(),
+            team: None,
+        }
+    }
+
+    fn user_activity(timestamp: i64) -> StoredActivity {
+        StoredActivity {
+            id: Uuid::new_v4().to_string(),
+            type_: "message".into(),
+            text: Some("hi".into()),
+            from: Some("alice".into()),
+            timestamp,
+            watermark: 0,
+            raw: Value::Null,
+            blobs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn bot_activity_takes_next_watermark() {
+        let ctx = ctx();
+        let mut store = MapStore(HashMap::new());
+        assert!(append_bot_activity(&mut store, &ctx, "conv-1", json!({"text": "hi"})).is_err());
+
+        let mut conversation =
+            create_conversation(&mut store, &ctx, "conv-1", Retention::default()).unwrap();
+        let key = conversation_key(&ctx, "conv-1");
+        append_activity(
+            &mut store,
+            &key,
+            &mut conversation,
+            user_activity(Utc::now().timestamp_millis()),
+        )
+        .unwrap();
+        let activity =
+            append_bot_activity(&mut store, &ctx, "conv-1", json!({"text": "hi"})).unwrap();
+        assert_eq!(activity.watermark, 1);
+        assert_eq!(activity.from.as_deref(), Some(BOT_ID));
+        let stored = load_conversation(&mut store, &key).unwrap().unwrap();
+        assert_eq!(stored.next_watermark, 2);
+        let (activities, cursor) = read_activities(&mut store, &key, &stored, None, 10).unwrap();
+        assert_eq!(activities.len(), 2);
+        assert_eq!(activities[1], activity);
+        assert_eq!(cursor, 2);
+    }
+
+    #[test]
+    fn history_is_paged_capped_and_paginated() {
+        let ctx = ctx();
+        let mut store = MapStore(HashMap::new());
+        let retention = Retention {
+            max_activities: 60,
+            max_age_seconds: DEFAULT_MAX_AGE_SECONDS,
+        };
+        let mut conversation = create_conversation(&mut store, &ctx, "c", retention).unwrap();
+        let key = conversation_key(&ctx, "c");
+        let now = Utc::now().timestamp_millis();
+        for _ in 0..120 {
+            append_activity(&mut store, &key, &mut conversation, user_activity(now)).unwrap();
+        }
+        assert_eq!(conversation.first_watermark, 60);
+        assert!(!store.0.contains_key(&page_key(&key, 0)));
+        assert!(store.0.contains_key(&page_key(&key, 1)));
+        assert!(store.0.contains_key(&page_key(&key, 2)));
+
+        let (first, cursor) = read_activities(&mut store, &key, &conversation, None, 25).unwrap();
+        assert_eq!(first.len(), 25);
+        assert_eq!(first[0].watermark, 60);
+        assert_eq!(cursor, 85);
+        let (rest, cursor) =
+            read_activities(&mut store, &key, &conversation, Some(cursor), 100).unwrap();
+        assert_eq!(rest.len(), 35);
+        assert_eq!(cursor, 120);
+    }
+
+    #[test]
+    fn history_drops_activities_past_max_age() {
+        let ctx = ctx();
+        let mut store = MapStore(HashMap::new());
+        let retention = Retention {
+            max_activities: DEFAULT_MAX_ACTIVITIES,
+            max_age_seconds: 60,
+        };
+        let mut conversation = create_conversation(&mut store, &ctx, "c", retention).unwrap();
+        let key = conversation_key(&ctx, "c");
+        let now = Utc::now().timestamp_millis();
+        for _ in 0..3 {
+            append_activity(
+                &mut store,
+                &key,
+                &mut conversation,
+                user_activity(now - 120_000),
+            )
+            .unwrap();
+        }
+        append_activity(&mut store, &key, &mut conversation, user_activity(now)).unwrap();
+        assert_eq!(conversation.first_watermark, 3);
+        let (activities, _) = read_activities(&mut store, &key, &conversation, None, 10).unwrap();
+        assert_eq!(activities.len(), 1);
+    }
+
+    #[test]
+    fn purge_removes_idle_conversations() {
+        let ctx = ctx();
+        let mut store = MapStore(HashMap::new());
+        let now = Utc::now().timestamp_millis();
+        let mut idle = create_conversation(&mut store, &ctx, "idle", Retention::default()).unwrap();
+        let idle_key = conversation_key(&ctx, "idle");
+        append_activity(
+            &mut store,
+            &idle_key,
+            &mut idle,
+            user_activity(now - 7_200_000),
+        )
+        .unwrap();
+        idle.blobs.push("blob-1".into());
+        save_conversation(&mut store, &idle_key, &idle).unwrap();
+        store
+            .0
+            .insert(blob_key(&ctx, "idle", "blob-1"), b"{}".to_vec());
+        create_conversation(&mut store, &ctx, "fresh", Retention::default()).unwrap();
+
+        let report = purge_idle_conversations(&mut store, now, 3600).unwrap();
+        assert_eq!(
+            report,
+            PurgeReport {
+                purged: 1,
+                remaining: 1
+            }
+        );
+        assert!(!store.0.contains_key(&idle_key));
+        assert!(!store.0.contains_key(&page_key(&idle_key, 0)));
+        assert!(!store.0.contains_key(&blob_key(&ctx, "idle", "blob-1")));
+        assert!(store.0.contains_key(&conversation_key(&ctx, "fresh")));
+    }
+
+    #[test]
+    fn purge_walks_day_buckets_and_the_legacy_index() {
+        let ctx = ctx();
+        let mut store = MapStore(HashMap::new());
+        let now = Utc::now().timestamp_millis();
+        let old = now - 10 * DAY_MS;
+        for (id, created) in [("old", old), ("legacy", old)] {
+            let mut conversation = ConversationState::new(ctx.clone());
+            conversation.last_active = created;
+            save_conversation(&mut store, &conversation_key(&ctx, id), &conversation).unwrap();
+        }
+        register_in_index(
+            &mut store,
+            index_day(old),
+            vec![IndexEntry {
+                ctx: ctx.clone(),
+                conversation_id: "old".into(),
+            }],
+        )
+        .unwrap();
+        let legacy = vec![IndexEntry {
+            ctx: ctx.clone(),
+            conversation_id: "legacy".into(),
+        }];
+        store.0.insert(
+            CONVERSATION_INDEX_KEY.to_string(),
+            serde_json::to_vec(&legacy).unwrap(),
+        );
+        create_conversation(&mut store, &ctx, "fresh", Retention::default()).unwrap();
+        assert_eq!(read_first_day(&mut store).unwrap(), Some(index_day(old)));
+        let fresh_bucket = index_bucket_key(index_day(now), index_shard("fresh"));
+        assert_eq!(read_index(&mut store, &fresh_bucket).unwrap().len(), 1);
+
+        let report = purge_idle_conversations(&mut store, now, 3600).unwrap();
+        assert_eq!(
+            report,
+            PurgeReport {
+                purged: 2,
+                remaining: 1
+            }
+        );
+        assert!(!store.0.contains_key(CONVERSATION_INDEX_KEY));
+        assert!(index_bucket_keys(index_day(old)).all(|bucket| !store.0.contains_key(&bucket)));
+        assert!(!store.0.contains_key(&conversation_key(&ctx, "old")));
+        assert!(!store.0.contains_key(&conversation_key(&ctx, "legacy")));
+        assert_eq!(read_first_day(&mut store).unwrap(), Some(index_day(now)));
+
+        let report = purge_idle_conversations(&mut store, now + 2 * DAY_MS, 3600).unwrap();
+        assert_eq!(report.remaining, 0);
+        assert!(!store.0.contains_key(INDEX_FIRST_DAY_KEY));
+    }
+
+    #[test]
+    fn conversations_created_together_spread_over_index_shards() {
+        let ctx = ctx();
+        let mut store = MapStore(HashMap::new());
+        let ids: Vec<String> = (0..32).map(|n| format!("conv-{n}")).collect();
+        for id in &ids {
+            create_conversation(&mut store, &ctx, id, Retention::default()).unwrap();
+        }
+        let today = index_day(Utc::now().timestamp_millis());
+        let buckets: Vec<Vec<IndexEntry>> = index_bucket_keys(today)
+            .map(|key| read_index(&mut store, &key).unwrap())
+            .filter(|entries| !entries.is_empty())
+            .collect();
+        assert!(buckets.len() > 1);
+        assert_eq!(buckets.iter().map(Vec::len).sum::<usize>(), ids.len());
+        for id in &ids {
+            let bucket = read_index(&mut store, &index_bucket_key(today, index_shard(id))).unwrap();
+            assert!(bucket.iter().any(|entry| &entry.conversation_id == id));
+        }
+    }
+
+    #[test]
+    fn retention_deletes_blobs_of_dropped_activities() {
+        let ctx = ctx();
+        let mut store = MapStore(HashMap::new());
+        let retention = Retention {
+            max_activities: 2,
+            max_age_seconds: DEFAULT_MAX_AGE_SECONDS,
+        };
+        let mut conversation = create_conversation(&mut store, &ctx, "c", retention).unwrap();
+        let key = conversation_key(&ctx, "c");
+        let now = Utc::now().timestamp_millis();
+        for blob in ["b0", "b1"] {
+            store.0.insert(blob_key(&ctx, "c", blob), b"{}".to_vec());
+            conversation.blobs.push(blob.into());
+            let mut activity = user_activity(now);
+            activity.blobs.push(blob.into());
+            append_activity(&mut store, &key, &mut conversation, activity).unwrap();
+        }
+        append_activity(&mut store, &key, &mut conversation, user_activity(now)).unwrap();
+
+        assert_eq!(conversation.first_watermark, 1);
+        assert!(!store.0.contains_key(&blob_key(&ctx, "c", "b0")));
+        assert!(store.0.contains_key(&blob_key(&ctx, "c", "b1")));
+        assert_eq!(conversation.blobs, vec!["b1".to_string()]);
+    }
+
+    #[test]
+    fn inline_history_moves_into_pages() {
+        let ctx = ctx();
+        let mut store = MapStore(HashMap::new());
+        let key = conversation_key(&ctx, "old");
+        let mut conversation = ConversationState::new(ctx.clone());
+        let mut legacy = user_activity(Utc::now().timestamp_millis());
+        legacy.watermark = conversation.bump_watermark();
+        conversation.activities.push(legacy.clone());
+        append_activity(
+            &mut store,
+            &key,
+            &mut conversation,
+            user_activity(Utc::now().timestamp_millis()),
+        )
+        .unwrap();
+        assert!(conversation.activities.is_empty());
+        let (activities, cursor) =
+            read_activities(&mut store, &key, &conversation, None, 10).unwrap();
+        assert_eq!(activities[0], legacy);
+        assert_eq!(activities.len(), 2);
+        assert_eq!(cursor, 2);
+    }
 }
@@ -7,6 +7,21 @@ use crate::bindings::greentic::state::state_store;
 pub trait StateStore {
     fn read(&mut self, key: &str) -> Result<Option<Vec<u8>>, String>;
     fn write(&mut self, key: &str, value: &[u8]) -> Result<(), String>;
+    fn delete(&mut self, key: &str) -> Result<(), String>;
+}
+
+impl<S: StateStore + ?Sized> StateStore for &mut S {
+    fn read(&mut self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        (**self).read(key)
+    }
+
+    fn write(&mut self, key: &str, value: &[u8]) -> Result<(), String> {
+        (**self).write(key, value)
+    }
+
+    fn delete(&mut self, key: &str) -> Result<(), String> {
+        (**self).delete(key)
+    }
 }
 
 /// Driver for reading secrets required by the Direct Line contract.
@@ -37,6 +52,12 @@ impl StateStore for HostStateStore {
             .map(|_| ())
             .map_err(|err| format!("state write error: {} - {}", err.code, err.message))
     }
+
+    fn delete(&mut self, key: &str) -> Result<(), String> {
+        state_store::delete(key, None)
+            .map(|_| ())
+            .map_err(|err| format!("state delete error: {} - {}", err.code, err.message))
+    }
 }
 
 /// Host-backed secrets drive implementation.
@@ -51,7 +72,8 @@ impl SecretStore for HostSecretStore {
     }
 }
 
-/// Simple rate-limit record persisted per (env,tenant,team,user) to enforce token generation limits.
+/// Fixed-window rate-limit counter persisted per limited subject (a user's
+/// token requests or posts, or a conversation's posts).
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RateLimitState {
     pub window_start: i64,
@@ -66,15 +88,37 @@ impl RateLimitState {
         }
     }
 
-    pub fn bump(&mut self, now: i64, window_seconds: i64, limit: u32) -> Result<u32, ()> {
-        if now - self.window_start >= window_seconds {
+    /// Counts a request, or returns the seconds until the window resets when
+    /// the limit is already reached.
+    pub fn bump(&mut self, now: i64, window_seconds: i64, limit: u32) -> Result<u32, i64> {
+        if now.saturating_sub(self.window_start) >= window_seconds {
             self.window_start = now;
             self.count = 0;
         }
         if self.count >= limit {
-            return Err(());
+            return Err(self
+                .window_start
+                .saturating_add(window_seconds)
+                .saturating_sub(now)
+                .max(1));
         }
         self.count = self.count.saturating_add(1);
         Ok(self.count)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bump_saturates_at_extreme_timestamps() {
+        let mut state = RateLimitState::new(i64::MAX - 1);
+        assert_eq!(state.bump(i64::MAX - 1, 86_400, 1), Ok(1));
+        assert_eq!(state.bump(i64::MAX, 86_400, 1), Err(1));
+
+        let mut state = RateLimitState::new(i64::MIN);
+        assert_eq!(state.bump(i64::MAX, 86_400, 1), Ok(1));
+        assert_eq!(state.window_start, i64::MAX);
+    }
+}
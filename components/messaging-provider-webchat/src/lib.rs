@@ -1,4 +1,5 @@
 use base64::{Engine as _, engine::general_purpose};
+use chrono::Utc;
 use greentic_types::messaging::universal_dto::{
     EncodeInV1, HttpInV1, HttpOutV1, ProviderPayloadV1, RenderPlanInV1, RenderPlanOutV1,
     SendPayloadInV1, SendPayloadResultV1,
@@ -20,11 +21,15 @@ mod directline;
 
 use bindings::exports::greentic::provider_schema_core::schema_core_api::Guest;
 use bindings::greentic::state::state_store;
+use directline::encryption::{EncryptedStateStore, EncryptionKeys, reseal_conversations};
+use directline::jwt::DirectLineContext;
+use directline::state::{DEFAULT_IDLE_TTL_SECONDS, append_bot_activity, purge_idle_conversations};
 use directline::{HostSecretStore, HostStateStore, handle_directline_request};
 use greentic_types::ProviderManifest;
 
 const PROVIDER_TYPE: &str = "messaging.webchat";
 const CONFIG_SCHEMA_REF: &str = "schemas/messaging/webchat/public.config.schema.json";
+const BOT_ACTIVITY_TYPES: &[&str] = &["message", "typing", "event", "invokeResponse"];
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -35,6 +40,26 @@ struct ProviderConfig {
     tenant_channel_id: Option<String>,
     #[serde(default)]
     public_base_url: Option<String>,
+    #[serde(default)]
+    history_max_activities: Option<u64>,
+    #[serde(default)]
+    history_max_age_seconds: Option<u64>,
+    #[serde(default)]
+    idle_conversation_ttl_seconds: Option<u64>,
+    #[serde(default)]
+    rate_limit_window_seconds: Option<u64>,
+    #[serde(default)]
+    token_rate_limit: Option<u64>,
+    #[serde(default)]
+    activity_rate_limit_per_user: Option<u64>,
+    #[serde(default)]
+    activity_rate_limit_per_conversation: Option<u64>,
+    #[serde(default)]
+    allowed_origins: Option<Vec<String>>,
+    #[serde(default)]
+    stream_retry_ms: Option<u64>,
+    #[serde(default)]
+    state_encryption_required: Option<bool>,
 }
 
 struct Component;
@@ -51,6 +76,7 @@ impl Guest for Component {
                 "render_plan".to_string(),
                 "encode".to_string(),
                 "send_payload".to_string(),
+                "cleanup".to_string(),
             ],
             config_schema_ref: Some(CONFIG_SCHEMA_REF.to_string()),
             state_schema_ref: None,
@@ -81,6 +107,16 @@ impl Guest for Component {
                         "route": cfg.route,
                         "tenant_channel_id": cfg.tenant_channel_id,
                         "public_base_url": cfg.public_base_url,
+                        "history_max_activities": cfg.history_max_activities,
+                        "history_max_age_seconds": cfg.history_max_age_seconds,
+                        "idle_conversation_ttl_seconds": cfg.idle_conversation_ttl_seconds,
+                        "rate_limit_window_seconds": cfg.rate_limit_window_seconds,
+                        "token_rate_limit": cfg.token_rate_limit,
+                        "activity_rate_limit_per_user": cfg.activity_rate_limit_per_user,
+                        "activity_rate_limit_per_conversation": cfg.activity_rate_limit_per_conversation,
+                        "allowed_origins": cfg.allowed_origins,
+                        "stream_retry_ms": cfg.stream_retry_ms,
+                        "state_encryption_required": cfg.state_encryption_required,
                     }
                 }))
             }
@@ -100,6 +136,7 @@ impl Guest for Component {
             "render_plan" => render_plan(&input_json),
             "encode" => encode_op(&input_json),
             "send_payload" => send_payload(&input_json),
+            "cleanup" => handle_cleanup(&input_json),
             other => json_bytes(&json!({"ok": false, "error": format!("unsupported op: {other}")})),
         }
     }
@@ -117,6 +154,10 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
         }
     };
 
+    if let Some((ctx, conversation_id)) = directline_target(&parsed) {
+        return send_to_conversation(&ctx, &conversation_id, &parsed);
+    }
+
     let cfg = match load_config(&parsed) {
         Ok(cfg) => cfg,
         Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
@@ -179,6 +220,161 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
     }))
 }
 
+/// Appends the bot's reply to a Direct Line conversation, where polling Web Chat
+/// clients pick it up.
+fn send_to_conversation(ctx: &DirectLineContext, conversation_id: &str, input: &Value) -> Vec<u8> {
+    let activity = match bot_activity(input) {
+        Ok(activity) => activity,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let result = directline_state(input)
+        .and_then(|mut store| append_bot_activity(&mut store, ctx, conversation_id, activity));
+    match result {
+        Ok(activity) => json_bytes(&json!({
+            "ok": true,
+            "status": "sent",
+            "provider_type": PROVIDER_TYPE,
+            "message_id": activity.id,
+            "provider_message_id": format!("webchat:{conversation_id}:{}", activity.id),
+            "conversation_id": conversation_id,
+            "watermark": activity.watermark.to_string(),
+        })),
+        Err(err) => json_bytes(&json!({"ok": false, "error": err})),
+    }
+}
+
+/// Bot activity for a Direct Line send: a `message` (the default), `typing`,
+/// `event` or `invokeResponse`. Messages carry `text`, card `attachments` and
+/// `suggested_actions`, given either as a list of strings (rendered as `imBack`
+/// buttons) or as a Bot Framework `suggestedActions` object.
+fn bot_activity(value: &Value) -> Result<Value, String> {
+    let kind = value_as_trimmed_string(value.get("type")).unwrap_or_else(|| "message".into());
+    if !BOT_ACTIVITY_TYPES.contains(&kind.as_str()) {
+        return Err(format!("unsupported activity type {kind}"));
+    }
+    let mut activity = json!({"type": kind});
+    let text = extract_text(value);
+    if !text.is_empty() {
+        activity["text"] = Value::String(text);
+    }
+    if let Some(attachments) = value.get("attachments").and_then(Value::as_array) {
+        activity["attachments"] = Value::Array(attachments.clone());
+    }
+    match value
+        .get("suggested_actions")
+        .or_else(|| value.get("suggestedActions"))
+    {
+        Some(Value::Array(items)) => {
+            let actions: Vec<Value> = items
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|title| json!({"type": "imBack", "title": title, "value": title}))
+                .collect();
+            activity["suggestedActions"] = json!({"actions": actions});
+        }
+        Some(actions @ Value::Object(_)) => activity["suggestedActions"] = actions.clone(),
+        _ => {}
+    }
+    for (key, target) in [
+        ("name", "name"),
+        ("value", "value"),
+        ("reply_to_id", "replyToId"),
+        ("replyToId", "replyToId"),
+    ] {
+        if let Some(field) = value.get(key).filter(|field| !field.is_null()) {
+            activity[target] = field.clone();
+        }
+    }
+    if kind == "message"
+        && activity.get("text").is_none()
+        && activity.get("attachments").is_none()
+        && activity.get("suggestedActions").is_none()
+    {
+        return Err("text required".into());
+    }
+    if kind == "event" && activity.get("name").is_none() {
+        return Err("event name required".into());
+    }
+    if kind == "invokeResponse"
+        && activity
+            .get("value")
+            .and_then(|value| value.get("status"))
+            .and_then(Value::as_u64)
+            .is_none()
+    {
+        return Err("invokeResponse value requires a status".into());
+    }
+    Ok(activity)
+}
+
+/// Direct Line conversation addressed by `conversation_id`, with the `env`,
+/// `tenant` and `team` the conversation was created under.
+fn directline_target(value: &Value) -> Option<(DirectLineContext, String)> {
+    let conversation_id = value_as_trimmed_string(value.get("conversation_id"))
+        .or_else(|| value_as_trimmed_string(value.get("conversationId")))?;
+    Some((directline_context(value), conversation_id))
+}
+
+/// Host state store for Direct Line conversations, encrypting records when
+/// `state_encryption_key` is set and refusing plaintext when the config in
+/// `input` sets `state_encryption_required` (as `ingest_http` does).
+fn directline_state(input: &Value) -> Result<EncryptedStateStore<HostStateStore>, String> {
+    let keys = EncryptionKeys::from_secrets(&HostSecretStore)?;
+    let required = load_config(input)
+        .ok()
+        .and_then(|cfg| cfg.state_encryption_required)
+        .unwrap_or(false);
+    Ok(EncryptedStateStore::new(HostStateStore, keys).require_encryption(required))
+}
+
+/// `env`, `tenant` and `team` of a Direct Line conversation, defaulting the
+/// first two to `default`.
+fn directline_context(value: &Value) -> DirectLineContext {
+    DirectLineContext {
+        env: value_as_trimmed_string(value.get("env")).unwrap_or_else(|| "default".to_string()),
+        tenant: value_as_trimmed_string(value.get("tenant"))
+            .unwrap_or_else(|| "default".to_string()),
+        team: value_as_trimmed_string(value.get("team")),
+    }
+}
+
+/// Purges Direct Line conversations idle for longer than `idle_ttl_seconds`
+/// (else the configured `idle_conversation_ttl_seconds`), with their history
+/// pages and uploads, then moves the remaining conversations' records to the
+/// current encryption key. Meant to be run on a schedule.
+fn handle_cleanup(input_json: &[u8]) -> Vec<u8> {
+    let parsed: Value = match serde_json::from_slice(input_json) {
+        Ok(val) => val,
+        Err(err) => {
+            return json_bytes(&json!({"ok": false, "error": format!("invalid json: {err}")}));
+        }
+    };
+    let configured = load_config(&parsed)
+        .ok()
+        .and_then(|cfg| cfg.idle_conversation_ttl_seconds);
+    let ttl = parsed
+        .get("idle_ttl_seconds")
+        .and_then(Value::as_u64)
+        .or(configured)
+        .map(|ttl| ttl.min(i64::MAX as u64) as i64)
+        .unwrap_or(DEFAULT_IDLE_TTL_SECONDS);
+    let result = directline_state(&parsed).and_then(|mut store| {
+        let now = Utc::now().timestamp_millis();
+        let report = purge_idle_conversations(&mut store, now, ttl)?;
+        let resealed = reseal_conversations(&mut store, now)?;
+        Ok((report, resealed))
+    });
+    match result {
+        Ok((report, resealed)) => json_bytes(&json!({
+            "ok": true,
+            "purged": report.purged,
+            "remaining": report.remaining,
+            "resealed": resealed,
+        })),
+        Err(err) => json_bytes(&json!({"ok": false, "error": err})),
+    }
+}
+
 fn handle_ingest(input_json: &[u8]) -> Vec<u8> {
     let parsed: Value = match serde_json::from_slice(input_json) {
         Ok(val) => val,
@@ -274,6 +470,43 @@ fn render_plan(input_json: &[u8]) -> Vec<u8> {
     json_bytes(&json!({"ok": true, "plan": plan_out}))
 }
 
+/// The reply is a `message` unless the outbound envelope asks otherwise with
+/// `reply_type`: `typing`, `event` (named by `reply_name`, carrying the JSON
+/// `reply_value`) or `invokeResponse` (`{"status": reply_status, "body":
+/// reply_value}`, status 200 by default). The inbound activity's own type,
+/// `name` and `value` are never copied into the reply.
+fn apply_reply_type(metadata: &MessageMetadata, payload_body: &mut Value) {
+    let json_value = |key: &str| {
+        metadata
+            .get(key)
+            .map(|raw| serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.clone())))
+    };
+    match metadata.get("reply_type").map(|kind| kind.trim()) {
+        Some("typing") => payload_body["type"] = json!("typing"),
+        Some("event") => {
+            payload_body["type"] = json!("event");
+            if let Some(name) = metadata.get("reply_name") {
+                payload_body["name"] = Value::String(name.clone());
+            }
+            if let Some(value) = json_value("reply_value") {
+                payload_body["value"] = value;
+            }
+        }
+        Some("invokeResponse") => {
+            let status = metadata
+                .get("reply_status")
+                .and_then(|status| status.trim().parse::<u16>().ok())
+                .unwrap_or(200);
+            payload_body["type"] = json!("invokeResponse");
+            payload_body["value"] = json!({
+                "status": status,
+                "body": json_value("reply_value").unwrap_or(Value::Null),
+            });
+        }
+        _ => {}
+    }
+}
+
 fn encode_op(input_json: &[u8]) -> Vec<u8> {
     let encode_in = match serde_json::from_slice::<EncodeInV1>(input_json) {
         Ok(value) => value,
@@ -290,11 +523,46 @@ fn encode_op(input_json: &[u8]) -> Vec<u8> {
         .clone()
         .or_else(|| Some(encode_in.message.session_id.clone()));
     let route_value = route.clone().unwrap_or_else(|| "webchat".to_string());
-    let payload_body = json!({
+    let mut payload_body = json!({
         "text": text,
         "route": route_value.clone(),
         "session_id": encode_in.message.session_id,
     });
+    // Messages from a Direct Line conversation are answered in that conversation.
+    let metadata_in = &encode_in.message.metadata;
+    if let Some(conversation_id) = metadata_in.get("conversation_id") {
+        payload_body["conversation_id"] = Value::String(conversation_id.clone());
+        for key in ["env", "tenant", "team"] {
+            if let Some(value) = metadata_in.get(key) {
+                payload_body[key] = Value::String(value.clone());
+            }
+        }
+        if let Some(activity_id) = metadata_in.get("activity_id") {
+            payload_body["reply_to_id"] = Value::String(activity_id.clone());
+        }
+        if let Some(raw) = metadata_in.get("suggested_actions") {
+            payload_body["suggested_actions"] =
+                serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.clone()));
+        }
+        apply_reply_type(metadata_in, &mut payload_body);
+        if payload_body.get("type").is_some() {
+            payload_body["text"] = json!(encode_in.message.text);
+        }
+        if !encode_in.message.attachments.is_empty() {
+            payload_body["attachments"] = encode_in
+                .message
+                .attachments
+                .iter()
+                .map(|attachment| {
+                    json!({
+                        "contentType": attachment.mime_type,
+                        "contentUrl": attachment.url,
+                        "name": attachment.name,
+                    })
+                })
+                .collect();
+        }
+    }
     let body_bytes = serde_json::to_vec(&payload_body).unwrap_or_else(|_| b"{}".to_vec());
     let mut metadata = BTreeMap::new();
     metadata.insert("route".to_string(), Value::String(route_value.clone()));
@@ -331,6 +599,11 @@ fn send_payload(input_json: &[u8]) -> Vec<u8> {
 }
 
 fn persist_send_payload(payload: &Value) -> Result<(), String> {
+    if let Some((ctx, conversation_id)) = directline_target(payload) {
+        let activity = bot_activity(payload)?;
+        let mut store = directline_state(payload)?;
+        return append_bot_activity(&mut store, &ctx, &conversation_id, activity).map(|_| ());
+    }
     let route = route_from_value(payload);
     let tenant_channel_id = tenant_channel_from_value(payload);
     let key = route
@@ -485,7 +758,21 @@ fn load_config(input: &Value) -> Result<ProviderConfig, String> {
         return parse_config_value(cfg);
     }
     let mut partial = serde_json::Map::new();
-    for key in ["route", "tenant_channel_id", "public_base_url"] {
+    for key in [
+        "route",
+        "tenant_channel_id",
+        "public_base_url",
+        "history_max_activities",
+        "history_max_age_seconds",
+        "idle_conversation_ttl_seconds",
+        "rate_limit_window_seconds",
+        "token_rate_limit",
+        "activity_rate_limit_per_user",
+        "activity_rate_limit_per_conversation",
+        "allowed_origins",
+        "stream_retry_ms",
+        "state_encryption_required",
+    ] {
         if let Some(v) = input.get(key) {
             partial.insert(key.to_string(), v.clone());
         }
@@ -550,6 +837,65 @@ mod tests {
         assert_eq!(cfg.public_base_url.as_deref(), Some("https://example.com"));
     }
 
+    #[test]
+    fn bot_activity_renders_typing_and_suggested_actions() {
+        let typing = bot_activity(&json!({"type": "typing"})).unwrap();
+        assert_eq!(typing, json!({"type": "typing"}));
+        let message = bot_activity(&json!({
+            "text": "Pick one",
+            "suggested_actions": ["Yes", "No"],
+            "reply_to_id": "a1",
+        }))
+        .unwrap();
+        assert_eq!(message["suggestedActions"]["actions"][1]["type"], "imBack");
+        assert_eq!(message["suggestedActions"]["actions"][1]["value"], "No");
+        assert_eq!(message["replyToId"], "a1");
+        let card = bot_activity(&json!({
+            "attachments": [{"contentType": "application/vnd.microsoft.card.adaptive", "content": {}}]
+        }))
+        .unwrap();
+        assert_eq!(card["type"], "message");
+        assert!(bot_activity(&json!({"type": "message"})).is_err());
+        assert!(bot_activity(&json!({"type": "invoke", "text": "x"})).is_err());
+        assert!(bot_activity(&json!({"type": "invokeResponse", "value": {"a": 1}})).is_err());
+        let response = bot_activity(&json!({
+            "type": "invokeResponse",
+            "value": {"status": 200, "body": {}},
+        }))
+        .unwrap();
+        assert_eq!(response["value"]["status"], 200);
+    }
+
+    #[test]
+    fn replies_are_messages_unless_the_outbound_envelope_asks() {
+        let mut inbound = MessageMetadata::new();
+        inbound.insert("activity_type".into(), "event".into());
+        inbound.insert("name".into(), "webchat/join".into());
+        inbound.insert("value".into(), r#"{"locale":"en"}"#.into());
+        let mut body = json!({"text": "hi"});
+        apply_reply_type(&inbound, &mut body);
+        assert_eq!(body, json!({"text": "hi"}));
+
+        let mut invoke = inbound.clone();
+        invoke.insert("activity_type".into(), "invoke".into());
+        invoke.insert("reply_type".into(), "invokeResponse".into());
+        invoke.insert("reply_value".into(), r#"{"ok":true}"#.into());
+        let mut body = json!({"text": "hi"});
+        apply_reply_type(&invoke, &mut body);
+        assert_eq!(body["type"], "invokeResponse");
+        assert_eq!(body["value"], json!({"status": 200, "body": {"ok": true}}));
+
+        let mut event = inbound;
+        event.insert("reply_type".into(), "event".into());
+        event.insert("reply_name".into(), "bot/ready".into());
+        let mut body = json!({"text": "hi"});
+        apply_reply_type(&event, &mut body);
+        assert_eq!(
+            body,
+            json!({"text": "hi", "type": "event", "name": "bot/ready"})
+        );
+    }
+
     #[test]
     fn parse_config_rejects_unknown() {
         let cfg = br#"{"route":"r","public_base_url":"https://example.com","extra":true}"#;
@@ -0,0 +1,198 @@
+use super::bindings::greentic::http::client;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use greentic_types::Attachment;
+use serde_json::{Map, Value};
+
+/// Webex rejects uploads larger than 100MB.
+pub(crate) const MAX_UPLOAD_BYTES: usize = 100 * 1024 * 1024;
+const MULTIPART_BOUNDARY: &str = "greentic-webex-file-boundary-3b9e7a41";
+
+/// Where an outbound attachment's bytes come from.
+#[derive(Debug, PartialEq)]
+pub(crate) enum FileSource {
+    /// Publicly reachable URL that Webex downloads itself.
+    Url(String),
+    /// Inline bytes (from a `data:` URL) uploaded as multipart/form-data.
+    Inline(Vec<u8>),
+}
+
+pub(crate) fn file_source(attachment: &Attachment) -> Result<FileSource, String> {
+    let url = attachment.url.trim();
+    if url.starts_with("https://") || url.starts_with("http://") {
+        return Ok(FileSource::Url(url.to_string()));
+    }
+    if let Some(data) = url.strip_prefix("data:") {
+        let (header, encoded) = data
+            .split_once(',')
+            .ok_or_else(|| "invalid data url".to_string())?;
+        if !header.ends_with(";base64") {
+            return Err("data url must be base64 encoded".into());
+        }
+        let bytes = STANDARD
+            .decode(encoded)
+            .map_err(|err| format!("invalid data url: {err}"))?;
+        if bytes.len() > MAX_UPLOAD_BYTES {
+            return Err(format!(
+                "attachment {} exceeds the 100MB Webex upload limit",
+                attachment.name.as_deref().unwrap_or("file")
+            ));
+        }
+        return Ok(FileSource::Inline(bytes));
+    }
+    Err(format!("unsupported attachment url: {url}"))
+}
+
+/// Posts one message to `/messages`. `fields` carries the destination, text and
+/// `parentId`; a file (if any) is sent as `files` (URL) or uploaded via multipart.
+pub(crate) fn post_message(
+    api_base: &str,
+    token: &str,
+    fields: &Map<String, Value>,
+    file: Option<(&FileSource, &Attachment)>,
+) -> Result<Value, String> {
+    let (content_type, body) = match file {
+        Some((FileSource::Inline(bytes), attachment)) => (
+            format!("multipart/form-data; boundary={MULTIPART_BOUNDARY}"),
+            multipart_body(
+                fields,
+                attachment.name.as_deref().unwrap_or("attachment"),
+                &attachment.mime_type,
+                bytes,
+            )?,
+        ),
+        Some((FileSource::Url(url), _)) => {
+            let mut payload = fields.clone();
+            payload.insert(
+                "files".into(),
+                Value::Array(vec![Value::String(url.clone())]),
+            );
+            ("application/json".to_string(), json_body(&payload))
+        }
+        None => ("application/json".to_string(), json_body(fields)),
+    };
+    let request = client::Request {
+        method: "POST".into(),
+        url: format!("{api_base}/messages"),
+        headers: vec![
+            ("Content-Type".into(), content_type),
+            ("Authorization".into(), format!("Bearer {token}")),
+        ],
+        body: Some(body),
+    };
+    let resp = client::send(&request, None, None)
+        .map_err(|err| format!("transport error: {}", err.message))?;
+    if resp.status < 200 || resp.status >= 300 {
+        return Err(format!("webex returned status {}", resp.status));
+    }
+    Ok(serde_json::from_slice(&resp.body.unwrap_or_default()).unwrap_or(Value::Null))
+}
+
+fn json_body(fields: &Map<String, Value>) -> Vec<u8> {
+    serde_json::to_vec(fields).unwrap_or_else(|_| b"{}".to_vec())
+}
+
+/// Builds the upload body. The MIME type and filename end up in part headers,
+/// so control characters are refused and the filename is percent-encoded
+/// where it could end the quoted string (RFC 7578 §4.2).
+fn multipart_body(
+    fields: &Map<String, Value>,
+    filename: &str,
+    mime_type: &str,
+    bytes: &[u8],
+) -> Result<Vec<u8>, String> {
+    if mime_type.chars().any(|c| c.is_control() || c == '"') || !mime_type.contains('/') {
+        return Err(format!("invalid attachment mime type: {mime_type:?}"));
+    }
+    if filename.chars().any(char::is_control) {
+        return Err("attachment filename contains control characters".into());
+    }
+    let filename = encode_filename(filename);
+    let mut body = Vec::with_capacity(bytes.len() + 512);
+    for (name, value) in fields {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        body.extend_from_slice(
+            format!(
+                "--{MULTIPART_BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "--{MULTIPART_BOUNDARY}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"{filename}\"\r\nContent-Type: {mime_type}\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{MULTIPART_BOUNDARY}--\r\n").as_bytes());
+    Ok(body)
+}
+
+fn encode_filename(filename: &str) -> String {
+    let mut encoded = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        match c {
+            '"' | '\\' | '%' => encoded.push_str(&format!("%{:02X}", c as u32)),
+            _ => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(url: &str) -> Attachment {
+        Attachment {
+            mime_type: "text/plain".into(),
+            url: url.into(),
+            name: Some("notes.txt".into()),
+            size_bytes: None,
+        }
+    }
+
+    #[test]
+    fn file_source_classifies_urls() {
+        assert_eq!(
+            file_source(&attachment("https://cdn.example/a.pdf")).unwrap(),
+            FileSource::Url("https://cdn.example/a.pdf".into())
+        );
+        assert_eq!(
+            file_source(&attachment("data:text/plain;base64,aGk=")).unwrap(),
+            FileSource::Inline(b"hi".to_vec())
+        );
+        assert!(file_source(&attachment("data:text/plain,hi")).is_err());
+        assert!(file_source(&attachment("ftp://nope")).is_err());
+    }
+
+    #[test]
+    fn multipart_body_contains_fields_and_file() {
+        let mut fields = Map::new();
+        fields.insert("roomId".into(), Value::String("room-1".into()));
+        fields.insert("text".into(), Value::String("see attached".into()));
+        let body =
+            String::from_utf8(multipart_body(&fields, "notes.txt", "text/plain", b"hi").unwrap())
+                .unwrap();
+        assert!(body.contains("name=\"roomId\"\r\n\r\nroom-1\r\n"));
+        assert!(body.contains("name=\"text\"\r\n\r\nsee attached\r\n"));
+        assert!(body.contains(
+            "name=\"files\"; filename=\"notes.txt\"\r\nContent-Type: text/plain\r\n\r\nhi\r\n"
+        ));
+        assert!(body.ends_with(&format!("--{MULTIPART_BOUNDARY}--\r\n")));
+    }
+
+    #[test]
+    fn multipart_body_escapes_filename_and_rejects_header_injection() {
+        let fields = Map::new();
+        let body =
+            String::from_utf8(multipart_body(&fields, "q\"1%.txt", "text/plain", b"hi").unwrap())
+                .unwrap();
+        assert!(body.contains("filename=\"q%221%25.txt\"\r\n"));
+        assert!(multipart_body(&fields, "a.txt\r\nX-Evil: 1", "text/plain", b"hi").is_err());
+        assert!(multipart_body(&fields, "a.txt", "text/plain\r\nX-Evil: 1", b"hi").is_err());
+    }
+}
@@ -1,16 +1,19 @@
 use base64::{Engine, engine::general_purpose::STANDARD};
 use greentic_types::messaging::universal_dto::{
-    EncodeInV1, HttpInV1, HttpOutV1, ProviderPayloadV1, RenderPlanInV1, RenderPlanOutV1,
+    EncodeInV1, Header, HttpInV1, HttpOutV1, ProviderPayloadV1, RenderPlanInV1, RenderPlanOutV1,
     SendPayloadInV1, SendPayloadResultV1,
 };
 use greentic_types::{
-    Actor, Attachment, ChannelMessageEnvelope, Destination, EnvId, MessageMetadata, TenantCtx,
-    TenantId,
+    Actor, Attachment, ChannelMessageEnvelope, Destination, EnvId, MessageMetadata, ReplyScope,
+    TenantCtx, TenantId,
 };
 use serde::Deserialize;
 use serde_json::{Value, json};
 use std::collections::BTreeMap;
 
+mod files;
+mod signature;
+
 mod bindings {
     wit_bindgen::generate!({
         path: "wit/messaging-provider-webex",
@@ -28,6 +31,9 @@ const PROVIDER_TYPE: &str = "messaging.webex.bot";
 const CONFIG_SCHEMA_REF: &str = "schemas/messaging/webex/public.config.schema.json";
 const DEFAULT_API_BASE: &str = "https://webexapis.com/v1";
 const DEFAULT_TOKEN_KEY: &str = "WEBEX_BOT_TOKEN";
+const WEBHOOK_SECRET_KEY: &str = "WEBEX_WEBHOOK_SECRET";
+/// Set by `webex-webhook` while it rotates the webhook secret.
+const PREVIOUS_WEBHOOK_SECRET_KEY: &str = "WEBEX_WEBHOOK_SECRET_PREVIOUS";
 
 #[derive(Debug, Deserialize, Default)]
 #[serde(deny_unknown_fields)]
@@ -54,6 +60,8 @@ impl Guest for Component {
                 "render_plan".to_string(),
                 "encode".to_string(),
                 "send_payload".to_string(),
+                "update_message".to_string(),
+                "delete_message".to_string(),
             ],
             config_schema_ref: Some(CONFIG_SCHEMA_REF.to_string()),
             state_schema_ref: None,
@@ -87,6 +95,8 @@ impl Guest for Component {
             "render_plan" => render_plan(&input_json),
             "encode" => encode_op(&input_json),
             "send_payload" => send_payload(&input_json),
+            "update_message" => handle_update_message(&input_json),
+            "delete_message" => handle_delete_message(&input_json),
             other => json_bytes(&json!({"ok": false, "error": format!("unsupported op: {other}")})),
         }
     }
@@ -127,29 +137,38 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
         "webex encoded envelope {}",
         serde_json::to_string(&envelope).unwrap_or_default()
     );
-    if !envelope.attachments.is_empty() {
-        return json_bytes(&json!({"ok": false, "error": "attachments not supported"}));
-    }
-
     let text = envelope
         .text
         .as_ref()
         .map(|value| value.trim())
         .filter(|value| !value.is_empty())
         .map(ToOwned::to_owned);
-    let text = match text {
-        Some(value) => value,
-        None => return json_bytes(&json!({"ok": false, "error": "text required"})),
+    if text.is_none() && envelope.attachments.is_empty() {
+        return json_bytes(&json!({"ok": false, "error": "text required"}));
+    }
+    let sources = match envelope
+        .attachments
+        .iter()
+        .map(files::file_source)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(sources) => sources,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
     };
 
-    let destination = envelope.to.first().cloned().or_else(|| {
-        cfg.default_to_person_email
-            .clone()
-            .map(|email| Destination {
-                id: email,
-                kind: Some("email".into()),
-            })
-    });
+    let destination = envelope
+        .to
+        .first()
+        .cloned()
+        .or_else(|| reply_scope_room(&envelope))
+        .or_else(|| {
+            cfg.default_to_person_email
+                .clone()
+                .map(|email| Destination {
+                    id: email,
+                    kind: Some("email".into()),
+                })
+        });
     println!("webex envelope to={:?}", envelope.to);
     let destination = match destination {
         Some(dest) => dest,
@@ -167,18 +186,16 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
         .api_base_url
         .clone()
         .unwrap_or_else(|| DEFAULT_API_BASE.to_string());
-    let url = format!("{}/messages", api_base);
-    let mut body = json!({ "text": text });
-    let body_obj = body.as_object_mut().expect("body object");
+    let mut fields = serde_json::Map::new();
     match kind {
         "room" => {
-            body_obj.insert("roomId".into(), Value::String(dest_id));
+            fields.insert("roomId".into(), Value::String(dest_id));
         }
         "person" | "user" => {
-            body_obj.insert("toPersonId".into(), Value::String(dest_id));
+            fields.insert("toPersonId".into(), Value::String(dest_id));
         }
         "email" | "" => {
-            body_obj.insert("toPersonEmail".into(), Value::String(dest_id));
+            fields.insert("toPersonEmail".into(), Value::String(dest_id));
         }
         other => {
             return json_bytes(&json!({
@@ -187,6 +204,12 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
             }));
         }
     }
+    if let Some(text) = text {
+        fields.insert("text".into(), Value::String(text));
+    }
+    if let Some(parent_id) = thread_parent_id(&envelope) {
+        fields.insert("parentId".into(), Value::String(parent_id));
+    }
 
     let token = match secrets_store::get(DEFAULT_TOKEN_KEY) {
         Ok(Some(bytes)) => match String::from_utf8(bytes) {
@@ -206,42 +229,28 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
     };
 
     println!(
-        "webex send url={} body={}",
-        url,
-        serde_json::to_string(&body).unwrap_or_default()
+        "webex send url={}/messages body={} attachments={}",
+        api_base,
+        serde_json::to_string(&fields).unwrap_or_default(),
+        envelope.attachments.len()
     );
-    let request = client::Request {
-        method: "POST".into(),
-        url,
-        headers: vec![
-            ("Content-Type".into(), "application/json".into()),
-            ("Authorization".into(), format!("Bearer {token}")),
-        ],
-        body: Some(serde_json::to_vec(&body).unwrap_or_else(|_| b"{}".to_vec())),
-    };
-
-    let resp = match client::send(&request, None, None) {
-        Ok(resp) => resp,
-        Err(err) => {
-            return json_bytes(
-                &json!({"ok": false, "error": format!("transport error: {}", err.message)}),
-            );
+    let files: Vec<_> = sources.iter().zip(envelope.attachments.iter()).collect();
+    let responses = match send_message_sequence(&api_base, &token, fields, &files) {
+        Ok(responses) => responses,
+        Err((err, sent)) => {
+            return json_bytes(&json!({
+                "ok": false,
+                "error": err,
+                "message_ids": sent.iter().map(response_message_id).collect::<Vec<_>>(),
+            }));
         }
     };
 
-    if resp.status < 200 || resp.status >= 300 {
-        return json_bytes(
-            &json!({"ok": false, "error": format!("webex returned status {}", resp.status)}),
-        );
-    }
-
-    let body_bytes = resp.body.unwrap_or_default();
-    let body_json: Value = serde_json::from_slice(&body_bytes).unwrap_or(Value::Null);
-    let msg_id = body_json
-        .get("id")
-        .and_then(|v| v.as_str())
-        .unwrap_or("webex-message")
-        .to_string();
+    let message_ids: Vec<String> = responses.iter().map(response_message_id).collect();
+    let msg_id = message_ids
+        .first()
+        .cloned()
+        .unwrap_or_else(|| "webex-message".to_string());
     let provider_message_id = format!("webex:{msg_id}");
 
     json_bytes(&json!({
@@ -250,10 +259,56 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
         "provider_type": PROVIDER_TYPE,
         "message_id": msg_id,
         "provider_message_id": provider_message_id,
-        "response": body_json
+        "message_ids": message_ids,
+        "provider_message_ids": message_ids.iter().map(|id| format!("webex:{id}")).collect::<Vec<_>>(),
+        "response": responses.into_iter().next().unwrap_or(Value::Null)
     }))
 }
 
+/// Webex accepts a single file per message, so the text travels with the first
+/// file and every further file is posted as a follow-up in the same thread.
+/// On failure the responses of the messages already sent are returned alongside
+/// the error.
+fn send_message_sequence(
+    api_base: &str,
+    token: &str,
+    mut fields: serde_json::Map<String, Value>,
+    files: &[(&files::FileSource, &Attachment)],
+) -> Result<Vec<Value>, (String, Vec<Value>)> {
+    let mut remaining = files.iter();
+    let first = files::post_message(api_base, token, &fields, remaining.next().copied())
+        .map_err(|err| (err, Vec::new()))?;
+    let mut responses = vec![first];
+    if files.len() > 1 {
+        let first = &responses[0];
+        if let Some(room_id) = first.get("roomId").and_then(Value::as_str) {
+            fields.remove("toPersonId");
+            fields.remove("toPersonEmail");
+            fields.insert("roomId".into(), Value::String(room_id.to_string()));
+        }
+        if !fields.contains_key("parentId") {
+            fields.insert("parentId".into(), Value::String(response_message_id(first)));
+        }
+        fields.remove("text");
+        fields.remove("markdown");
+    }
+    for file in remaining {
+        match files::post_message(api_base, token, &fields, Some(*file)) {
+            Ok(resp) => responses.push(resp),
+            Err(err) => return Err((err, responses)),
+        }
+    }
+    Ok(responses)
+}
+
+fn response_message_id(response: &Value) -> String {
+    response
+        .get("id")
+        .and_then(Value::as_str)
+        .unwrap_or("webex-message")
+        .to_string()
+}
+
 fn handle_reply(_input_json: &[u8]) -> Vec<u8> {
     let parsed: Value = match serde_json::from_slice(_input_json) {
         Ok(val) => val,
@@ -295,10 +350,18 @@ fn handle_reply(_input_json: &[u8]) -> Vec<u8> {
         .api_base_url
         .unwrap_or_else(|| DEFAULT_API_BASE.to_string());
     let url = format!("{}/messages", api_base);
-    let payload = json!({
+    let mut payload = json!({
         "parentId": thread_id,
         "markdown": text,
     });
+    if let Some(room_id) = parsed
+        .get("room_id")
+        .or_else(|| parsed.pointer("/reply_scope/conversation"))
+        .and_then(Value::as_str)
+        .filter(|room| !room.trim().is_empty())
+    {
+        payload["roomId"] = Value::String(room_id.to_string());
+    }
     let request = client::Request {
         method: "POST".into(),
         url,
@@ -343,6 +406,134 @@ fn handle_reply(_input_json: &[u8]) -> Vec<u8> {
     }))
 }
 
+fn handle_update_message(input_json: &[u8]) -> Vec<u8> {
+    let parsed: Value = match serde_json::from_slice(input_json) {
+        Ok(val) => val,
+        Err(err) => {
+            return json_bytes(&json!({"ok": false, "error": format!("invalid json: {err}")}));
+        }
+    };
+    let cfg = match load_config(&parsed) {
+        Ok(cfg) => cfg,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let Some(message_id) = webex_message_id(&parsed) else {
+        return json_bytes(&json!({"ok": false, "error": "message_id required"}));
+    };
+    let markdown = parsed.get("markdown").and_then(Value::as_str);
+    let text = parsed.get("text").and_then(Value::as_str);
+    if markdown
+        .or(text)
+        .is_none_or(|value| value.trim().is_empty())
+    {
+        return json_bytes(&json!({"ok": false, "error": "text or markdown required"}));
+    }
+    let token = match get_secret_string(DEFAULT_TOKEN_KEY) {
+        Ok(token) => token,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let api_base = resolve_api_base(&cfg);
+    // Webex requires the roomId on edits; look it up when the caller did not pass it.
+    let room_id = match parsed
+        .get("room_id")
+        .and_then(Value::as_str)
+        .filter(|room| !room.trim().is_empty())
+    {
+        Some(room) => room.to_string(),
+        None => match fetch_message_details(&message_id, &api_base, &token) {
+            Ok(details) => match details.room_id {
+                Some(room) => room,
+                None => {
+                    return json_bytes(&json!({"ok": false, "error": "message has no roomId"}));
+                }
+            },
+            Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+        },
+    };
+    let mut payload = json!({ "roomId": room_id });
+    if let Some(markdown) = markdown {
+        payload["markdown"] = Value::String(markdown.to_string());
+    }
+    if let Some(text) = text {
+        payload["text"] = Value::String(text.to_string());
+    }
+    let request = client::Request {
+        method: "PUT".into(),
+        url: format!("{api_base}/messages/{message_id}"),
+        headers: vec![
+            ("Content-Type".into(), "application/json".into()),
+            ("Authorization".into(), format!("Bearer {token}")),
+        ],
+        body: Some(serde_json::to_vec(&payload).unwrap_or_else(|_| b"{}".to_vec())),
+    };
+    let resp = match client::send(&request, None, None) {
+        Ok(resp) => resp,
+        Err(err) => {
+            return json_bytes(
+                &json!({"ok": false, "error": format!("transport error: {}", err.message)}),
+            );
+        }
+    };
+    let body = resp.body.unwrap_or_default();
+    if resp.status < 200 || resp.status >= 300 {
+        return json_bytes(&json!({"ok": false, "error": format_webex_error(resp.status, &body)}));
+    }
+    let body_json: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    json_bytes(&json!({
+        "ok": true,
+        "status": "updated",
+        "provider_type": PROVIDER_TYPE,
+        "message_id": message_id,
+        "provider_message_id": format!("webex:{message_id}"),
+        "response": body_json
+    }))
+}
+
+fn handle_delete_message(input_json: &[u8]) -> Vec<u8> {
+    let parsed: Value = match serde_json::from_slice(input_json) {
+        Ok(val) => val,
+        Err(err) => {
+            return json_bytes(&json!({"ok": false, "error": format!("invalid json: {err}")}));
+        }
+    };
+    let cfg = match load_config(&parsed) {
+        Ok(cfg) => cfg,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let Some(message_id) = webex_message_id(&parsed) else {
+        return json_bytes(&json!({"ok": false, "error": "message_id required"}));
+    };
+    let token = match get_secret_string(DEFAULT_TOKEN_KEY) {
+        Ok(token) => token,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let request = client::Request {
+        method: "DELETE".into(),
+        url: format!("{}/messages/{message_id}", resolve_api_base(&cfg)),
+        headers: vec![("Authorization".into(), format!("Bearer {token}"))],
+        body: None,
+    };
+    let resp = match client::send(&request, None, None) {
+        Ok(resp) => resp,
+        Err(err) => {
+            return json_bytes(
+                &json!({"ok": false, "error": format!("transport error: {}", err.message)}),
+            );
+        }
+    };
+    if resp.status < 200 || resp.status >= 300 {
+        let body = resp.body.unwrap_or_default();
+        return json_bytes(&json!({"ok": false, "error": format_webex_error(resp.status, &body)}));
+    }
+    json_bytes(&json!({
+        "ok": true,
+        "status": "deleted",
+        "provider_type": PROVIDER_TYPE,
+        "message_id": message_id,
+        "provider_message_id": format!("webex:{message_id}"),
+    }))
+}
+
 fn parse_config_bytes(bytes: &[u8]) -> Result<ProviderConfig, String> {
     serde_json::from_slice::<ProviderConfig>(bytes).map_err(|e| format!("invalid config: {e}"))
 }
@@ -382,6 +573,39 @@ fn override_config_from_metadata(cfg: &mut ProviderConfig, metadata: &MessageMet
     }
 }
 
+/// Thread root for an outbound message: the envelope reply scope (populated on
+/// ingest from Webex `parentId`), else an explicit `webex.parentId` metadata entry.
+fn thread_parent_id(envelope: &ChannelMessageEnvelope) -> Option<String> {
+    envelope
+        .reply_scope
+        .as_ref()
+        .and_then(|scope| scope.thread.clone())
+        .or_else(|| envelope.metadata.get("webex.parentId").cloned())
+        .filter(|id| !id.trim().is_empty())
+}
+
+fn reply_scope_room(envelope: &ChannelMessageEnvelope) -> Option<Destination> {
+    envelope
+        .reply_scope
+        .as_ref()
+        .filter(|scope| !scope.conversation.trim().is_empty())
+        .map(|scope| Destination {
+            id: scope.conversation.clone(),
+            kind: Some("room".into()),
+        })
+}
+
+/// Accepts a bare Webex message id or the `webex:{id}` form returned by send/reply.
+fn webex_message_id(input: &Value) -> Option<String> {
+    input
+        .get("message_id")
+        .or_else(|| input.get("provider_message_id"))
+        .and_then(Value::as_str)
+        .map(|id| id.trim())
+        .map(|id| id.strip_prefix("webex:").unwrap_or(id).to_string())
+        .filter(|id| !id.is_empty())
+}
+
 fn build_send_envelope_from_input(
     parsed: &Value,
     cfg: &ProviderConfig,
@@ -406,6 +630,14 @@ fn build_send_envelope_from_input(
     if let Some(kind) = &destination.kind {
         metadata.insert("destination_kind".to_string(), kind.clone());
     }
+    if let Some(parent_id) = parsed
+        .get("parent_id")
+        .or_else(|| parsed.get("thread_id"))
+        .and_then(Value::as_str)
+        .filter(|id| !id.trim().is_empty())
+    {
+        metadata.insert("webex.parentId".to_string(), parent_id.to_string());
+    }
     let channel_name = destination.id.clone();
 
     Ok(ChannelMessageEnvelope {
@@ -542,6 +774,7 @@ struct MessageDetails {
     room_id: Option<String>,
     person_email: Option<String>,
     person_id: Option<String>,
+    parent_id: Option<String>,
     attachments: Vec<Attachment>,
 }
 
@@ -572,17 +805,18 @@ fn handle_webhook_event(body: &Value, cfg: &ProviderConfig) -> IngestOutcome {
         .and_then(|v| v.as_str())
         .map(|s| s.to_string());
 
+    if resource == "attachmentActions"
+        && event == "created"
+        && let Some(action_id) = message_id.clone()
+    {
+        return handle_attachment_action(&action_id, data, cfg);
+    }
+
     if resource == "messages"
         && event == "created"
         && let Some(message_id) = message_id.clone()
     {
-        let api_base = cfg
-            .api_base_url
-            .as_deref()
-            .filter(|s| !s.trim().is_empty())
-            .unwrap_or(DEFAULT_API_BASE)
-            .trim_end_matches('/')
-            .to_string();
+        let api_base = resolve_api_base(cfg);
         match get_secret_string(DEFAULT_TOKEN_KEY) {
             Ok(token) => match fetch_message_details(&message_id, &api_base, &token) {
                 Ok(details) => {
@@ -626,14 +860,24 @@ fn handle_webhook_event(body: &Value, cfg: &ProviderConfig) -> IngestOutcome {
                         attachment_types.clone(),
                         Some(200),
                     );
-                    let envelope = build_webhook_envelope(
+                    let mut envelope = build_webhook_envelope(
                         text,
-                        session_id,
+                        session_id.clone(),
                         sender,
                         metadata,
                         details.attachments.clone(),
                         Some(&message_id),
                     );
+                    envelope.reply_scope = Some(inbound_reply_scope(
+                        &session_id,
+                        &message_id,
+                        details.parent_id.as_ref(),
+                    ));
+                    if let Some(parent_id) = &details.parent_id {
+                        envelope
+                            .metadata
+                            .insert("webex.parentId".to_string(), parent_id.clone());
+                    }
                     return IngestOutcome {
                         envelope,
                         status: 200,
@@ -737,6 +981,122 @@ fn handle_webhook_event(body: &Value, cfg: &ProviderConfig) -> IngestOutcome {
     }
 }
 
+/// Webex threads are one level deep: replies share the root's id as `parentId`.
+/// An inbound thread reply therefore maps to that root; a top-level message has
+/// no thread until someone replies to it.
+fn inbound_reply_scope(room_id: &str, message_id: &str, parent_id: Option<&String>) -> ReplyScope {
+    ReplyScope {
+        conversation: room_id.to_string(),
+        thread: parent_id.cloned(),
+        reply_to: Some(message_id.to_string()),
+        correlation: None,
+    }
+}
+
+fn resolve_api_base(cfg: &ProviderConfig) -> String {
+    cfg.api_base_url
+        .as_deref()
+        .filter(|s| !s.trim().is_empty())
+        .unwrap_or(DEFAULT_API_BASE)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Adaptive Card submits arrive as `attachmentActions.created`; the webhook only
+/// carries ids, so the submitted `inputs` are fetched from `/attachmentActions/{id}`.
+fn handle_attachment_action(action_id: &str, data: &Value, cfg: &ProviderConfig) -> IngestOutcome {
+    let token = match get_secret_string(DEFAULT_TOKEN_KEY) {
+        Ok(token) => token,
+        Err(err) => return card_submit_failure(action_id, data, err, 500),
+    };
+    match fetch_attachment_action(action_id, &resolve_api_base(cfg), &token) {
+        Ok(action) => IngestOutcome {
+            envelope: build_card_submit_envelope(action_id, &action, data, None, 200),
+            status: 200,
+            error: None,
+        },
+        Err(err) => {
+            println!("webex ingest attachment action error for {action_id}: {err}");
+            card_submit_failure(action_id, data, err, 502)
+        }
+    }
+}
+
+fn card_submit_failure(action_id: &str, data: &Value, err: String, status: u16) -> IngestOutcome {
+    IngestOutcome {
+        envelope: build_card_submit_envelope(action_id, &Value::Null, data, Some(&err), status),
+        status,
+        error: Some(err),
+    }
+}
+
+fn fetch_attachment_action(action_id: &str, api_base: &str, token: &str) -> Result<Value, String> {
+    let request = client::Request {
+        method: "GET".to_string(),
+        url: format!("{api_base}/attachmentActions/{action_id}"),
+        headers: vec![("Authorization".into(), format!("Bearer {token}"))],
+        body: None,
+    };
+    let resp = client::send(&request, None, None)
+        .map_err(|err| format!("transport error: {}", err.message))?;
+    let body = resp.body.unwrap_or_default();
+    if resp.status < 200 || resp.status >= 300 {
+        return Err(format_webex_error(resp.status, &body));
+    }
+    serde_json::from_slice(&body).map_err(|err| format!("invalid attachment action JSON: {err}"))
+}
+
+/// Builds the envelope for a card submission. Fields from the fetched action win
+/// over the (sparser) webhook `data`, which is all we have when the fetch failed.
+fn build_card_submit_envelope(
+    action_id: &str,
+    action: &Value,
+    data: &Value,
+    error: Option<&String>,
+    status: u16,
+) -> ChannelMessageEnvelope {
+    let field = |key: &str| {
+        action
+            .get(key)
+            .or_else(|| data.get(key))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    let origin_message_id = field("messageId");
+    let room_id = field("roomId");
+    let person_id = field("personId");
+    let mut metadata = build_webhook_metadata(
+        "attachmentActions",
+        "created",
+        origin_message_id.as_ref(),
+        room_id.as_ref(),
+        None,
+        person_id.as_ref(),
+        error,
+        None,
+        Some(status),
+    );
+    metadata.insert("webex.actionId".to_string(), action_id.to_string());
+    if let Some(kind) = field("type") {
+        metadata.insert("webex.actionType".to_string(), kind);
+    }
+    if let Some(inputs) = action.get("inputs") {
+        metadata.insert("webex.inputs".to_string(), inputs.to_string());
+    }
+    let session_id = room_id.unwrap_or_else(|| action_id.to_string());
+    let mut envelope = build_webhook_envelope(
+        String::new(),
+        session_id,
+        pick_sender(&None, &person_id),
+        metadata,
+        Vec::new(),
+        None,
+    );
+    envelope.id = format!("webex-action-{action_id}");
+    envelope.text = None;
+    envelope
+}
+
 fn fetch_message_details(
     message_id: &str,
     api_base: &str,
@@ -786,6 +1146,10 @@ fn fetch_message_details(
             .get("personId")
             .and_then(|v| v.as_str())
             .map(|s| s.to_string()),
+        parent_id: data
+            .get("parentId")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string()),
         attachments,
     })
 }
@@ -932,6 +1296,26 @@ fn ingest_http(input_json: &[u8]) -> Vec<u8> {
         Ok(bytes) => bytes,
         Err(err) => return http_out_error(400, &format!("invalid body encoding: {err}")),
     };
+    let secret = match get_optional_secret(WEBHOOK_SECRET_KEY) {
+        Some(Ok(secret)) => secret,
+        Some(Err(err)) => return http_out_error(500, &err),
+        None => {
+            let err = signature::SignatureError::SecretNotConfigured {
+                secret: WEBHOOK_SECRET_KEY,
+            };
+            return http_out_json(401, &err.to_json());
+        }
+    };
+    let previous = match get_optional_secret(PREVIOUS_WEBHOOK_SECRET_KEY) {
+        Some(Ok(previous)) => Some(previous),
+        Some(Err(err)) => return http_out_error(500, &err),
+        None => None,
+    };
+    if let Err(err) =
+        signature::verify_any(&request.headers, &body_bytes, &secret, previous.as_deref())
+    {
+        return http_out_json(401, &err.to_json());
+    }
     let body_val: Value = serde_json::from_slice(&body_bytes).unwrap_or(Value::Null);
     let cfg = load_config(&json!({})).unwrap_or_default();
     let outcome = handle_webhook_event(&body_val, &cfg);
@@ -1098,6 +1482,9 @@ fn send_payload(input_json: &[u8]) -> Vec<u8> {
             return send_payload_error(&format!("unsupported destination kind: {other}"), false);
         }
     }
+    if let Some(parent_id) = thread_parent_id(&envelope) {
+        body_map.insert("parentId".into(), Value::String(parent_id));
+    }
     let body_req = Value::Object(body_map);
     println!(
         "webex send url={}/messages body={}",
@@ -1141,6 +1528,19 @@ fn http_out_error(status: u16, message: &str) -> Vec<u8> {
     json_bytes(&out)
 }
 
+fn http_out_json(status: u16, body: &Value) -> Vec<u8> {
+    let out = HttpOutV1 {
+        status,
+        headers: vec![Header {
+            name: "Content-Type".into(),
+            value: "application/json".into(),
+        }],
+        body_b64: STANDARD.encode(serde_json::to_vec(body).unwrap_or_default()),
+        events: Vec::new(),
+    };
+    json_bytes(&out)
+}
+
 fn render_plan_error(message: &str) -> Vec<u8> {
     json_bytes(&json!({"ok": false, "error": message}))
 }
@@ -1175,6 +1575,20 @@ fn get_secret_string(key: &str) -> Result<String, String> {
     }
 }
 
+/// Returns `None` when the secret is not provisioned so optional features stay off.
+/// Empty values count as unset; `webex-webhook` clears secrets by writing an
+/// empty value.
+fn get_optional_secret(key: &str) -> Option<Result<String, String>> {
+    match secrets_store::get(key) {
+        Ok(Some(bytes)) if bytes.is_empty() => None,
+        Ok(Some(bytes)) => {
+            Some(String::from_utf8(bytes).map_err(|_| "secret not valid utf-8".into()))
+        }
+        Ok(None) => None,
+        Err(e) => Some(Err(format!("secret store error: {e:?}"))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1232,6 +1646,97 @@ mod tests {
         assert!(cfg.default_room_id.is_none());
     }
 
+    #[test]
+    fn card_submit_envelope_carries_inputs_and_origin() {
+        let action = json!({
+            "id": "act-1",
+            "type": "submit",
+            "messageId": "msg-9",
+            "personId": "person-3",
+            "roomId": "room-7",
+            "inputs": {"choice": "yes", "comment": "ok"}
+        });
+        let envelope = build_card_submit_envelope("act-1", &action, &json!({}), None, 200);
+        assert_eq!(envelope.id, "webex-action-act-1");
+        assert_eq!(envelope.session_id, "room-7");
+        assert_eq!(
+            envelope.from.as_ref().map(|a| a.id.as_str()),
+            Some("person-3")
+        );
+        assert_eq!(envelope.text, None);
+        assert_eq!(
+            envelope.metadata.get("webex.messageId").map(String::as_str),
+            Some("msg-9")
+        );
+        assert_eq!(
+            envelope
+                .metadata
+                .get("webex.actionType")
+                .map(String::as_str),
+            Some("submit")
+        );
+        let inputs: Value =
+            serde_json::from_str(envelope.metadata.get("webex.inputs").expect("inputs")).unwrap();
+        assert_eq!(inputs["choice"], "yes");
+    }
+
+    #[test]
+    fn card_submit_envelope_falls_back_to_webhook_data() {
+        let data =
+            json!({"id": "act-2", "messageId": "msg-1", "roomId": "room-1", "personId": "p-1"});
+        let err = "webex returned status 404".to_string();
+        let envelope = build_card_submit_envelope("act-2", &Value::Null, &data, Some(&err), 502);
+        assert_eq!(envelope.session_id, "room-1");
+        assert!(!envelope.metadata.contains_key("webex.inputs"));
+        assert_eq!(
+            envelope
+                .metadata
+                .get("webex.ingestError")
+                .map(String::as_str),
+            Some(err.as_str())
+        );
+    }
+
+    #[test]
+    fn webex_message_id_strips_provider_prefix() {
+        assert_eq!(
+            webex_message_id(&json!({"provider_message_id": "webex:abc"})).as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            webex_message_id(&json!({"message_id": "xyz"})).as_deref(),
+            Some("xyz")
+        );
+        assert_eq!(webex_message_id(&json!({"message_id": "webex:"})), None);
+    }
+
+    #[test]
+    fn thread_parent_prefers_reply_scope() {
+        let mut envelope = build_webhook_envelope(
+            "hi".into(),
+            "room-1".into(),
+            None,
+            MessageMetadata::new(),
+            Vec::new(),
+            None,
+        );
+        assert_eq!(thread_parent_id(&envelope), None);
+        envelope
+            .metadata
+            .insert("webex.parentId".into(), "meta-parent".into());
+        assert_eq!(thread_parent_id(&envelope).as_deref(), Some("meta-parent"));
+        envelope.reply_scope = Some(inbound_reply_scope(
+            "room-1",
+            "msg-2",
+            Some(&"root-1".to_string()),
+        ));
+        assert_eq!(thread_parent_id(&envelope).as_deref(), Some("root-1"));
+        assert_eq!(
+            reply_scope_room(&envelope).map(|dest| dest.id),
+            Some("room-1".to_string())
+        );
+    }
+
     #[test]
     fn parse_config_rejects_unknown() {
         let cfg = br#"{"default_room_id":"k","unexpected":true}"#;
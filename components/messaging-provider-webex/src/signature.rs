@@ -0,0 +1,260 @@
+use greentic_types::messaging::universal_dto::Header;
+use hmac::{Hmac, Mac};
+use serde_json::{Value, json};
+use sha1::Sha1;
+use sha2::Sha256;
+
+/// Legacy header: bare hex HMAC-SHA1 of the raw body, keyed with the webhook secret.
+pub(crate) const SPARK_SIGNATURE_HEADER: &str = "X-Spark-Signature";
+/// Newer header: comma separated `SHA-1=<hex>` / `SHA-256=<hex>` segments.
+pub(crate) const WEBEX_SIGNATURE_HEADER: &str = "X-Webex-Signature";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Algorithm {
+    Sha1,
+    Sha256,
+}
+
+impl Algorithm {
+    fn label(self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+/// Why a webhook delivery failed verification; serialized into the 401 body.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum SignatureError {
+    /// No webhook secret is configured, so no delivery can be trusted.
+    SecretNotConfigured {
+        secret: &'static str,
+    },
+    Missing,
+    Malformed {
+        header: &'static str,
+    },
+    Mismatch {
+        header: &'static str,
+        algorithm: Algorithm,
+    },
+}
+
+impl SignatureError {
+    pub(crate) fn reason(&self) -> &'static str {
+        match self {
+            SignatureError::SecretNotConfigured { .. } => "secret_not_configured",
+            SignatureError::Missing => "missing_signature",
+            SignatureError::Malformed { .. } => "malformed_signature",
+            SignatureError::Mismatch { .. } => "signature_mismatch",
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        let mut body = json!({
+            "ok": false,
+            "error": "invalid_signature",
+            "reason": self.reason(),
+        });
+        match self {
+            SignatureError::SecretNotConfigured { secret } => {
+                body["secret"] = Value::String((*secret).to_string());
+            }
+            SignatureError::Missing => {
+                body["expected_headers"] = json!([SPARK_SIGNATURE_HEADER, WEBEX_SIGNATURE_HEADER]);
+            }
+            SignatureError::Malformed { header } => {
+                body["header"] = Value::String((*header).to_string());
+            }
+            SignatureError::Mismatch { header, algorithm } => {
+                body["header"] = Value::String((*header).to_string());
+                body["algorithm"] = Value::String(algorithm.label().to_string());
+            }
+        }
+        body
+    }
+}
+
+/// Verifies a Webex webhook delivery. `X-Webex-Signature` is preferred when
+/// present (SHA-256 over SHA-1); otherwise `X-Spark-Signature` is checked as
+/// HMAC-SHA1.
+pub(crate) fn verify(headers: &[Header], body: &[u8], secret: &str) -> Result<(), SignatureError> {
+    if let Some(value) = header_value(headers, WEBEX_SIGNATURE_HEADER) {
+        let header = WEBEX_SIGNATURE_HEADER;
+        let (algorithm, signature) =
+            parse_webex_signature(value).ok_or(SignatureError::Malformed { header })?;
+        return check(algorithm, &signature, body, secret)
+            .map_err(|_| SignatureError::Mismatch { header, algorithm });
+    }
+    if let Some(value) = header_value(headers, SPARK_SIGNATURE_HEADER) {
+        let header = SPARK_SIGNATURE_HEADER;
+        let signature = hex_decode(value.trim_matches('"'))
+            .filter(|bytes| bytes.len() == 20)
+            .ok_or(SignatureError::Malformed { header })?;
+        let algorithm = Algorithm::Sha1;
+        return check(algorithm, &signature, body, secret)
+            .map_err(|_| SignatureError::Mismatch { header, algorithm });
+    }
+    Err(SignatureError::Missing)
+}
+
+/// Verifies against the current secret and, during a secret rotation, the
+/// previous one. Errors describe the check against the current secret.
+pub(crate) fn verify_any(
+    headers: &[Header],
+    body: &[u8],
+    secret: &str,
+    previous: Option<&str>,
+) -> Result<(), SignatureError> {
+    verify(headers, body, secret).or_else(|err| match previous {
+        Some(previous) if verify(headers, body, previous).is_ok() => Ok(()),
+        _ => Err(err),
+    })
+}
+
+fn header_value<'a>(headers: &'a [Header], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case(name))
+        .map(|header| header.value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+fn parse_webex_signature(value: &str) -> Option<(Algorithm, Vec<u8>)> {
+    let mut sha1 = None;
+    let mut sha256 = None;
+    for segment in value.split(',') {
+        let Some((name, hex)) = segment.trim().split_once('=') else {
+            continue;
+        };
+        let hex = hex.trim().trim_matches('"');
+        match name.trim().to_ascii_uppercase().as_str() {
+            "SHA-256" | "SHA256" => sha256 = Some(hex_decode(hex)?),
+            "SHA-1" | "SHA1" => sha1 = Some(hex_decode(hex)?),
+            _ => {}
+        }
+    }
+    sha256
+        .map(|sig| (Algorithm::Sha256, sig))
+        .or_else(|| sha1.map(|sig| (Algorithm::Sha1, sig)))
+}
+
+fn check(algorithm: Algorithm, signature: &[u8], body: &[u8], secret: &str) -> Result<(), ()> {
+    match algorithm {
+        Algorithm::Sha1 => {
+            let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).map_err(|_| ())?;
+            mac.update(body);
+            mac.verify_slice(signature).map_err(|_| ())
+        }
+        Algorithm::Sha256 => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|_| ())?;
+            mac.update(body);
+            mac.verify_slice(signature).map_err(|_| ())
+        }
+    }
+}
+
+fn hex_decode(value: &str) -> Option<Vec<u8>> {
+    if value.is_empty() || !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(value.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn sign_sha1(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        hex(&mac.finalize().into_bytes())
+    }
+
+    fn sign_sha256(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        hex(&mac.finalize().into_bytes())
+    }
+
+    fn header(name: &str, value: String) -> Vec<Header> {
+        vec![Header {
+            name: name.into(),
+            value,
+        }]
+    }
+
+    #[test]
+    fn accepts_spark_sha1_signature() {
+        let body = br#"{"resource":"messages"}"#;
+        let headers = header("x-spark-signature", sign_sha1("s3cret", body));
+        assert!(verify(&headers, body, "s3cret").is_ok());
+    }
+
+    #[test]
+    fn accepts_webex_sha256_and_sha1_segments() {
+        let body = br#"{"resource":"messages"}"#;
+        let value = format!(
+            "SHA-1={}, SHA-256={}",
+            sign_sha1("s3cret", body),
+            sign_sha256("s3cret", body)
+        );
+        assert!(verify(&header(WEBEX_SIGNATURE_HEADER, value), body, "s3cret").is_ok());
+        let value = format!("SHA-1={}", sign_sha1("s3cret", body));
+        assert!(verify(&header(WEBEX_SIGNATURE_HEADER, value), body, "s3cret").is_ok());
+    }
+
+    #[test]
+    fn accepts_previous_secret_during_rotation() {
+        let body = br#"{"resource":"messages"}"#;
+        let old = header(SPARK_SIGNATURE_HEADER, sign_sha1("old", body));
+        assert!(verify_any(&old, body, "new", Some("old")).is_ok());
+        assert_eq!(
+            verify_any(&old, body, "new", None).unwrap_err().reason(),
+            "signature_mismatch"
+        );
+        let other = header(SPARK_SIGNATURE_HEADER, sign_sha1("other", body));
+        assert!(verify_any(&other, body, "new", Some("old")).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_malformed_and_mismatched() {
+        let body = br#"{"resource":"messages"}"#;
+        assert_eq!(verify(&[], body, "s3cret"), Err(SignatureError::Missing));
+        assert_eq!(
+            verify(&header(SPARK_SIGNATURE_HEADER, "zz".into()), body, "s3cret"),
+            Err(SignatureError::Malformed {
+                header: SPARK_SIGNATURE_HEADER
+            })
+        );
+        let err = verify(
+            &header(
+                WEBEX_SIGNATURE_HEADER,
+                format!("SHA-256={}", sign_sha256("other", body)),
+            ),
+            body,
+            "s3cret",
+        )
+        .unwrap_err();
+        assert_eq!(err.reason(), "signature_mismatch");
+        let json = err.to_json();
+        assert_eq!(json["error"], "invalid_signature");
+        assert_eq!(json["algorithm"], "sha256");
+        assert_eq!(json["header"], WEBEX_SIGNATURE_HEADER);
+
+        let json = SignatureError::SecretNotConfigured {
+            secret: "WEBEX_WEBHOOK_SECRET",
+        }
+        .to_json();
+        assert_eq!(json["reason"], "secret_not_configured");
+        assert_eq!(json["secret"], "WEBEX_WEBHOOK_SECRET");
+    }
+}
@@ -1,15 +1,23 @@
 use base64::{Engine as _, engine::general_purpose};
+use chrono::Utc;
+use greentic_types::messaging::universal_dto::Header;
 use greentic_types::messaging::universal_dto::{
     EncodeInV1, HttpInV1, HttpOutV1, ProviderPayloadV1, RenderPlanInV1, RenderPlanOutV1,
     SendPayloadInV1, SendPayloadResultV1,
 };
 use greentic_types::{
-    Actor, ChannelMessageEnvelope, Destination, EnvId, MessageMetadata, TenantCtx, TenantId,
+    Actor, Attachment, ChannelMessageEnvelope, Destination, EnvId, MessageMetadata, TenantCtx,
+    TenantId,
 };
+use hmac::{Hmac, Mac};
 use serde::Deserialize;
 use serde_json::{Value, json};
+use sha2::Sha256;
 use std::collections::{BTreeMap, HashMap};
 
+mod media;
+mod window;
+
 mod bindings {
     wit_bindgen::generate!({
         path: "wit/messaging-provider-whatsapp",
@@ -22,12 +30,18 @@ use bindings::exports::greentic::provider_schema_core::schema_core_api::Guest;
 use bindings::greentic::http::client;
 use bindings::greentic::secrets_store::secrets_store;
 use greentic_types::ProviderManifest;
+use window::WindowState;
 
 const PROVIDER_TYPE: &str = "messaging.whatsapp.cloud";
 const CONFIG_SCHEMA_REF: &str = "schemas/messaging/whatsapp/public.config.schema.json";
 const DEFAULT_API_BASE: &str = "https://graph.facebook.com";
 const DEFAULT_API_VERSION: &str = "v19.0";
 const DEFAULT_TOKEN_KEY: &str = "WHATSAPP_TOKEN";
+const VERIFY_TOKEN_KEY: &str = "WHATSAPP_VERIFY_TOKEN";
+const APP_SECRET_KEY: &str = "WHATSAPP_APP_SECRET";
+const SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+/// Envelope metadata flag (`"true"`) that sends WebP attachments as stickers.
+const SEND_AS_STICKER_KEY: &str = "send_as_sticker";
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -39,6 +53,24 @@ struct ProviderConfig {
     api_base_url: Option<String>,
     #[serde(default)]
     api_version: Option<String>,
+    #[serde(default)]
+    reengagement_template: Option<TemplateConfig>,
+}
+
+/// Approved template sent instead of a free-form message once the 24h
+/// customer-service window has closed.
+#[derive(Clone, Debug, Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateConfig {
+    name: String,
+    #[serde(default = "default_template_language")]
+    language: String,
+    #[serde(default)]
+    components: Option<Value>,
+}
+
+fn default_template_language() -> String {
+    "en_US".to_string()
 }
 
 struct Component;
@@ -55,6 +87,10 @@ impl Guest for Component {
                 "render_plan".to_string(),
                 "encode".to_string(),
                 "send_payload".to_string(),
+                "download_media".to_string(),
+                "window_status".to_string(),
+                "mark_read".to_string(),
+                "typing".to_string(),
             ],
             config_schema_ref: Some(CONFIG_SCHEMA_REF.to_string()),
             state_schema_ref: None,
@@ -71,6 +107,7 @@ impl Guest for Component {
                     "business_account_id": cfg.business_account_id,
                     "api_base_url": cfg.api_base_url.unwrap_or_else(|| DEFAULT_API_BASE.to_string()),
                     "api_version": cfg.api_version.unwrap_or_else(|| DEFAULT_API_VERSION.to_string()),
+                    "reengagement_template": cfg.reengagement_template,
                 }
             })),
             Err(err) => json_bytes(&json!({"ok": false, "error": err})),
@@ -89,6 +126,10 @@ impl Guest for Component {
             "render_plan" => render_plan(&input_json),
             "encode" => encode_op(&input_json),
             "send_payload" => send_payload(&input_json),
+            "download_media" => download_media(&input_json),
+            "window_status" => window_status(&input_json),
+            "mark_read" => mark_read(&input_json, false),
+            "typing" => mark_read(&input_json, true),
             other => json_bytes(&json!({"ok": false, "error": format!("unsupported op: {other}")})),
         }
     }
@@ -129,20 +170,15 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
         },
     };
 
-    if !envelope.attachments.is_empty() {
-        return json_bytes(&json!({"ok": false, "error": "attachments not supported"}));
-    }
-
     let text = envelope
         .text
         .as_ref()
         .map(|value| value.trim())
         .filter(|value| !value.is_empty())
         .map(ToOwned::to_owned);
-    let text = match text {
-        Some(value) => value,
-        None => return json_bytes(&json!({"ok": false, "error": "text required"})),
-    };
+    if text.is_none() && envelope.attachments.is_empty() {
+        return json_bytes(&json!({"ok": false, "error": "text required"}));
+    }
 
     let destination = envelope.to.first().cloned();
     let destination = match destination {
@@ -179,47 +215,214 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
         }
     };
 
-    let api_base = cfg
-        .api_base_url
-        .unwrap_or_else(|| DEFAULT_API_BASE.to_string());
-    let api_version = cfg
-        .api_version
-        .unwrap_or_else(|| DEFAULT_API_VERSION.to_string());
-    let url = format!(
-        "{}/{}/{}/messages",
-        api_base, api_version, cfg.phone_number_id
-    );
+    let graph_base = graph_base(Some(&cfg));
+    let url = format!("{}/{}/messages", graph_base, cfg.phone_number_id);
 
-    let payload = json!({
-        "messaging_product": "whatsapp",
-        "to": dest_id,
-        "type": "text",
-        "text": {"body": text},
+    let mut payloads = Vec::new();
+    let fallback = match window_fallback(&cfg, dest_id) {
+        Ok(fallback) => fallback,
+        Err(resp) => return resp,
+    };
+    if let Some(template) = fallback.clone() {
+        payloads.push(template);
+    } else {
+        match build_free_form_payloads(
+            &envelope,
+            text.as_deref(),
+            dest_id,
+            &graph_base,
+            &cfg,
+            &token,
+        ) {
+            Ok(free_form) => payloads.extend(free_form),
+            Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+        }
+    }
+
+    let mut message_ids = Vec::new();
+    let mut responses = Vec::new();
+    for payload in &payloads {
+        match post_message(&url, &token, payload) {
+            Ok((msg_id, body_json)) => {
+                message_ids.push(msg_id);
+                responses.push(body_json);
+            }
+            Err(err) => {
+                return json_bytes(&json!({
+                    "ok": false,
+                    "error": err,
+                    "message_ids": message_ids,
+                }));
+            }
+        }
+    }
+
+    let fallback_label = fallback.as_ref().map(|_| "reengagement_template");
+    let msg_id = message_ids
+        .first()
+        .cloned()
+        .unwrap_or_else(|| "wa-message".to_string());
+    let provider_message_id = format!("whatsapp:{msg_id}");
+    let response = if responses.len() == 1 {
+        responses.remove(0)
+    } else {
+        Value::Array(responses)
+    };
+
+    json_bytes(&json!({
+        "ok": true,
+        "status": "sent",
+        "provider_type": PROVIDER_TYPE,
+        "message_id": msg_id,
+        "message_ids": message_ids,
+        "provider_message_id": provider_message_id,
+        "fallback": fallback_label,
+        "response": response
+    }))
+}
+
+/// Builds the text and media messages for a free-form send. Text rides along as
+/// the caption of the first attachment when that media type supports captions.
+fn build_free_form_payloads(
+    envelope: &ChannelMessageEnvelope,
+    text: Option<&str>,
+    dest_id: &str,
+    graph_base: &str,
+    cfg: &ProviderConfig,
+    token: &str,
+) -> Result<Vec<Value>, String> {
+    let mut payloads = Vec::new();
+    let mut caption = text;
+    let as_sticker = envelope
+        .metadata
+        .get(SEND_AS_STICKER_KEY)
+        .is_some_and(|value| value == "true");
+    let first_takes_caption = envelope
+        .attachments
+        .first()
+        .map(|att| media::supports_caption(media::media_kind(&att.mime_type, as_sticker)))
+        .unwrap_or(false);
+    if let Some(body) = text
+        && !first_takes_caption
+    {
+        payloads.push(json!({
+            "messaging_product": "whatsapp",
+            "to": dest_id,
+            "type": "text",
+            "text": {"body": body},
+        }));
+        caption = None;
+    }
+    for attachment in &envelope.attachments {
+        payloads.push(build_media_payload(
+            attachment,
+            as_sticker,
+            dest_id,
+            caption.take(),
+            graph_base,
+            cfg,
+            token,
+        )?);
+    }
+    Ok(payloads)
+}
+
+/// Checks the customer-service window for `wa_id`. Returns the re-engagement
+/// template payload when the window is closed and a template is configured,
+/// or a non-retryable error response when it is closed without one.
+fn window_fallback(cfg: &ProviderConfig, wa_id: &str) -> Result<Option<Value>, Vec<u8>> {
+    let record = match window::load_record(&cfg.phone_number_id, wa_id) {
+        Ok(record) => record,
+        Err(err) => {
+            println!("whatsapp window lookup failed: {err}");
+            None
+        }
+    };
+    let state = window::window_state(record.as_ref(), Utc::now().timestamp());
+    let WindowState::Closed { expired_at } = state else {
+        return Ok(None);
+    };
+    match cfg.reengagement_template.as_ref() {
+        Some(template) => Ok(Some(template_payload(wa_id, template))),
+        None => Err(json_bytes(&json!({
+            "ok": false,
+            "error": "customer service window closed; send an approved template to re-engage",
+            "code": "window_closed",
+            "retryable": false,
+            "window": {"status": state.label(), "expired_at": expired_at},
+        }))),
+    }
+}
+
+fn template_payload(to: &str, template: &TemplateConfig) -> Value {
+    let mut body = json!({
+        "name": template.name,
+        "language": {"code": template.language},
     });
+    if let Some(components) = template.components.as_ref() {
+        body["components"] = components.clone();
+    }
+    json!({
+        "messaging_product": "whatsapp",
+        "to": to,
+        "type": "template",
+        "template": body,
+    })
+}
+
+/// Turns an envelope attachment into a media message, uploading inline bytes first.
+fn build_media_payload(
+    attachment: &Attachment,
+    as_sticker: bool,
+    to: &str,
+    caption: Option<&str>,
+    graph_base: &str,
+    cfg: &ProviderConfig,
+    token: &str,
+) -> Result<Value, String> {
+    let kind = media::media_kind(&attachment.mime_type, as_sticker);
+    let media_ref = match media::media_source(attachment)? {
+        media::MediaSource::Link(link) => json!({"link": link}),
+        media::MediaSource::Id(id) => json!({"id": id}),
+        media::MediaSource::Inline(bytes) => {
+            let filename = attachment.name.as_deref().unwrap_or(kind);
+            let id = media::upload_media(
+                graph_base,
+                &cfg.phone_number_id,
+                token,
+                &attachment.mime_type,
+                filename,
+                &bytes,
+            )?;
+            json!({"id": id})
+        }
+    };
+    Ok(media::media_message_payload(
+        to,
+        kind,
+        media_ref,
+        caption,
+        attachment.name.as_deref(),
+    ))
+}
 
+/// Posts one `/messages` body and returns the WhatsApp message id with the raw response.
+fn post_message(url: &str, token: &str, payload: &Value) -> Result<(String, Value), String> {
     let request = client::Request {
         method: "POST".into(),
-        url,
+        url: url.to_string(),
         headers: vec![
             ("Content-Type".into(), "application/json".into()),
             ("Authorization".into(), format!("Bearer {token}")),
         ],
-        body: Some(serde_json::to_vec(&payload).unwrap_or_else(|_| b"{}".to_vec())),
+        body: Some(serde_json::to_vec(payload).unwrap_or_else(|_| b"{}".to_vec())),
     };
 
-    let resp = match client::send(&request, None, None) {
-        Ok(resp) => resp,
-        Err(err) => {
-            return json_bytes(
-                &json!({"ok": false, "error": format!("transport error: {}", err.message)}),
-            );
-        }
-    };
+    let resp = client::send(&request, None, None)
+        .map_err(|err| format!("transport error: {}", err.message))?;
 
     if resp.status < 200 || resp.status >= 300 {
-        return json_bytes(
-            &json!({"ok": false, "error": format!("whatsapp returned status {}", resp.status)}),
-        );
+        return Err(format!("whatsapp returned status {}", resp.status));
     }
 
     let body = resp.body.unwrap_or_default();
@@ -232,16 +435,7 @@ fn handle_send(input_json: &[u8]) -> Vec<u8> {
         .and_then(|v| v.as_str())
         .unwrap_or("wa-message")
         .to_string();
-    let provider_message_id = format!("whatsapp:{msg_id}");
-
-    json_bytes(&json!({
-        "ok": true,
-        "status": "sent",
-        "provider_type": PROVIDER_TYPE,
-        "message_id": msg_id,
-        "provider_message_id": provider_message_id,
-        "response": body_json
-    }))
+    Ok((msg_id, body_json))
 }
 
 fn build_send_envelope_from_input(parsed: &Value) -> Result<ChannelMessageEnvelope, String> {
@@ -356,22 +550,23 @@ fn handle_reply(_input_json: &[u8]) -> Vec<u8> {
     if token.is_empty() {
         return json_bytes(&json!({"ok": false, "error": "access token empty"}));
     }
-    let api_base = cfg
-        .api_base_url
-        .unwrap_or_else(|| DEFAULT_API_BASE.to_string());
-    let api_version = cfg
-        .api_version
-        .unwrap_or_else(|| DEFAULT_API_VERSION.to_string());
+    let fallback = match window_fallback(&cfg, to_id) {
+        Ok(fallback) => fallback,
+        Err(resp) => return resp,
+    };
     let url = format!(
-        "{}/{}/{}/messages",
-        api_base, api_version, cfg.phone_number_id
+        "{}/{}/messages",
+        graph_base(Some(&cfg)),
+        cfg.phone_number_id
     );
-    let payload = json!({
-        "messaging_product": "whatsapp",
-        "to": to_id,
-        "type": "text",
-        "context": {"message_id": reply_to},
-        "text": { "body": text }
+    let payload = fallback.unwrap_or_else(|| {
+        json!({
+            "messaging_product": "whatsapp",
+            "to": to_id,
+            "type": "text",
+            "context": {"message_id": reply_to},
+            "text": { "body": text }
+        })
     });
     let request = client::Request {
         method: "POST".into(),
@@ -425,49 +620,397 @@ fn ingest_http(input_json: &[u8]) -> Vec<u8> {
         Err(err) => return http_out_error(400, &format!("invalid http input: {err}")),
     };
     if request.method.eq_ignore_ascii_case("GET") {
-        let challenge = parse_query(&request.query)
-            .and_then(|params| params.get("hub.challenge").cloned())
-            .unwrap_or_default();
-        let out = HttpOutV1 {
-            status: 200,
-            headers: Vec::new(),
-            body_b64: general_purpose::STANDARD.encode(challenge.as_bytes()),
-            events: Vec::new(),
-        };
-        return json_bytes(&out);
+        return handle_subscription_challenge(&request);
     }
     let body_bytes = match general_purpose::STANDARD.decode(&request.body_b64) {
         Ok(bytes) => bytes,
         Err(err) => return http_out_error(400, &format!("invalid body encoding: {err}")),
     };
+    match get_optional_secret(APP_SECRET_KEY) {
+        Some(Ok(app_secret)) => {
+            if let Err(reason) = verify_signature(&request.headers, &body_bytes, &app_secret) {
+                return http_out_error(403, &reason);
+            }
+        }
+        Some(Err(err)) => return http_out_error(500, &err),
+        None => return http_out_error(403, &format!("missing secret: {APP_SECRET_KEY}")),
+    }
     let body_val: Value = serde_json::from_slice(&body_bytes).unwrap_or(Value::Null);
-    let text = body_val
-        .get("text")
-        .and_then(|t| t.get("body"))
-        .and_then(Value::as_str)
-        .unwrap_or("")
-        .to_string();
-    let from = body_val
-        .get("from")
-        .and_then(Value::as_str)
-        .map(str::to_string);
-    let envelope = build_whatsapp_envelope(text.clone(), from.clone());
+    let mut events = parse_webhook_events(&body_val);
+    let cfg = request
+        .config
+        .as_ref()
+        .and_then(|value| parse_config_value(value).ok());
+    resolve_inbound_media(&mut events, &graph_base(cfg.as_ref()));
+    record_customer_windows(&mut events);
     let normalized = json!({
         "ok": true,
         "event": body_val,
-        "text": text,
-        "from": from,
+        "messages": events.iter().filter(|env| is_event_type(env, "message")).count(),
+        "statuses": events.iter().filter(|env| is_event_type(env, "status")).count(),
     });
     let normalized_bytes = serde_json::to_vec(&normalized).unwrap_or_else(|_| b"{}".to_vec());
     let out = HttpOutV1 {
         status: 200,
         headers: Vec::new(),
         body_b64: general_purpose::STANDARD.encode(&normalized_bytes),
-        events: vec![envelope],
+        events,
+    };
+    json_bytes(&out)
+}
+
+/// Persists the last inbound timestamp per user so sends can detect a closed
+/// customer-service window. Failures are surfaced as `window_error` metadata.
+fn record_customer_windows(events: &mut [ChannelMessageEnvelope]) {
+    let now = Utc::now().timestamp();
+    for envelope in events
+        .iter_mut()
+        .filter(|env| is_event_type(env, "message"))
+    {
+        let (Some(phone_number_id), Some(from)) = (
+            envelope.metadata.get("phone_number_id").cloned(),
+            envelope.metadata.get("from").cloned(),
+        ) else {
+            continue;
+        };
+        let at = envelope
+            .metadata
+            .get("timestamp")
+            .and_then(|ts| ts.parse::<i64>().ok())
+            .unwrap_or(now);
+        if let Err(err) = window::record_inbound(&phone_number_id, &from, at) {
+            envelope.metadata.insert("window_error".to_string(), err);
+        }
+    }
+}
+
+/// Answers Meta's `hub.challenge` handshake only when `hub.mode` is `subscribe`
+/// and `hub.verify_token` matches the configured verify token.
+fn handle_subscription_challenge(request: &HttpInV1) -> Vec<u8> {
+    let params = parse_query(&request.query).unwrap_or_default();
+    if params.get("hub.mode").map(String::as_str) != Some("subscribe") {
+        return http_out_error(403, "hub.mode must be subscribe");
+    }
+    let expected = match get_secret_string(VERIFY_TOKEN_KEY) {
+        Ok(value) => value,
+        Err(err) => return http_out_error(403, &err),
+    };
+    let provided = params
+        .get("hub.verify_token")
+        .map(String::as_str)
+        .unwrap_or_default();
+    if provided.is_empty() || provided != expected {
+        return http_out_error(403, "verify token mismatch");
+    }
+    let challenge = params.get("hub.challenge").cloned().unwrap_or_default();
+    let out = HttpOutV1 {
+        status: 200,
+        headers: Vec::new(),
+        body_b64: general_purpose::STANDARD.encode(challenge.as_bytes()),
+        events: Vec::new(),
     };
     json_bytes(&out)
 }
 
+/// Checks `X-Hub-Signature-256` (`sha256=<hex>`) against an HMAC of the raw body.
+fn verify_signature(headers: &[Header], body: &[u8], app_secret: &str) -> Result<(), String> {
+    let header = headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case(SIGNATURE_HEADER))
+        .map(|header| header.value.trim())
+        .ok_or_else(|| "missing signature".to_string())?;
+    let signature = header
+        .strip_prefix("sha256=")
+        .and_then(hex_decode)
+        .ok_or_else(|| "malformed signature".to_string())?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(app_secret.as_bytes())
+        .map_err(|_| "invalid app secret".to_string())?;
+    mac.update(body);
+    mac.verify_slice(&signature)
+        .map_err(|_| "invalid signature".to_string())
+}
+
+fn hex_decode(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(value.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+/// Flattens a Cloud API webhook (`entry[].changes[].value`) into one envelope per
+/// inbound message and one per delivery status update.
+fn parse_webhook_events(body: &Value) -> Vec<ChannelMessageEnvelope> {
+    let mut events = Vec::new();
+    let entries = body
+        .get("entry")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    for entry in entries {
+        let changes = entry
+            .get("changes")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for change in changes {
+            let Some(value) = change.get("value") else {
+                continue;
+            };
+            let phone_number_id = value
+                .get("metadata")
+                .and_then(|m| m.get("phone_number_id"))
+                .and_then(Value::as_str);
+            let contacts = value
+                .get("contacts")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            if let Some(messages) = value.get("messages").and_then(Value::as_array) {
+                for message in messages {
+                    if let Some(envelope) =
+                        build_message_envelope(message, contacts, phone_number_id)
+                    {
+                        events.push(envelope);
+                    }
+                }
+            }
+            if let Some(statuses) = value.get("statuses").and_then(Value::as_array) {
+                for status in statuses {
+                    if let Some(envelope) = build_status_envelope(status, phone_number_id) {
+                        events.push(envelope);
+                    }
+                }
+            }
+        }
+    }
+    events
+}
+
+fn build_message_envelope(
+    message: &Value,
+    contacts: &[Value],
+    phone_number_id: Option<&str>,
+) -> Option<ChannelMessageEnvelope> {
+    let message_id = message.get("id").and_then(Value::as_str)?;
+    let from = message.get("from").and_then(Value::as_str)?;
+    let message_type = message
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("unknown");
+    let mut metadata = base_metadata("message", phone_number_id);
+    metadata.insert("from".to_string(), from.to_string());
+    metadata.insert("message_id".to_string(), message_id.to_string());
+    metadata.insert("message_type".to_string(), message_type.to_string());
+    if let Some(name) = contact_profile_name(contacts, from) {
+        metadata.insert("contact_name".to_string(), name.to_string());
+    }
+    if let Some(timestamp) = message.get("timestamp").and_then(Value::as_str) {
+        metadata.insert("timestamp".to_string(), timestamp.to_string());
+    }
+    if let Some(context_id) = message
+        .get("context")
+        .and_then(|c| c.get("id"))
+        .and_then(Value::as_str)
+    {
+        metadata.insert("context_message_id".to_string(), context_id.to_string());
+    }
+    let mut text = extract_message_text(message);
+    let mut attachments = Vec::new();
+    if let Some((kind, media_obj)) = media::inbound_media(message) {
+        let media_id = media_obj
+            .get("id")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let mime_type = media_obj
+            .get("mime_type")
+            .and_then(Value::as_str)
+            .unwrap_or("application/octet-stream");
+        metadata.insert("media_id".to_string(), media_id.to_string());
+        metadata.insert("media_kind".to_string(), kind.to_string());
+        if let Some(sha256) = media_obj.get("sha256").and_then(Value::as_str) {
+            metadata.insert("media_sha256".to_string(), sha256.to_string());
+        }
+        if let Some(caption) = media_obj.get("caption").and_then(Value::as_str) {
+            text = caption.to_string();
+        }
+        attachments.push(Attachment {
+            mime_type: mime_type.to_string(),
+            url: format!("{}{media_id}", media::MEDIA_REF_PREFIX),
+            name: media_obj
+                .get("filename")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            size_bytes: None,
+        });
+    }
+    Some(ChannelMessageEnvelope {
+        id: format!("whatsapp-{message_id}"),
+        tenant: default_tenant_ctx(),
+        channel: "whatsapp".to_string(),
+        session_id: from.to_string(),
+        reply_scope: None,
+        from: Some(Actor {
+            id: from.to_string(),
+            kind: Some("user".into()),
+        }),
+        to: Vec::new(),
+        correlation_id: None,
+        text: Some(text),
+        attachments,
+        metadata,
+    })
+}
+
+/// Fills MIME type, sha256 and size for inbound media from the Graph media endpoint.
+/// Lookup failures are recorded as `media_error` so the message is still delivered.
+fn resolve_inbound_media(events: &mut [ChannelMessageEnvelope], graph_base: &str) {
+    if !events
+        .iter()
+        .any(|env| env.metadata.contains_key("media_id"))
+    {
+        return;
+    }
+    let token = match get_secret_string(DEFAULT_TOKEN_KEY) {
+        Ok(token) => token,
+        Err(err) => {
+            for envelope in events
+                .iter_mut()
+                .filter(|env| env.metadata.contains_key("media_id"))
+            {
+                envelope
+                    .metadata
+                    .insert("media_error".to_string(), err.clone());
+            }
+            return;
+        }
+    };
+    for envelope in events.iter_mut() {
+        let Some(media_id) = envelope.metadata.get("media_id").cloned() else {
+            continue;
+        };
+        match media::resolve_media(graph_base, &token, &media_id) {
+            Ok(info) => {
+                if let Some(attachment) = envelope.attachments.first_mut() {
+                    if let Some(mime_type) = info.mime_type {
+                        attachment.mime_type = mime_type;
+                    }
+                    attachment.size_bytes = info.file_size;
+                }
+                if let Some(sha256) = info.sha256 {
+                    envelope.metadata.insert("media_sha256".to_string(), sha256);
+                }
+            }
+            Err(err) => {
+                envelope.metadata.insert("media_error".to_string(), err);
+            }
+        }
+    }
+}
+
+fn build_status_envelope(
+    status: &Value,
+    phone_number_id: Option<&str>,
+) -> Option<ChannelMessageEnvelope> {
+    let message_id = status.get("id").and_then(Value::as_str)?;
+    let state = status.get("status").and_then(Value::as_str)?;
+    let recipient = status
+        .get("recipient_id")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let mut metadata = base_metadata("status", phone_number_id);
+    metadata.insert("status".to_string(), state.to_string());
+    metadata.insert("status_message_id".to_string(), message_id.to_string());
+    metadata.insert("recipient_id".to_string(), recipient.to_string());
+    if let Some(timestamp) = status.get("timestamp").and_then(Value::as_str) {
+        metadata.insert("timestamp".to_string(), timestamp.to_string());
+    }
+    if let Some(conversation_id) = status
+        .get("conversation")
+        .and_then(|c| c.get("id"))
+        .and_then(Value::as_str)
+    {
+        metadata.insert("conversation_id".to_string(), conversation_id.to_string());
+    }
+    if let Some(error) = status
+        .get("errors")
+        .and_then(Value::as_array)
+        .and_then(|errors| errors.first())
+    {
+        if let Some(code) = error.get("code").and_then(Value::as_i64) {
+            metadata.insert("error_code".to_string(), code.to_string());
+        }
+        if let Some(title) = error.get("title").and_then(Value::as_str) {
+            metadata.insert("error_title".to_string(), title.to_string());
+        }
+        if let Some(details) = error
+            .get("error_data")
+            .and_then(|d| d.get("details"))
+            .and_then(Value::as_str)
+        {
+            metadata.insert("error_details".to_string(), details.to_string());
+        }
+    }
+    Some(ChannelMessageEnvelope {
+        id: format!("whatsapp-status-{message_id}-{state}"),
+        tenant: default_tenant_ctx(),
+        channel: "whatsapp".to_string(),
+        session_id: if recipient.is_empty() {
+            "whatsapp".to_string()
+        } else {
+            recipient.to_string()
+        },
+        reply_scope: None,
+        from: None,
+        to: Vec::new(),
+        correlation_id: None,
+        text: None,
+        attachments: Vec::new(),
+        metadata,
+    })
+}
+
+fn base_metadata(event_type: &str, phone_number_id: Option<&str>) -> MessageMetadata {
+    let mut metadata = MessageMetadata::new();
+    metadata.insert("universal".to_string(), "true".to_string());
+    metadata.insert("channel_id".to_string(), "whatsapp".to_string());
+    metadata.insert("event_type".to_string(), event_type.to_string());
+    if let Some(id) = phone_number_id {
+        metadata.insert("phone_number_id".to_string(), id.to_string());
+    }
+    metadata
+}
+
+fn contact_profile_name<'a>(contacts: &'a [Value], wa_id: &str) -> Option<&'a str> {
+    contacts
+        .iter()
+        .find(|c| c.get("wa_id").and_then(Value::as_str) == Some(wa_id))?
+        .get("profile")
+        .and_then(|p| p.get("name"))
+        .and_then(Value::as_str)
+}
+
+fn extract_message_text(message: &Value) -> String {
+    let text = match message.get("type").and_then(Value::as_str) {
+        Some("text") => message.get("text").and_then(|t| t.get("body")),
+        Some("button") => message.get("button").and_then(|b| b.get("text")),
+        Some("interactive") => message.get("interactive").and_then(|i| {
+            i.get("button_reply")
+                .or_else(|| i.get("list_reply"))
+                .and_then(|reply| reply.get("title"))
+        }),
+        Some("reaction") => message.get("reaction").and_then(|r| r.get("emoji")),
+        _ => None,
+    };
+    text.and_then(Value::as_str).unwrap_or_default().to_string()
+}
+
+fn is_event_type(envelope: &ChannelMessageEnvelope, event_type: &str) -> bool {
+    envelope.metadata.get("event_type").map(String::as_str) == Some(event_type)
+}
+
 fn render_plan(input_json: &[u8]) -> Vec<u8> {
     let plan_in = match serde_json::from_slice::<RenderPlanInV1>(input_json) {
         Ok(value) => value,
@@ -562,6 +1105,152 @@ fn send_payload(input_json: &[u8]) -> Vec<u8> {
     }
 }
 
+/// Streams inbound media bytes back to the host so the bearer token never leaves
+/// the component. Accepts `media_id` or a `whatsapp-media:` attachment url.
+fn download_media(input_json: &[u8]) -> Vec<u8> {
+    let parsed: Value = match serde_json::from_slice(input_json) {
+        Ok(val) => val,
+        Err(err) => {
+            return json_bytes(&json!({"ok": false, "error": format!("invalid json: {err}")}));
+        }
+    };
+    let media_id = parsed
+        .get("media_id")
+        .and_then(Value::as_str)
+        .or_else(|| {
+            parsed
+                .get("url")
+                .and_then(Value::as_str)
+                .and_then(|url| url.strip_prefix(media::MEDIA_REF_PREFIX))
+        })
+        .map(str::trim)
+        .filter(|id| !id.is_empty());
+    let Some(media_id) = media_id else {
+        return json_bytes(&json!({"ok": false, "error": "media_id required"}));
+    };
+    let token = match get_secret_string(DEFAULT_TOKEN_KEY) {
+        Ok(token) => token,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let cfg = load_config(&parsed).ok();
+    let info = match media::resolve_media(&graph_base(cfg.as_ref()), &token, media_id) {
+        Ok(info) => info,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    match media::download_media_bytes(&info.url, &token) {
+        Ok(bytes) => json_bytes(&json!({
+            "ok": true,
+            "media_id": media_id,
+            "mime_type": info.mime_type,
+            "sha256": info.sha256,
+            "size_bytes": bytes.len(),
+            "body_b64": general_purpose::STANDARD.encode(&bytes),
+        })),
+        Err(err) => json_bytes(&json!({"ok": false, "error": err})),
+    }
+}
+
+/// Reports whether free-form messages can currently be sent to a user.
+fn window_status(input_json: &[u8]) -> Vec<u8> {
+    let parsed: Value = match serde_json::from_slice(input_json) {
+        Ok(val) => val,
+        Err(err) => {
+            return json_bytes(&json!({"ok": false, "error": format!("invalid json: {err}")}));
+        }
+    };
+    let cfg = match load_config(&parsed) {
+        Ok(cfg) => cfg,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let Some(wa_id) = parse_send_destination(&parsed).map(|dest| dest.id) else {
+        return json_bytes(&json!({"ok": false, "error": "destination required"}));
+    };
+    let record = match window::load_record(&cfg.phone_number_id, &wa_id) {
+        Ok(record) => record,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let now = Utc::now().timestamp();
+    let state = window::window_state(record.as_ref(), now);
+    let (expires_at, remaining_seconds) = match state {
+        WindowState::Open { expires_at } => (Some(expires_at), Some(expires_at - now)),
+        WindowState::Closed { expired_at } => (Some(expired_at), Some(0)),
+        WindowState::Unknown => (None, None),
+    };
+    json_bytes(&json!({
+        "ok": true,
+        "to": wa_id,
+        "status": state.label(),
+        "open": matches!(state, WindowState::Open { .. }),
+        "last_inbound_at": record.map(|r| r.last_inbound_at),
+        "expires_at": expires_at,
+        "remaining_seconds": remaining_seconds,
+        "template_fallback": cfg.reengagement_template.is_some(),
+    }))
+}
+
+/// Marks an inbound message as read (blue ticks). With `typing` set, also shows
+/// the typing indicator, which WhatsApp clears on the next reply or after 25s.
+fn mark_read(input_json: &[u8], typing: bool) -> Vec<u8> {
+    let parsed: Value = match serde_json::from_slice(input_json) {
+        Ok(val) => val,
+        Err(err) => {
+            return json_bytes(&json!({"ok": false, "error": format!("invalid json: {err}")}));
+        }
+    };
+    let cfg = match load_config(&parsed) {
+        Ok(cfg) => cfg,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let Some(message_id) = inbound_message_id(&parsed) else {
+        return json_bytes(&json!({"ok": false, "error": "message_id required"}));
+    };
+    let token = match get_secret_string(DEFAULT_TOKEN_KEY) {
+        Ok(token) => token,
+        Err(err) => return json_bytes(&json!({"ok": false, "error": err})),
+    };
+    let typing = typing
+        || parsed
+            .get("typing")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+    let mut payload = json!({
+        "messaging_product": "whatsapp",
+        "status": "read",
+        "message_id": message_id,
+    });
+    if typing {
+        payload["typing_indicator"] = json!({"type": "text"});
+    }
+    let url = format!(
+        "{}/{}/messages",
+        graph_base(Some(&cfg)),
+        cfg.phone_number_id
+    );
+    match post_message(&url, &token, &payload) {
+        Ok((_, response)) => json_bytes(&json!({
+            "ok": true,
+            "status": if typing { "typing" } else { "read" },
+            "message_id": message_id,
+            "response": response,
+        })),
+        Err(err) => json_bytes(&json!({"ok": false, "error": err})),
+    }
+}
+
+/// Accepts `message_id`, `metadata.message_id` (as set on inbound envelopes) or a
+/// `whatsapp:`-prefixed provider message id.
+fn inbound_message_id(parsed: &Value) -> Option<String> {
+    parsed
+        .get("message_id")
+        .or_else(|| parsed.get("metadata").and_then(|m| m.get("message_id")))
+        .or_else(|| parsed.get("provider_message_id"))
+        .and_then(Value::as_str)
+        .map(|id| id.trim())
+        .map(|id| id.strip_prefix("whatsapp:").unwrap_or(id))
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+}
+
 fn forward_send_payload(payload: &Value) -> Result<(), String> {
     let payload_bytes =
         serde_json::to_vec(payload).map_err(|err| format!("serialize failed: {err}"))?;
@@ -584,32 +1273,22 @@ fn forward_send_payload(payload: &Value) -> Result<(), String> {
     }
 }
 
-fn build_whatsapp_envelope(text: String, from: Option<String>) -> ChannelMessageEnvelope {
+/// Returns `{api_base}/{api_version}` for the Graph API, falling back to defaults.
+fn graph_base(cfg: Option<&ProviderConfig>) -> String {
+    let api_base = cfg
+        .and_then(|c| c.api_base_url.as_deref())
+        .unwrap_or(DEFAULT_API_BASE)
+        .trim_end_matches('/');
+    let api_version = cfg
+        .and_then(|c| c.api_version.as_deref())
+        .unwrap_or(DEFAULT_API_VERSION);
+    format!("{api_base}/{api_version}")
+}
+
+fn default_tenant_ctx() -> TenantCtx {
     let env = EnvId::try_from("default").expect("env id");
     let tenant = TenantId::try_from("default").expect("tenant id");
-    let mut metadata = MessageMetadata::new();
-    metadata.insert("universal".to_string(), "true".to_string());
-    metadata.insert("channel_id".to_string(), "whatsapp".to_string());
-    let sender = from.map(|id| Actor {
-        id,
-        kind: Some("user".into()),
-    });
-    if let Some(actor) = &sender {
-        metadata.insert("from".to_string(), actor.id.clone());
-    }
-    ChannelMessageEnvelope {
-        id: format!("whatsapp-{}", text),
-        tenant: TenantCtx::new(env.clone(), tenant.clone()),
-        channel: "whatsapp".to_string(),
-        session_id: "whatsapp".to_string(),
-        reply_scope: None,
-        from: sender,
-        to: Vec::new(),
-        correlation_id: None,
-        text: Some(text),
-        attachments: Vec::new(),
-        metadata,
-    }
+    TenantCtx::new(env, tenant)
 }
 
 fn parse_query(query: &Option<String>) -> Option<HashMap<String, String>> {
@@ -679,6 +1358,7 @@ fn load_config(input: &Value) -> Result<ProviderConfig, String> {
         "business_account_id",
         "api_base_url",
         "api_version",
+        "reengagement_template",
     ] {
         if let Some(v) = input.get(key) {
             partial.insert(key.to_string(), v.clone());
@@ -691,6 +1371,24 @@ fn load_config(input: &Value) -> Result<ProviderConfig, String> {
     Err("config required".into())
 }
 
+fn get_secret_string(key: &str) -> Result<String, String> {
+    match secrets_store::get(key) {
+        Ok(Some(bytes)) => String::from_utf8(bytes).map_err(|_| "secret not valid utf-8".into()),
+        Ok(None) => Err(format!("missing secret: {key}")),
+        Err(e) => Err(format!("secret store error: {e:?}")),
+    }
+}
+
+fn get_optional_secret(key: &str) -> Option<Result<String, String>> {
+    match secrets_store::get(key) {
+        Ok(Some(bytes)) => {
+            Some(String::from_utf8(bytes).map_err(|_| "secret not valid utf-8".into()))
+        }
+        Ok(None) => None,
+        Err(e) => Some(Err(format!("secret store error: {e:?}"))),
+    }
+}
+
 fn json_bytes<T: serde::Serialize>(value: &T) -> Vec<u8> {
     serde_json::to_vec(value).unwrap_or_else(|_| b"{}".to_vec())
 }
@@ -724,4 +1422,191 @@ mod tests {
         assert_eq!(cfg.api_version.as_deref(), Some("v20.0"));
         assert_eq!(cfg.phone_number_id, "pn");
     }
+
+    #[test]
+    fn inbound_message_id_accepts_metadata_and_provider_ids() {
+        assert_eq!(
+            inbound_message_id(&json!({"metadata": {"message_id": "wamid.1"}})).as_deref(),
+            Some("wamid.1")
+        );
+        assert_eq!(
+            inbound_message_id(&json!({"provider_message_id": "whatsapp:wamid.2"})).as_deref(),
+            Some("wamid.2")
+        );
+        assert!(inbound_message_id(&json!({"message_id": "  "})).is_none());
+    }
+
+    #[test]
+    fn reengagement_template_builds_template_payload() {
+        let cfg = load_config(&json!({
+            "config": {
+                "phone_number_id": "pn",
+                "reengagement_template": {"name": "follow_up"}
+            }
+        }))
+        .unwrap();
+        let template = cfg.reengagement_template.as_ref().expect("template");
+        assert_eq!(template.language, "en_US");
+        let payload = template_payload("15551234567", template);
+        assert_eq!(payload["type"], "template");
+        assert_eq!(payload["template"]["name"], "follow_up");
+        assert_eq!(payload["template"]["language"]["code"], "en_US");
+        assert!(payload["template"].get("components").is_none());
+    }
+
+    fn cloud_webhook() -> Value {
+        json!({
+            "object": "whatsapp_business_account",
+            "entry": [{
+                "id": "waba-1",
+                "changes": [{
+                    "field": "messages",
+                    "value": {
+                        "messaging_product": "whatsapp",
+                        "metadata": {"display_phone_number": "15550001111", "phone_number_id": "pn-1"},
+                        "contacts": [{"profile": {"name": "Ada"}, "wa_id": "15551234567"}],
+                        "messages": [
+                            {"from": "15551234567", "id": "wamid.1", "timestamp": "1700000000", "type": "text", "text": {"body": "hi"}},
+                            {"from": "15551234567", "id": "wamid.2", "timestamp": "1700000001", "type": "interactive",
+                             "interactive": {"type": "button_reply", "button_reply": {"id": "b1", "title": "Yes"}}}
+                        ],
+                        "statuses": [{
+                            "id": "wamid.out", "status": "failed", "timestamp": "1700000002", "recipient_id": "15557654321",
+                            "errors": [{"code": 131047, "title": "Re-engagement message", "error_data": {"details": "window closed"}}]
+                        }]
+                    }
+                }]
+            }]
+        })
+    }
+
+    #[test]
+    fn parse_webhook_emits_messages_and_statuses() {
+        let events = parse_webhook_events(&cloud_webhook());
+        assert_eq!(events.len(), 3);
+
+        let first = &events[0];
+        assert_eq!(first.id, "whatsapp-wamid.1");
+        assert_eq!(first.session_id, "15551234567");
+        assert_eq!(first.text.as_deref(), Some("hi"));
+        assert_eq!(
+            first.metadata.get("message_id").map(String::as_str),
+            Some("wamid.1")
+        );
+        assert_eq!(
+            first.metadata.get("contact_name").map(String::as_str),
+            Some("Ada")
+        );
+        assert_eq!(
+            first.metadata.get("phone_number_id").map(String::as_str),
+            Some("pn-1")
+        );
+        assert_eq!(events[1].text.as_deref(), Some("Yes"));
+
+        let status = &events[2];
+        assert_eq!(
+            status.metadata.get("event_type").map(String::as_str),
+            Some("status")
+        );
+        assert_eq!(
+            status.metadata.get("status").map(String::as_str),
+            Some("failed")
+        );
+        assert_eq!(
+            status.metadata.get("error_code").map(String::as_str),
+            Some("131047")
+        );
+        assert_eq!(status.session_id, "15557654321");
+        assert!(status.text.is_none());
+    }
+
+    #[test]
+    fn contact_name_requires_matching_wa_id() {
+        let contacts = vec![
+            json!({"profile": {"name": "Ada"}, "wa_id": "15550000001"}),
+            json!({"profile": {"name": "Grace"}, "wa_id": "15550000002"}),
+        ];
+        assert_eq!(
+            contact_profile_name(&contacts, "15550000002"),
+            Some("Grace")
+        );
+        assert_eq!(contact_profile_name(&contacts, "15559999999"), None);
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        let digest = mac.finalize().into_bytes();
+        let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+        format!("sha256={hex}")
+    }
+
+    #[test]
+    fn verify_signature_accepts_matching_hmac() {
+        let body = br#"{"entry":[]}"#;
+        let headers = vec![Header {
+            name: "x-hub-signature-256".into(),
+            value: sign("app-secret", body),
+        }];
+        assert!(verify_signature(&headers, body, "app-secret").is_ok());
+    }
+
+    #[test]
+    fn verify_signature_rejects_mismatch_and_missing_header() {
+        let body = br#"{"entry":[]}"#;
+        let headers = vec![Header {
+            name: SIGNATURE_HEADER.into(),
+            value: sign("other-secret", body),
+        }];
+        assert_eq!(
+            verify_signature(&headers, body, "app-secret").unwrap_err(),
+            "invalid signature"
+        );
+        assert_eq!(
+            verify_signature(&[], body, "app-secret").unwrap_err(),
+            "missing signature"
+        );
+        let malformed = vec![Header {
+            name: SIGNATURE_HEADER.into(),
+            value: "sha1=abcd".into(),
+        }];
+        assert_eq!(
+            verify_signature(&malformed, body, "app-secret").unwrap_err(),
+            "malformed signature"
+        );
+    }
+
+    #[test]
+    fn parse_webhook_maps_media_to_attachment_reference() {
+        let body = json!({
+            "entry": [{"changes": [{"value": {
+                "metadata": {"phone_number_id": "pn-1"},
+                "messages": [{
+                    "from": "15551234567", "id": "wamid.img", "type": "image",
+                    "image": {"id": "media-9", "mime_type": "image/jpeg", "sha256": "abc", "caption": "look"}
+                }]
+            }}]}]
+        });
+        let events = parse_webhook_events(&body);
+        assert_eq!(events.len(), 1);
+        let envelope = &events[0];
+        assert_eq!(envelope.text.as_deref(), Some("look"));
+        assert_eq!(envelope.attachments.len(), 1);
+        assert_eq!(envelope.attachments[0].url, "whatsapp-media:media-9");
+        assert_eq!(envelope.attachments[0].mime_type, "image/jpeg");
+        assert_eq!(
+            envelope.metadata.get("media_sha256").map(String::as_str),
+            Some("abc")
+        );
+        assert_eq!(
+            envelope.metadata.get("media_kind").map(String::as_str),
+            Some("image")
+        );
+    }
+
+    #[test]
+    fn parse_webhook_ignores_flat_payloads() {
+        let events = parse_webhook_events(&json!({"from": "x", "text": {"body": "hi"}}));
+        assert!(events.is_empty());
+    }
 }
@@ -0,0 +1,331 @@
+use super::bindings::greentic::http::client;
+use base64::{Engine as _, engine::general_purpose};
+use greentic_types::Attachment;
+use serde_json::{Value, json};
+
+/// Attachment URL scheme used for inbound media. The Graph download URL needs the
+/// bearer token, so envelopes only carry the media id and hosts fetch the bytes
+/// through the `download_media` op.
+pub(crate) const MEDIA_REF_PREFIX: &str = "whatsapp-media:";
+const MULTIPART_BOUNDARY: &str = "greentic-whatsapp-media-boundary-5f8c2d1e";
+const MEDIA_KINDS: [&str; 5] = ["image", "document", "audio", "video", "sticker"];
+
+/// Where an outbound attachment's bytes come from.
+#[derive(Debug, PartialEq)]
+pub(crate) enum MediaSource {
+    /// Publicly reachable URL that WhatsApp fetches itself.
+    Link(String),
+    /// Media id previously returned by `/{phone_number_id}/media`.
+    Id(String),
+    /// Inline bytes (from a `data:` URL) that must be uploaded first.
+    Inline(Vec<u8>),
+}
+
+pub(crate) struct MediaInfo {
+    pub url: String,
+    pub mime_type: Option<String>,
+    pub sha256: Option<String>,
+    pub file_size: Option<u64>,
+}
+
+/// Maps a MIME type onto the WhatsApp message type used to send it. WebP goes
+/// out as a sticker only when the sender asks for one, since stickers must meet
+/// WhatsApp's size and format rules; otherwise it is sent as a document (image
+/// messages take JPEG and PNG only).
+pub(crate) fn media_kind(mime_type: &str, as_sticker: bool) -> &'static str {
+    let mime = mime_type.trim().to_ascii_lowercase();
+    if mime == "image/webp" {
+        if as_sticker { "sticker" } else { "document" }
+    } else if mime.starts_with("image/") {
+        "image"
+    } else if mime.starts_with("video/") {
+        "video"
+    } else if mime.starts_with("audio/") {
+        "audio"
+    } else {
+        "document"
+    }
+}
+
+/// Audio and sticker messages cannot carry a caption.
+pub(crate) fn supports_caption(kind: &str) -> bool {
+    matches!(kind, "image" | "video" | "document")
+}
+
+pub(crate) fn media_source(attachment: &Attachment) -> Result<MediaSource, String> {
+    let url = attachment.url.trim();
+    if let Some(id) = url.strip_prefix(MEDIA_REF_PREFIX) {
+        return Ok(MediaSource::Id(id.to_string()));
+    }
+    if url.starts_with("https://") || url.starts_with("http://") {
+        return Ok(MediaSource::Link(url.to_string()));
+    }
+    if let Some(data) = url.strip_prefix("data:") {
+        let (header, encoded) = data
+            .split_once(',')
+            .ok_or_else(|| "invalid data url".to_string())?;
+        if !header.ends_with(";base64") {
+            return Err("data url must be base64 encoded".into());
+        }
+        let bytes = general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|err| format!("invalid data url: {err}"))?;
+        return Ok(MediaSource::Inline(bytes));
+    }
+    Err(format!("unsupported attachment url: {url}"))
+}
+
+/// Builds the `/messages` body for a single media message.
+pub(crate) fn media_message_payload(
+    to: &str,
+    kind: &str,
+    media_ref: Value,
+    caption: Option<&str>,
+    filename: Option<&str>,
+) -> Value {
+    let mut media = media_ref;
+    if let Some(caption) = caption.filter(|_| supports_caption(kind)) {
+        media["caption"] = Value::String(caption.to_string());
+    }
+    if kind == "document"
+        && let Some(filename) = filename
+    {
+        media["filename"] = Value::String(filename.to_string());
+    }
+    let mut payload = json!({
+        "messaging_product": "whatsapp",
+        "to": to,
+        "type": kind,
+    });
+    payload[kind] = media;
+    payload
+}
+
+/// Uploads inline bytes to `/{phone_number_id}/media` and returns the media id.
+pub(crate) fn upload_media(
+    graph_base: &str,
+    phone_number_id: &str,
+    token: &str,
+    mime_type: &str,
+    filename: &str,
+    bytes: &[u8],
+) -> Result<String, String> {
+    let body = multipart_body(mime_type, filename, bytes)?;
+    let request = client::Request {
+        method: "POST".into(),
+        url: format!("{graph_base}/{phone_number_id}/media"),
+        headers: vec![
+            (
+                "Content-Type".into(),
+                format!("multipart/form-data; boundary={MULTIPART_BOUNDARY}"),
+            ),
+            ("Authorization".into(), format!("Bearer {token}")),
+        ],
+        body: Some(body),
+    };
+    let resp = client::send(&request, None, None)
+        .map_err(|err| format!("transport error: {}", err.message))?;
+    if resp.status < 200 || resp.status >= 300 {
+        return Err(format!(
+            "whatsapp media upload returned status {}",
+            resp.status
+        ));
+    }
+    let body: Value = serde_json::from_slice(&resp.body.unwrap_or_default()).unwrap_or(Value::Null);
+    body.get("id")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| "whatsapp media upload returned no id".to_string())
+}
+
+/// Builds the upload body. The MIME type and filename end up in part headers,
+/// so control characters are refused and the filename is percent-encoded
+/// where it could end the quoted string (RFC 7578 §4.2).
+fn multipart_body(mime_type: &str, filename: &str, bytes: &[u8]) -> Result<Vec<u8>, String> {
+    if mime_type.chars().any(|c| c.is_control() || c == '"') || !mime_type.contains('/') {
+        return Err(format!("invalid media mime type: {mime_type:?}"));
+    }
+    if filename.chars().any(char::is_control) {
+        return Err("media filename contains control characters".into());
+    }
+    let filename = encode_filename(filename);
+    let mut body = Vec::with_capacity(bytes.len() + 512);
+    for (name, value) in [("messaging_product", "whatsapp"), ("type", mime_type)] {
+        body.extend_from_slice(
+            format!(
+                "--{MULTIPART_BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            )
+            .as_bytes(),
+        );
+    }
+    body.extend_from_slice(
+        format!(
+            "--{MULTIPART_BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: {mime_type}\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{MULTIPART_BOUNDARY}--\r\n").as_bytes());
+    Ok(body)
+}
+
+fn encode_filename(filename: &str) -> String {
+    let mut encoded = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        match c {
+            '"' | '\\' | '%' => encoded.push_str(&format!("%{:02X}", c as u32)),
+            _ => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// Looks up a media id on the Graph media endpoint.
+pub(crate) fn resolve_media(
+    graph_base: &str,
+    token: &str,
+    media_id: &str,
+) -> Result<MediaInfo, String> {
+    let request = client::Request {
+        method: "GET".into(),
+        url: format!("{graph_base}/{media_id}"),
+        headers: vec![("Authorization".into(), format!("Bearer {token}"))],
+        body: None,
+    };
+    let resp = client::send(&request, None, None)
+        .map_err(|err| format!("transport error: {}", err.message))?;
+    if resp.status < 200 || resp.status >= 300 {
+        return Err(format!(
+            "whatsapp media lookup returned status {}",
+            resp.status
+        ));
+    }
+    let body: Value = serde_json::from_slice(&resp.body.unwrap_or_default())
+        .map_err(|err| format!("invalid media JSON: {err}"))?;
+    let url = body
+        .get("url")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "media lookup returned no url".to_string())?;
+    Ok(MediaInfo {
+        url: url.to_string(),
+        mime_type: body
+            .get("mime_type")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        sha256: body
+            .get("sha256")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        file_size: body.get("file_size").and_then(|v| {
+            v.as_u64()
+                .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+        }),
+    })
+}
+
+/// Downloads media bytes from the lookaside URL returned by [`resolve_media`].
+pub(crate) fn download_media_bytes(url: &str, token: &str) -> Result<Vec<u8>, String> {
+    let request = client::Request {
+        method: "GET".into(),
+        url: url.to_string(),
+        headers: vec![("Authorization".into(), format!("Bearer {token}"))],
+        body: None,
+    };
+    let resp = client::send(&request, None, None)
+        .map_err(|err| format!("transport error: {}", err.message))?;
+    if resp.status < 200 || resp.status >= 300 {
+        return Err(format!(
+            "whatsapp media download returned status {}",
+            resp.status
+        ));
+    }
+    Ok(resp.body.unwrap_or_default())
+}
+
+/// Extracts the media object of an inbound image/document/audio/video/sticker message.
+pub(crate) fn inbound_media(message: &Value) -> Option<(&'static str, &Value)> {
+    let kind = message.get("type").and_then(Value::as_str)?;
+    let kind = MEDIA_KINDS.into_iter().find(|k| *k == kind)?;
+    let media = message.get(kind)?;
+    media.get("id").and_then(Value::as_str)?;
+    Some((kind, media))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(url: &str) -> Attachment {
+        Attachment {
+            mime_type: "image/png".into(),
+            url: url.into(),
+            name: None,
+            size_bytes: None,
+        }
+    }
+
+    #[test]
+    fn media_kind_maps_mime_types() {
+        assert_eq!(media_kind("image/jpeg", false), "image");
+        assert_eq!(media_kind("image/webp", false), "document");
+        assert_eq!(media_kind("image/webp", true), "sticker");
+        assert_eq!(media_kind("image/png", true), "image");
+        assert_eq!(media_kind("audio/ogg", false), "audio");
+        assert_eq!(media_kind("video/mp4", false), "video");
+        assert_eq!(media_kind("application/pdf", false), "document");
+    }
+
+    #[test]
+    fn media_source_classifies_urls() {
+        assert_eq!(
+            media_source(&attachment("https://cdn.example/a.png")).unwrap(),
+            MediaSource::Link("https://cdn.example/a.png".into())
+        );
+        assert_eq!(
+            media_source(&attachment("whatsapp-media:123")).unwrap(),
+            MediaSource::Id("123".into())
+        );
+        assert_eq!(
+            media_source(&attachment("data:image/png;base64,aGk=")).unwrap(),
+            MediaSource::Inline(b"hi".to_vec())
+        );
+        assert!(media_source(&attachment("ftp://nope")).is_err());
+    }
+
+    #[test]
+    fn media_payload_drops_caption_for_audio() {
+        let payload =
+            media_message_payload("1555", "audio", json!({"id": "m1"}), Some("hello"), None);
+        assert_eq!(payload["type"], "audio");
+        assert!(payload["audio"].get("caption").is_none());
+
+        let payload = media_message_payload(
+            "1555",
+            "document",
+            json!({"link": "https://x/y.pdf"}),
+            Some("report"),
+            Some("y.pdf"),
+        );
+        assert_eq!(payload["document"]["caption"], "report");
+        assert_eq!(payload["document"]["filename"], "y.pdf");
+    }
+
+    #[test]
+    fn multipart_body_contains_fields_and_file() {
+        let body =
+            String::from_utf8(multipart_body("image/png", "a.png", b"PNG").unwrap()).unwrap();
+        assert!(body.contains("name=\"messaging_product\"\r\n\r\nwhatsapp\r\n"));
+        assert!(body.contains("filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\nPNG\r\n"));
+        assert!(body.ends_with(&format!("--{MULTIPART_BOUNDARY}--\r\n")));
+    }
+
+    #[test]
+    fn multipart_body_escapes_filename_and_rejects_header_injection() {
+        let body =
+            String::from_utf8(multipart_body("image/png", "a\"b%.png", b"PNG").unwrap()).unwrap();
+        assert!(body.contains("filename=\"a%22b%25.png\"\r\n"));
+        assert!(multipart_body("image/png", "a.png\r\nX-Evil: 1", b"PNG").is_err());
+        assert!(multipart_body("image/png\r\nX-Evil: 1", "a.png", b"PNG").is_err());
+        assert!(multipart_body("image/png\"", "a.png", b"PNG").is_err());
+    }
+}
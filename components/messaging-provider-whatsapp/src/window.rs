@@ -0,0 +1,112 @@
+use super::bindings::greentic::state::state_store;
+use serde::{Deserialize, Serialize};
+
+/// WhatsApp only accepts free-form messages within 24 hours of the user's last
+/// inbound message; outside that window a template must be used (error 131047).
+pub(crate) const WINDOW_SECONDS: i64 = 24 * 60 * 60;
+
+/// Last inbound activity persisted per (phone_number_id, wa_id).
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub(crate) struct WindowRecord {
+    pub last_inbound_at: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum WindowState {
+    /// Window is open until `expires_at` (unix seconds).
+    Open { expires_at: i64 },
+    /// Window closed at `expired_at` (unix seconds).
+    Closed { expired_at: i64 },
+    /// No inbound message has been recorded for this user.
+    Unknown,
+}
+
+impl WindowState {
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            WindowState::Open { .. } => "open",
+            WindowState::Closed { .. } => "closed",
+            WindowState::Unknown => "unknown",
+        }
+    }
+}
+
+pub(crate) fn window_key(phone_number_id: &str, wa_id: &str) -> String {
+    format!("whatsapp:window:{phone_number_id}:{wa_id}")
+}
+
+pub(crate) fn window_state(record: Option<&WindowRecord>, now: i64) -> WindowState {
+    match record {
+        Some(record) => {
+            let expires_at = record.last_inbound_at + WINDOW_SECONDS;
+            if now < expires_at {
+                WindowState::Open { expires_at }
+            } else {
+                WindowState::Closed {
+                    expired_at: expires_at,
+                }
+            }
+        }
+        None => WindowState::Unknown,
+    }
+}
+
+pub(crate) fn load_record(
+    phone_number_id: &str,
+    wa_id: &str,
+) -> Result<Option<WindowRecord>, String> {
+    let key = window_key(phone_number_id, wa_id);
+    match state_store::read(&key, None) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|err| format!("invalid window record: {err}")),
+        Err(err) => {
+            let code = err.code.to_ascii_lowercase().replace('-', "_");
+            if code == "not_found" {
+                Ok(None)
+            } else {
+                Err(format!("state read error: {} - {}", err.code, err.message))
+            }
+        }
+    }
+}
+
+/// Records an inbound message, never moving the timestamp backwards so that
+/// out-of-order webhook deliveries cannot shorten the window.
+pub(crate) fn record_inbound(phone_number_id: &str, wa_id: &str, at: i64) -> Result<(), String> {
+    if let Some(existing) = load_record(phone_number_id, wa_id)?
+        && existing.last_inbound_at >= at
+    {
+        return Ok(());
+    }
+    let record = WindowRecord {
+        last_inbound_at: at,
+    };
+    let bytes = serde_json::to_vec(&record).map_err(|err| format!("serialize window: {err}"))?;
+    state_store::write(&window_key(phone_number_id, wa_id), &bytes, None)
+        .map(|_| ())
+        .map_err(|err| format!("state write error: {} - {}", err.code, err.message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_is_open_for_24_hours() {
+        let record = WindowRecord {
+            last_inbound_at: 1_000,
+        };
+        assert_eq!(
+            window_state(Some(&record), 1_000 + WINDOW_SECONDS - 1),
+            WindowState::Open {
+                expires_at: 1_000 + WINDOW_SECONDS
+            }
+        );
+        assert!(matches!(
+            window_state(Some(&record), 1_000 + WINDOW_SECONDS),
+            WindowState::Closed { .. }
+        ));
+        assert_eq!(window_state(None, 1_000), WindowState::Unknown);
+    }
+}
@@ -19,9 +19,15 @@ use bindings::greentic::secrets_store::secrets_store;
 const DEFAULT_API_BASE: &str = "https://webexapis.com/v1";
 const DEFAULT_RESOURCE: &str = "messages";
 const DEFAULT_EVENT: &str = "created";
+const ATTACHMENT_ACTIONS_RESOURCE: &str = "attachmentActions";
 const TOKEN_SECRET: &str = "WEBEX_BOT_TOKEN";
 const DEFAULT_WEBHOOK_NAME: &str = "greentic:webex";
 const SIGNATURE_HEADER: &str = "X-Webex-Signature";
+const WEBHOOK_SECRET_KEY: &str = "WEBEX_WEBHOOK_SECRET";
+/// Appended to the secret key to hold the outgoing secret during a rotation.
+const PREVIOUS_SECRET_SUFFIX: &str = "_PREVIOUS";
+const STATUS_ACTIVE: &str = "active";
+const STATUS_DISABLED: &str = "disabled";
 
 #[derive(Deserialize)]
 struct ReconcileInput {
@@ -31,6 +37,14 @@ struct ReconcileInput {
     #[serde(default)]
     dry_run: Option<bool>,
     #[serde(default)]
+    attachment_actions: Option<bool>,
+    #[serde(default)]
+    subscriptions: Option<Vec<SubscriptionSpec>>,
+    #[serde(default)]
+    rotate_secret: Option<bool>,
+    #[serde(default)]
+    secret_key: Option<String>,
+    #[serde(default)]
     api_base_url: Option<String>,
     #[serde(default)]
     env: Option<String>,
@@ -46,6 +60,36 @@ struct ReconcileInput {
     team_id: Option<String>,
 }
 
+/// One desired `resource`/`event` webhook, optionally narrowed by a Webex filter
+/// (e.g. `roomId=...`).
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct SubscriptionSpec {
+    resource: String,
+    event: String,
+    #[serde(default)]
+    filter: Option<String>,
+}
+
+impl SubscriptionSpec {
+    fn new(resource: &str, event: &str) -> Self {
+        Self {
+            resource: resource.to_string(),
+            event: event.to_string(),
+            filter: None,
+        }
+    }
+
+    fn matches(&self, hook: &WebhookDetails) -> bool {
+        hook.resource == self.resource
+            && hook.event == self.event
+            && normalized_filter(&hook.filter) == normalized_filter(&self.filter)
+    }
+}
+
+fn normalized_filter(filter: &Option<String>) -> Option<&str> {
+    filter.as_deref().map(str::trim).filter(|f| !f.is_empty())
+}
+
 #[derive(Serialize)]
 struct ReconcileOutput {
     ok: bool,
@@ -64,9 +108,25 @@ struct WebhookSummary {
     resource: String,
     event: String,
     target_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filter: Option<String>,
     status: Option<String>,
 }
 
+impl WebhookSummary {
+    fn from_details(hook: &WebhookDetails) -> Self {
+        WebhookSummary {
+            id: Some(hook.id.clone()),
+            name: hook.name.clone(),
+            resource: hook.resource.clone(),
+            event: hook.event.clone(),
+            target_url: hook.target_url.clone(),
+            filter: hook.filter.clone(),
+            status: hook.status.clone(),
+        }
+    }
+}
+
 component_entrypoint!({
     manifest: describe_manifest,
     invoke: handle_message,
@@ -119,127 +179,313 @@ fn reconcile_webhook(input: &str) -> Result<String, String> {
         .trim_end_matches('/')
         .to_string();
 
-    let webhook_name = build_webhook_name(&parsed);
-    let mut actions = Vec::new();
-    let mut notes = vec![format!(
-        "Webex subscribes to {resource}.{event} and signs callbacks with the {header} header.",
-        resource = DEFAULT_RESOURCE,
-        event = DEFAULT_EVENT,
-        header = SIGNATURE_HEADER
-    )];
-
-    if parsed.dry_run.unwrap_or(false) {
-        actions.push("dry-run".to_string());
-        if parsed
-            .secret_token
+    let rotate = parsed.rotate_secret.unwrap_or(false);
+    let secret_token = parsed
+        .secret_token
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string);
+    if rotate && secret_token.is_none() {
+        return Err("rotate_secret requires secret_token".to_string());
+    }
+    let plan = ReconcilePlan {
+        target: target.to_string(),
+        webhook_name: build_webhook_name(&parsed),
+        subscriptions: desired_subscriptions(&parsed),
+        secret_token,
+        rotate,
+        secret_key: parsed
+            .secret_key
             .as_deref()
             .filter(|s| !s.trim().is_empty())
-            .is_some()
-        {
-            notes.push(format!(
-                "Provided secret will be shared with Webex so the {header} header can be verified on ingress.",
-                header = SIGNATURE_HEADER
-            ));
-        }
-        let planned = WebhookSummary {
-            id: None,
-            name: webhook_name.clone(),
-            resource: DEFAULT_RESOURCE.to_string(),
-            event: DEFAULT_EVENT.to_string(),
-            target_url: target.to_string(),
-            status: Some("planned".to_string()),
+            .unwrap_or(WEBHOOK_SECRET_KEY)
+            .to_string(),
+    };
+
+    let output = if parsed.dry_run.unwrap_or(false) {
+        plan_dry_run(&plan)
+    } else {
+        let mut api = HostApi {
+            api_base,
+            token: load_token()?,
         };
-        let output = ReconcileOutput {
+        reconcile_live(&mut api, &plan)?
+    };
+    serde_json::to_string(&output).map_err(|err| format!("serialization failed: {err}"))
+}
+
+/// Resolved reconcile request shared by the dry-run and live paths.
+struct ReconcilePlan {
+    target: String,
+    webhook_name: String,
+    subscriptions: Vec<SubscriptionSpec>,
+    secret_token: Option<String>,
+    rotate: bool,
+    secret_key: String,
+}
+
+impl ReconcilePlan {
+    fn previous_secret_key(&self) -> String {
+        format!("{}{PREVIOUS_SECRET_SUFFIX}", self.secret_key)
+    }
+
+    fn signature_notes(&self) -> Vec<String> {
+        self.subscriptions
+            .iter()
+            .map(|spec| {
+                format!(
+                    "Webex subscribes to {resource}.{event} and signs callbacks with the {header} header.",
+                    resource = spec.resource,
+                    event = spec.event,
+                    header = SIGNATURE_HEADER
+                )
+            })
+            .collect()
+    }
+
+    fn output(
+        &self,
+        actions: Vec<String>,
+        webhooks: Vec<WebhookSummary>,
+        notes: Vec<String>,
+    ) -> ReconcileOutput {
+        ReconcileOutput {
             ok: true,
             provider: "webex".to_string(),
-            target_url: target.to_string(),
-            webhook_name,
+            target_url: self.target.clone(),
+            webhook_name: self.webhook_name.clone(),
             actions,
-            webhooks: vec![planned],
+            webhooks,
             notes,
-        };
-        return serde_json::to_string(&output)
-            .map_err(|err| format!("serialization failed: {err}"));
+        }
+    }
+}
+
+fn plan_dry_run(plan: &ReconcilePlan) -> ReconcileOutput {
+    let mut notes = plan.signature_notes();
+    if plan.secret_token.is_some() {
+        notes.push(format!(
+            "Provided secret will be shared with Webex so the {header} header can be verified on ingress.",
+            header = SIGNATURE_HEADER
+        ));
+    }
+    if plan.rotate {
+        notes.push(format!(
+            "Rotation would keep the current secret as {previous}, store the new one as {key}, create new webhooks, delete the old webhooks and then clear {previous}.",
+            key = plan.secret_key,
+            previous = plan.previous_secret_key()
+        ));
     }
+    let planned = plan
+        .subscriptions
+        .iter()
+        .map(|spec| WebhookSummary {
+            id: None,
+            name: plan.webhook_name.clone(),
+            resource: spec.resource.clone(),
+            event: spec.event.clone(),
+            target_url: plan.target.clone(),
+            filter: spec.filter.clone(),
+            status: Some("planned".to_string()),
+        })
+        .collect();
+    plan.output(vec!["dry-run".to_string()], planned, notes)
+}
 
-    let token = load_token()?;
-    let mut webhooks = parse_webhooks(&list_webhooks(&api_base, &token)?);
+fn reconcile_live<A: WebexApi>(
+    api: &mut A,
+    plan: &ReconcilePlan,
+) -> Result<ReconcileOutput, String> {
+    let mut actions = Vec::new();
+    let mut notes = plan.signature_notes();
+    let mut webhooks = parse_webhooks(&api.list_webhooks()?);
     actions.push("list".to_string());
 
-    let primary_index = find_primary_webhook_index(&webhooks, &webhook_name, target);
-
-    let final_webhook = if let Some(idx) = primary_index {
-        let current = webhooks.get(idx).cloned().expect("index valid");
-        if current.target_url != target
-            || current.resource != DEFAULT_RESOURCE
-            || current.event != DEFAULT_EVENT
-        {
-            let response = update_webhook(
-                &api_base,
-                &token,
-                &current.id,
-                &webhook_name,
-                target,
-                &parsed.secret_token,
-            )?;
-            actions.push("update".to_string());
-            let updated = WebhookDetails::from_value(&response)?;
-            webhooks[idx] = updated.clone();
-            updated
-        } else {
-            actions.push("noop".to_string());
-            current
-        }
+    let managed_ids = if plan.rotate {
+        rotate_webhooks(api, plan, &mut webhooks, &mut actions, &mut notes)?
     } else {
-        let response = create_webhook(
-            &api_base,
-            &token,
-            &webhook_name,
-            target,
-            &parsed.secret_token,
-        )?;
-        actions.push("create".to_string());
-        let created = WebhookDetails::from_value(&response)?;
-        webhooks.push(created.clone());
-        created
+        let mut managed_ids = Vec::new();
+        for spec in &plan.subscriptions {
+            let primary_index = find_primary_webhook_index(
+                &webhooks,
+                &plan.webhook_name,
+                &plan.target,
+                spec,
+                &managed_ids,
+            );
+            let final_webhook = if let Some(idx) = primary_index {
+                let current = webhooks.get(idx).cloned().expect("index valid");
+                let disabled = current.status.as_deref() == Some(STATUS_DISABLED);
+                if current.target_url != plan.target
+                    || current.name != plan.webhook_name
+                    || disabled
+                {
+                    let response = api.update_webhook(
+                        &current.id,
+                        &plan.webhook_name,
+                        &plan.target,
+                        &plan.secret_token,
+                        disabled.then_some(STATUS_ACTIVE),
+                    )?;
+                    if disabled {
+                        actions.push("reenable".to_string());
+                        notes.push(format!(
+                            "Re-enabled webhook {} ({}.{}) that Webex had disabled.",
+                            current.id, current.resource, current.event
+                        ));
+                    } else {
+                        actions.push("update".to_string());
+                    }
+                    let updated = WebhookDetails::from_value(&response)?;
+                    webhooks[idx] = updated.clone();
+                    updated
+                } else {
+                    actions.push("noop".to_string());
+                    current
+                }
+            } else {
+                let response =
+                    api.create_webhook(&plan.webhook_name, &plan.target, spec, &plan.secret_token)?;
+                actions.push("create".to_string());
+                let created = WebhookDetails::from_value(&response)?;
+                webhooks.push(created.clone());
+                created
+            };
+            managed_ids.push(final_webhook.id);
+        }
+        managed_ids
     };
 
-    let duplicates: Vec<String> = webhooks
-        .iter()
-        .filter(|hook| hook.name == webhook_name && hook.id != final_webhook.id)
-        .map(|hook| hook.id.clone())
-        .collect();
-    for dup in &duplicates {
-        delete_webhook(&api_base, &token, dup)?;
+    let stale = stale_webhooks(&webhooks, &plan.webhook_name, &managed_ids);
+    for hook in &stale {
+        api.delete_webhook(&hook.id)?;
         actions.push("delete".to_string());
-        notes.push(format!("Removed duplicate webhook with id {dup}."));
+        if plan.subscriptions.iter().any(|spec| spec.matches(hook)) {
+            notes.push(format!("Removed duplicate webhook with id {}.", hook.id));
+        } else {
+            notes.push(format!(
+                "Removed stale webhook with id {} ({}.{}).",
+                hook.id, hook.resource, hook.event
+            ));
+        }
+    }
+    webhooks.retain(|hook| !stale.iter().any(|s| s.id == hook.id));
+
+    if plan.rotate {
+        let previous_key = plan.previous_secret_key();
+        api.write_secret(&previous_key, "");
+        actions.push("clear-previous-secret".to_string());
+        notes.push(format!(
+            "Cleared {previous_key} now that the old webhooks are gone."
+        ));
     }
-    webhooks.retain(|hook| !(hook.name == webhook_name && hook.id != final_webhook.id));
 
     let summaries = webhooks
         .iter()
-        .filter(|hook| hook.name == webhook_name)
-        .map(|hook| WebhookSummary {
-            id: Some(hook.id.clone()),
-            name: hook.name.clone(),
-            resource: hook.resource.clone(),
-            event: hook.event.clone(),
-            target_url: hook.target_url.clone(),
-            status: hook.status.clone(),
-        })
+        .filter(|hook| managed_ids.contains(&hook.id))
+        .map(WebhookSummary::from_details)
         .collect();
+    Ok(plan.output(actions, summaries, notes))
+}
 
-    let output = ReconcileOutput {
-        ok: true,
-        provider: "webex".to_string(),
-        target_url: target.to_string(),
-        webhook_name,
-        actions,
-        webhooks: summaries,
-        notes,
-    };
+/// Webhooks this component owns (by name) that no desired subscription claimed:
+/// duplicates and subscriptions that are no longer wanted. Other integrations'
+/// webhooks for the same target URL are left alone.
+fn stale_webhooks(
+    webhooks: &[WebhookDetails],
+    webhook_name: &str,
+    managed_ids: &[String],
+) -> Vec<WebhookDetails> {
+    webhooks
+        .iter()
+        .filter(|hook| hook.name == webhook_name && !managed_ids.contains(&hook.id))
+        .cloned()
+        .collect()
+}
 
-    serde_json::to_string(&output).map_err(|err| format!("serialization failed: {err}"))
+/// Explicit `subscriptions` win; otherwise `messages.created` is managed, plus
+/// `attachmentActions.created` for Adaptive Card submits when requested.
+fn desired_subscriptions(input: &ReconcileInput) -> Vec<SubscriptionSpec> {
+    if let Some(specs) = input.subscriptions.as_ref().filter(|s| !s.is_empty()) {
+        let mut unique: Vec<SubscriptionSpec> = Vec::new();
+        for spec in specs {
+            if !unique.contains(spec) {
+                unique.push(spec.clone());
+            }
+        }
+        return unique;
+    }
+    let mut subscriptions = vec![SubscriptionSpec::new(DEFAULT_RESOURCE, DEFAULT_EVENT)];
+    if input.attachment_actions.unwrap_or(false) {
+        subscriptions.push(SubscriptionSpec::new(
+            ATTACHMENT_ACTIONS_RESOURCE,
+            DEFAULT_EVENT,
+        ));
+    }
+    subscriptions
+}
+
+/// Keeps the current secret under the previous-secret key and stores
+/// `secret_token` as the current one, so the provider accepts callbacks from
+/// both webhook generations. Then installs a fresh webhook per subscription and
+/// lets the caller delete the old ones and clear the previous secret. If any new
+/// webhook is not active, the new ones are removed again and the old webhooks
+/// and secret are restored.
+fn rotate_webhooks<A: WebexApi>(
+    api: &mut A,
+    plan: &ReconcilePlan,
+    webhooks: &mut Vec<WebhookDetails>,
+    actions: &mut Vec<String>,
+    notes: &mut Vec<String>,
+) -> Result<Vec<String>, String> {
+    let new_secret = plan.secret_token.as_deref().unwrap_or_default();
+    let previous_key = plan.previous_secret_key();
+    let old_secret = api
+        .read_secret(&plan.secret_key)?
+        .filter(|secret| !secret.is_empty());
+    if let Some(old) = old_secret.as_deref() {
+        api.write_secret(&previous_key, old);
+        actions.push("store-previous-secret".to_string());
+    }
+    api.write_secret(&plan.secret_key, new_secret);
+    actions.push("store-secret".to_string());
+
+    let mut created: Vec<WebhookDetails> = Vec::new();
+    for spec in &plan.subscriptions {
+        let result = api
+            .create_webhook(&plan.webhook_name, &plan.target, spec, &plan.secret_token)
+            .and_then(|response| WebhookDetails::from_value(&response));
+        let failure = match result {
+            Ok(hook) if hook.status.as_deref().unwrap_or(STATUS_ACTIVE) == STATUS_ACTIVE => {
+                actions.push("create".to_string());
+                created.push(hook);
+                continue;
+            }
+            Ok(hook) => {
+                let status = hook.status.clone().unwrap_or_default();
+                created.push(hook);
+                format!("new {}.{} webhook is {status}", spec.resource, spec.event)
+            }
+            Err(err) => err,
+        };
+        for hook in &created {
+            let _ = api.delete_webhook(&hook.id);
+        }
+        api.write_secret(&plan.secret_key, old_secret.as_deref().unwrap_or_default());
+        api.write_secret(&previous_key, "");
+        return Err(format!(
+            "secret rotation aborted: {failure}; existing webhooks and secret left unchanged"
+        ));
+    }
+
+    notes.push(format!(
+        "Stored the new webhook secret as {key} and kept the old one as {previous_key} until the old webhooks are removed.",
+        key = plan.secret_key
+    ));
+    let ids = created.iter().map(|hook| hook.id.clone()).collect();
+    webhooks.extend(created);
+    Ok(ids)
 }
 
 fn build_webhook_name(input: &ReconcileInput) -> String {
@@ -270,22 +516,23 @@ fn first_non_empty<'a>(candidates: &'a [Option<&'a str>]) -> Option<&'a str> {
         .copied()
 }
 
+/// Prefers a webhook we named, then one already pointing at the target URL.
+/// Ids already claimed by another subscription are skipped.
 fn find_primary_webhook_index(
     webhooks: &[WebhookDetails],
     name: &str,
     target_url: &str,
+    spec: &SubscriptionSpec,
+    claimed: &[String],
 ) -> Option<usize> {
+    let candidate = |hook: &WebhookDetails| spec.matches(hook) && !claimed.contains(&hook.id);
     webhooks
         .iter()
-        .position(|hook| {
-            hook.name == name && hook.resource == DEFAULT_RESOURCE && hook.event == DEFAULT_EVENT
-        })
+        .position(|hook| candidate(hook) && hook.name == name)
         .or_else(|| {
-            webhooks.iter().position(|hook| {
-                hook.target_url == target_url
-                    && hook.resource == DEFAULT_RESOURCE
-                    && hook.event == DEFAULT_EVENT
-            })
+            webhooks
+                .iter()
+                .position(|hook| candidate(hook) && hook.target_url == target_url)
         })
 }
 
@@ -297,6 +544,96 @@ fn load_token() -> Result<String, String> {
     }
 }
 
+/// Webex webhook calls and secret storage used by a live reconcile.
+trait WebexApi {
+    fn list_webhooks(&mut self) -> Result<Value, String>;
+    fn create_webhook(
+        &mut self,
+        name: &str,
+        target_url: &str,
+        spec: &SubscriptionSpec,
+        secret_token: &Option<String>,
+    ) -> Result<Value, String>;
+    fn update_webhook(
+        &mut self,
+        webhook_id: &str,
+        name: &str,
+        target_url: &str,
+        secret_token: &Option<String>,
+        status: Option<&str>,
+    ) -> Result<Value, String>;
+    fn delete_webhook(&mut self, webhook_id: &str) -> Result<(), String>;
+    fn read_secret(&mut self, key: &str) -> Result<Option<String>, String>;
+    /// The secrets store has no delete; an empty value clears a secret.
+    fn write_secret(&mut self, key: &str, value: &str);
+}
+
+/// `WebexApi` backed by the host HTTP client and secrets store.
+struct HostApi {
+    api_base: String,
+    token: String,
+}
+
+impl WebexApi for HostApi {
+    fn list_webhooks(&mut self) -> Result<Value, String> {
+        list_webhooks(&self.api_base, &self.token)
+    }
+
+    fn create_webhook(
+        &mut self,
+        name: &str,
+        target_url: &str,
+        spec: &SubscriptionSpec,
+        secret_token: &Option<String>,
+    ) -> Result<Value, String> {
+        create_webhook(
+            &self.api_base,
+            &self.token,
+            name,
+            target_url,
+            spec,
+            secret_token,
+        )
+    }
+
+    fn update_webhook(
+        &mut self,
+        webhook_id: &str,
+        name: &str,
+        target_url: &str,
+        secret_token: &Option<String>,
+        status: Option<&str>,
+    ) -> Result<Value, String> {
+        update_webhook(
+            &self.api_base,
+            &self.token,
+            webhook_id,
+            name,
+            target_url,
+            secret_token,
+            status,
+        )
+    }
+
+    fn delete_webhook(&mut self, webhook_id: &str) -> Result<(), String> {
+        delete_webhook(&self.api_base, &self.token, webhook_id)
+    }
+
+    fn read_secret(&mut self, key: &str) -> Result<Option<String>, String> {
+        match secrets_store::get(key) {
+            Ok(Some(bytes)) => String::from_utf8(bytes)
+                .map(Some)
+                .map_err(|_| format!("secret {key} not utf-8")),
+            Ok(None) => Ok(None),
+            Err(err) => Err(format!("secret store error: {err:?}")),
+        }
+    }
+
+    fn write_secret(&mut self, key: &str, value: &str) {
+        secrets_store::put(key, value.as_bytes());
+    }
+}
+
 fn list_webhooks(api_base: &str, token: &str) -> Result<Value, String> {
     let request = client::Request {
         method: "GET".into(),
@@ -312,14 +649,21 @@ fn create_webhook(
     token: &str,
     name: &str,
     target_url: &str,
+    spec: &SubscriptionSpec,
     secret_token: &Option<String>,
 ) -> Result<Value, String> {
     let mut payload = json!({
         "name": name,
         "targetUrl": target_url,
-        "resource": DEFAULT_RESOURCE,
-        "event": DEFAULT_EVENT,
+        "resource": spec.resource,
+        "event": spec.event,
     });
+    if let Some(filter) = normalized_filter(&spec.filter) {
+        payload
+            .as_object_mut()
+            .expect("payload object")
+            .insert("filter".to_string(), Value::String(filter.to_string()));
+    }
     if let Some(secret) = secret_token.as_deref().filter(|s| !s.trim().is_empty()) {
         payload
             .as_object_mut()
@@ -348,11 +692,18 @@ fn update_webhook(
     name: &str,
     target_url: &str,
     secret_token: &Option<String>,
+    status: Option<&str>,
 ) -> Result<Value, String> {
     let mut payload = json!({
         "name": name,
         "targetUrl": target_url,
     });
+    if let Some(status) = status {
+        payload
+            .as_object_mut()
+            .expect("payload object")
+            .insert("status".to_string(), Value::String(status.to_string()));
+    }
     if let Some(secret) = secret_token.as_deref().filter(|s| !s.trim().is_empty()) {
         payload
             .as_object_mut()
@@ -414,6 +765,7 @@ struct WebhookDetails {
     resource: String,
     event: String,
     target_url: String,
+    filter: Option<String>,
     status: Option<String>,
 }
 
@@ -444,6 +796,10 @@ impl WebhookDetails {
             .and_then(|v| v.as_str())
             .unwrap_or("")
             .to_string();
+        let filter = value
+            .get("filter")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
         let status = value
             .get("status")
             .and_then(|v| v.as_str())
@@ -454,7 +810,321 @@ impl WebhookDetails {
             resource,
             event,
             target_url,
+            filter,
             status,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct FakeApi {
+        webhooks: Vec<Value>,
+        secrets: HashMap<String, String>,
+        /// New webhooks for this resource come back with this status.
+        created_status: Option<(String, String)>,
+        /// (current, previous) secret when each webhook was created.
+        secrets_at_create: Vec<(String, String)>,
+        next_id: usize,
+    }
+
+    impl FakeApi {
+        fn secret(&self, key: &str) -> &str {
+            self.secrets
+                .get(key)
+                .map(String::as_str)
+                .unwrap_or_default()
+        }
+
+        fn ids(&self) -> Vec<String> {
+            self.webhooks
+                .iter()
+                .map(|hook| hook["id"].as_str().unwrap().to_string())
+                .collect()
+        }
+    }
+
+    impl WebexApi for FakeApi {
+        fn list_webhooks(&mut self) -> Result<Value, String> {
+            Ok(json!({"items": self.webhooks}))
+        }
+
+        fn create_webhook(
+            &mut self,
+            name: &str,
+            target_url: &str,
+            spec: &SubscriptionSpec,
+            secret_token: &Option<String>,
+        ) -> Result<Value, String> {
+            self.next_id += 1;
+            self.secrets_at_create.push((
+                self.secret(WEBHOOK_SECRET_KEY).to_string(),
+                self.secret("WEBEX_WEBHOOK_SECRET_PREVIOUS").to_string(),
+            ));
+            let status = match &self.created_status {
+                Some((resource, status)) if *resource == spec.resource => status.clone(),
+                _ => STATUS_ACTIVE.to_string(),
+            };
+            let hook = json!({
+                "id": format!("new-{}", self.next_id),
+                "name": name,
+                "targetUrl": target_url,
+                "resource": spec.resource,
+                "event": spec.event,
+                "filter": spec.filter,
+                "secret": secret_token,
+                "status": status,
+            });
+            self.webhooks.push(hook.clone());
+            Ok(hook)
+        }
+
+        fn update_webhook(
+            &mut self,
+            webhook_id: &str,
+            name: &str,
+            target_url: &str,
+            _secret_token: &Option<String>,
+            status: Option<&str>,
+        ) -> Result<Value, String> {
+            let hook = self
+                .webhooks
+                .iter_mut()
+                .find(|hook| hook["id"] == webhook_id)
+                .ok_or("unknown webhook")?;
+            hook["name"] = json!(name);
+            hook["targetUrl"] = json!(target_url);
+            if let Some(status) = status {
+                hook["status"] = json!(status);
+            }
+            Ok(hook.clone())
+        }
+
+        fn delete_webhook(&mut self, webhook_id: &str) -> Result<(), String> {
+            self.webhooks.retain(|hook| hook["id"] != webhook_id);
+            Ok(())
+        }
+
+        fn read_secret(&mut self, key: &str) -> Result<Option<String>, String> {
+            Ok(self.secrets.get(key).cloned())
+        }
+
+        fn write_secret(&mut self, key: &str, value: &str) {
+            self.secrets.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    const TARGET: &str = "https://bot.example.com/webex";
+    const NAME: &str = "greentic:dev:acme:support:webex";
+
+    fn hook(id: &str, name: &str, resource: &str, event: &str, status: &str) -> Value {
+        json!({
+            "id": id,
+            "name": name,
+            "targetUrl": TARGET,
+            "resource": resource,
+            "event": event,
+            "status": status,
+        })
+    }
+
+    fn details(value: Value) -> WebhookDetails {
+        WebhookDetails::from_value(&value).unwrap()
+    }
+
+    fn input(value: Value) -> ReconcileInput {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn plan(subscriptions: Vec<SubscriptionSpec>, rotate: bool) -> ReconcilePlan {
+        ReconcilePlan {
+            target: TARGET.to_string(),
+            webhook_name: NAME.to_string(),
+            subscriptions,
+            secret_token: Some("new-secret".to_string()),
+            rotate,
+            secret_key: WEBHOOK_SECRET_KEY.to_string(),
+        }
+    }
+
+    #[test]
+    fn desired_subscriptions_dedupe_and_default() {
+        let explicit = desired_subscriptions(&input(json!({
+            "public_base_url": TARGET,
+            "attachment_actions": true,
+            "subscriptions": [
+                {"resource": "memberships", "event": "created"},
+                {"resource": "memberships", "event": "created"},
+                {"resource": "messages", "event": "created", "filter": "roomId=abc"},
+                {"resource": "messages", "event": "created"}
+            ]
+        })));
+        assert_eq!(explicit.len(), 3);
+        assert_eq!(explicit[1].filter.as_deref(), Some("roomId=abc"));
+
+        let defaults = desired_subscriptions(&input(json!({
+            "public_base_url": TARGET,
+            "attachment_actions": true,
+            "subscriptions": []
+        })));
+        assert_eq!(
+            defaults,
+            vec![
+                SubscriptionSpec::new(DEFAULT_RESOURCE, DEFAULT_EVENT),
+                SubscriptionSpec::new(ATTACHMENT_ACTIONS_RESOURCE, DEFAULT_EVENT),
+            ]
+        );
+        let plain = desired_subscriptions(&input(json!({"public_base_url": TARGET})));
+        assert_eq!(
+            plain,
+            vec![SubscriptionSpec::new(DEFAULT_RESOURCE, DEFAULT_EVENT)]
+        );
+    }
+
+    #[test]
+    fn spec_matches_resource_event_and_normalized_filter() {
+        let mut spec = SubscriptionSpec::new("messages", "created");
+        let mut existing = details(hook("a", NAME, "messages", "created", "active"));
+        assert!(spec.matches(&existing));
+        existing.filter = Some("  ".to_string());
+        assert!(spec.matches(&existing));
+        spec.filter = Some(" roomId=abc ".to_string());
+        assert!(!spec.matches(&existing));
+        existing.filter = Some("roomId=abc".to_string());
+        assert!(spec.matches(&existing));
+        assert!(!SubscriptionSpec::new("messages", "deleted").matches(&existing));
+    }
+
+    #[test]
+    fn primary_webhook_skips_claimed_ids() {
+        let webhooks = vec![
+            details(hook("a", NAME, "messages", "created", "active")),
+            details(hook("b", "other", "messages", "created", "active")),
+            details(hook("c", NAME, "messages", "created", "active")),
+        ];
+        let spec = SubscriptionSpec::new("messages", "created");
+        assert_eq!(
+            find_primary_webhook_index(&webhooks, NAME, TARGET, &spec, &[]),
+            Some(0)
+        );
+        let claimed = vec!["a".to_string()];
+        assert_eq!(
+            find_primary_webhook_index(&webhooks, NAME, TARGET, &spec, &claimed),
+            Some(2)
+        );
+        let claimed = vec!["a".to_string(), "c".to_string()];
+        assert_eq!(
+            find_primary_webhook_index(&webhooks, NAME, TARGET, &spec, &claimed),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn reconcile_reenables_and_removes_only_owned_extras() {
+        let mut api = FakeApi {
+            webhooks: vec![
+                hook("a", NAME, "messages", "created", STATUS_DISABLED),
+                hook("dup", NAME, "messages", "created", "active"),
+                hook("stale", NAME, "rooms", "updated", "active"),
+                hook(
+                    "foreign",
+                    "crm-integration",
+                    "memberships",
+                    "created",
+                    "active",
+                ),
+            ],
+            ..FakeApi::default()
+        };
+        let output = reconcile_live(
+            &mut api,
+            &plan(vec![SubscriptionSpec::new("messages", "created")], false),
+        )
+        .unwrap();
+
+        assert_eq!(output.actions, ["list", "reenable", "delete", "delete"]);
+        assert_eq!(api.ids(), ["a", "foreign"]);
+        assert_eq!(api.webhooks[0]["status"], STATUS_ACTIVE);
+        assert_eq!(output.webhooks.len(), 1);
+        assert!(
+            output
+                .notes
+                .iter()
+                .any(|n| n.contains("duplicate webhook with id dup"))
+        );
+        assert!(
+            output
+                .notes
+                .iter()
+                .any(|n| n.contains("stale webhook with id stale"))
+        );
+    }
+
+    #[test]
+    fn rotation_keeps_previous_secret_until_old_webhooks_are_gone() {
+        let mut api = FakeApi {
+            webhooks: vec![hook("old", NAME, "messages", "created", "active")],
+            ..FakeApi::default()
+        };
+        api.write_secret(WEBHOOK_SECRET_KEY, "old-secret");
+        let output = reconcile_live(
+            &mut api,
+            &plan(vec![SubscriptionSpec::new("messages", "created")], true),
+        )
+        .unwrap();
+
+        assert_eq!(
+            api.secrets_at_create,
+            [("new-secret".to_string(), "old-secret".to_string())]
+        );
+        assert_eq!(api.ids(), ["new-1"]);
+        assert_eq!(api.webhooks[0]["secret"], "new-secret");
+        assert_eq!(api.secret(WEBHOOK_SECRET_KEY), "new-secret");
+        assert_eq!(api.secret("WEBEX_WEBHOOK_SECRET_PREVIOUS"), "");
+        assert_eq!(
+            output.actions,
+            [
+                "list",
+                "store-previous-secret",
+                "store-secret",
+                "create",
+                "delete",
+                "clear-previous-secret"
+            ]
+        );
+    }
+
+    #[test]
+    fn rotation_aborts_when_a_new_webhook_is_not_active() {
+        let mut api = FakeApi {
+            webhooks: vec![
+                hook("old-msg", NAME, "messages", "created", "active"),
+                hook("old-card", NAME, "attachmentActions", "created", "active"),
+            ],
+            created_status: Some(("attachmentActions".to_string(), STATUS_DISABLED.to_string())),
+            ..FakeApi::default()
+        };
+        api.write_secret(WEBHOOK_SECRET_KEY, "old-secret");
+        let err = reconcile_live(
+            &mut api,
+            &plan(
+                vec![
+                    SubscriptionSpec::new("messages", "created"),
+                    SubscriptionSpec::new("attachmentActions", "created"),
+                ],
+                true,
+            ),
+        )
+        .err()
+        .unwrap();
+
+        assert!(err.contains("secret rotation aborted"), "{err}");
+        assert_eq!(api.ids(), ["old-msg", "old-card"]);
+        assert_eq!(api.secret(WEBHOOK_SECRET_KEY), "old-secret");
+        assert_eq!(api.secret("WEBEX_WEBHOOK_SECRET_PREVIOUS"), "");
+    }
+}
@@ -8,7 +8,7 @@ use std::{
 
 use anyhow::Result;
 use greentic_interfaces_wasmtime::host_helpers::v1::{
-    HostFns, add_all_v1_to_linker, http_client, secrets_store, state_store,
+    HostFns, add_all_v1_to_linker, http_client, secrets_store, state_store, telemetry_logger,
 };
 use greentic_interfaces_wasmtime::http_client_client_v1_1::greentic::http::http_client as http_client_client_alias;
 use serde_json::json;
@@ -105,6 +105,17 @@ impl secrets_store::SecretsStoreHostV1_1 for TestHostState {
     }
 }
 
+impl telemetry_logger::TelemetryLoggerHost for TestHostState {
+    fn log(
+        &mut self,
+        _span: telemetry_logger::SpanContext,
+        _fields: Vec<(String, String)>,
+        _ctx: Option<telemetry_logger::TenantCtx>,
+    ) -> Result<telemetry_logger::OpAck, telemetry_logger::TelemetryLoggerError> {
+        Ok(telemetry_logger::OpAck::Ok)
+    }
+}
+
 impl state_store::StateStoreHost for TestHostState {
     fn read(
         &mut self,
@@ -147,6 +158,7 @@ pub fn add_wasmtime_hosts(linker: &mut Linker<TestHostState>) -> Result<()> {
         HostFns {
             secrets_store_v1_1: Some(|state| state as &mut dyn secrets_store::SecretsStoreHostV1_1),
             state_store: Some(|state| state as &mut dyn state_store::StateStoreHost),
+            telemetry_logger: Some(|state| state as &mut dyn telemetry_logger::TelemetryLoggerHost),
             ..Default::default()
         },
     )?;
@@ -339,6 +351,7 @@ const SECRET_PAIRS: &[(&str, &str)] = &[
     ("WEBEX_BOT_TOKEN", "webex-token"),
     ("WHATSAPP_TOKEN", "whatsapp-token"),
     ("WHATSAPP_VERIFY_TOKEN", "whatsapp-verify"),
+    ("WHATSAPP_APP_SECRET", "whatsapp-app-secret"),
     ("EMAIL_PASSWORD", "email-secret"),
 ];
 
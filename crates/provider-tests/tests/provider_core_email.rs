@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
@@ -21,6 +22,9 @@ mod bindings {
     });
 }
 
+const GRAPH_REFRESH_TOKEN_KEY: &str = "EMAIL_GRAPH_REFRESH_TOKEN";
+const BINDING_ID: &str = "user-1|EMAIL_GRAPH_REFRESH_TOKEN";
+
 fn workspace_root() -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .parent()
@@ -94,6 +98,7 @@ fn new_engine() -> Engine {
 struct HostState {
     table: ResourceTable,
     wasi_ctx: WasiCtx,
+    state: HashMap<String, Vec<u8>>,
 }
 
 impl Default for HostState {
@@ -101,6 +106,7 @@ impl Default for HostState {
         Self {
             table: ResourceTable::new(),
             wasi_ctx: WasiCtxBuilder::new().inherit_stdio().build(),
+            state: HashMap::new(),
         }
     }
 }
@@ -117,15 +123,39 @@ impl WasiView for HostState {
 impl http_client::HttpClientHostV1_1 for HostState {
     fn send(
         &mut self,
-        _req: http_client::RequestV1_1,
+        req: http_client::RequestV1_1,
         _opts: Option<http_client::RequestOptionsV1_1>,
         _ctx: Option<http_client::TenantCtxV1_1>,
     ) -> Result<http_client::ResponseV1_1, http_client::HttpClientErrorV1_1> {
-        let body = serde_json::to_vec(&json!({"status":"ok"})).unwrap_or_else(|_| b"{}".to_vec());
+        // Minimal Graph double: token exchange, draft creation (new or createReply)
+        // and the 202 returned by `/send`.
+        let (status, body) = if req.url.contains("/oauth2/v2.0/token") {
+            (200, Some(json!({"access_token": "graph-token"})))
+        } else if req.url.ends_with("/send") {
+            (202, None)
+        } else if req.url.contains("createReply") {
+            (
+                201,
+                Some(json!({
+                    "id": "graph-reply-1",
+                    "internetMessageId": "<reply-1@example.com>",
+                    "conversationId": "conv-1"
+                })),
+            )
+        } else {
+            (
+                201,
+                Some(json!({
+                    "id": "graph-msg-1",
+                    "internetMessageId": "<msg-1@example.com>",
+                    "conversationId": "conv-1"
+                })),
+            )
+        };
         Ok(http_client::ResponseV1_1 {
-            status: 200,
+            status,
             headers: Vec::new(),
-            body: Some(body),
+            body: body.map(|value| serde_json::to_vec(&value).unwrap_or_else(|_| b"{}".to_vec())),
         })
     }
 }
@@ -249,8 +279,14 @@ fn alias_error_from_host(
 }
 
 impl secrets_store::SecretsStoreHostV1_1 for HostState {
-    fn get(&mut self, _key: String) -> Result<Option<Vec<u8>>, secrets_store::SecretsErrorV1_1> {
-        Ok(None)
+    fn get(&mut self, key: String) -> Result<Option<Vec<u8>>, secrets_store::SecretsErrorV1_1> {
+        let value = match key.as_str() {
+            "MS_GRAPH_CLIENT_ID" => Some("client-id"),
+            "MS_GRAPH_CLIENT_SECRET" => Some("client-secret"),
+            GRAPH_REFRESH_TOKEN_KEY => Some("refresh-token"),
+            _ => None,
+        };
+        Ok(value.map(|v| v.as_bytes().to_vec()))
     }
 
     fn put(&mut self, _key: String, _value: Vec<u8>) {}
@@ -259,25 +295,26 @@ impl secrets_store::SecretsStoreHostV1_1 for HostState {
 impl state_store::StateStoreHost for HostState {
     fn read(
         &mut self,
-        _key: state_store::StateKey,
+        key: state_store::StateKey,
         _ctx: Option<state_store::TenantCtx>,
     ) -> Result<Vec<u8>, state_store::StateStoreError> {
-        Err(state_store::StateStoreError {
-            code: "unimplemented".into(),
-            message: "state store not available in provider_core_email tests".into(),
-        })
+        self.state
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| state_store::StateStoreError {
+                code: "not_found".into(),
+                message: format!("no state for {key}"),
+            })
     }
 
     fn write(
         &mut self,
-        _key: state_store::StateKey,
-        _bytes: Vec<u8>,
+        key: state_store::StateKey,
+        bytes: Vec<u8>,
         _ctx: Option<state_store::TenantCtx>,
     ) -> Result<state_store::OpAck, state_store::StateStoreError> {
-        Err(state_store::StateStoreError {
-            code: "unimplemented".into(),
-            message: "state store not available in provider_core_email tests".into(),
-        })
+        self.state.insert(key, bytes);
+        Ok(state_store::OpAck::Ok)
     }
 
     fn delete(
@@ -425,11 +462,13 @@ fn invoke_send_smoke_test() -> Result<()> {
         "to": "test@example.com",
         "subject": "hello",
         "body": "hi there",
+        "binding_id": BINDING_ID,
         "config": {
             "host": "smtp.example.com",
             "port": 2525,
             "username": "user",
-            "from_address": "no-reply@example.com"
+            "from_address": "no-reply@example.com",
+            "graph_tenant_id": "tenant-1"
         }
     });
     let input_bytes = serde_json::to_vec(&input)?;
@@ -443,8 +482,18 @@ fn invoke_send_smoke_test() -> Result<()> {
         resp_json.get("provider_type"),
         Some(&Value::String("messaging.email.smtp".into()))
     );
-    assert!(resp_json.get("message_id").is_some());
-    assert!(resp_json.get("provider_message_id").is_some());
+    assert_eq!(
+        resp_json.get("message_id"),
+        Some(&Value::String("graph-msg-1".into()))
+    );
+    assert_eq!(
+        resp_json.get("provider_message_id"),
+        Some(&Value::String("graph:graph-msg-1".into()))
+    );
+    assert_eq!(
+        resp_json.get("session_id"),
+        Some(&Value::String("email:user-1:conv:conv-1".into()))
+    );
 
     Ok(())
 }
@@ -483,11 +532,13 @@ fn invoke_reply_smoke_test() -> Result<()> {
         "subject": "Re: hello",
         "body": "reply body",
         "reply_to_id": "msg-123",
+        "binding_id": BINDING_ID,
         "config": {
             "host": "smtp.example.com",
             "port": 25,
             "username": "u",
-            "from_address": "noreply@example.com"
+            "from_address": "noreply@example.com",
+            "graph_tenant_id": "tenant-1"
         }
     });
     let (resp,) = invoke
@@ -506,8 +557,12 @@ fn invoke_reply_smoke_test() -> Result<()> {
             .get("provider_message_id")
             .and_then(|v| v.as_str())
             .unwrap_or("")
-            .starts_with("smtp-reply:"),
-        "provider_message_id should be smtp-reply:*"
+            .starts_with("graph:"),
+        "provider_message_id should be graph:*"
+    );
+    assert_eq!(
+        resp_json.get("message_id"),
+        Some(&Value::String("graph-reply-1".into()))
     );
 
     Ok(())
@@ -564,3 +619,81 @@ fn reply_requires_to() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn reply_threads_from_session_state() -> Result<()> {
+    let component_path = ensure_component_artifact()?;
+    let engine = new_engine();
+    let component = Component::from_file(&engine, &component_path).context("loading component")?;
+    let mut linker = Linker::new(&engine);
+    add_wasi_to_linker(&mut linker);
+    add_http_client_http_client_world(&mut linker)?;
+    add_http_client_client_world(&mut linker)?;
+
+    let mut store = Store::new(&engine, HostState::default());
+    let instance = linker
+        .instantiate(&mut store, &component)
+        .context("instantiate for threaded reply")?;
+    let api_index: ComponentExportIndex = instance
+        .get_export_index(
+            &mut store,
+            None,
+            "greentic:provider-schema-core/schema-core-api@1.0.0",
+        )
+        .context("get schema-core-api export index for invoke")?;
+    let invoke_index = instance
+        .get_export_index(&mut store, Some(&api_index), "invoke")
+        .context("get invoke export index")?;
+    let invoke: TypedFunc<(String, Vec<u8>), (Vec<u8>,)> = instance
+        .get_typed_func(&mut store, invoke_index)
+        .context("get invoke func")?;
+    let config = json!({
+        "host": "smtp.example.com",
+        "username": "u",
+        "from_address": "noreply@example.com",
+        "graph_tenant_id": "tenant-1"
+    });
+
+    let send = json!({
+        "to": "user@example.com",
+        "subject": "Quarterly plan",
+        "body": "first message",
+        "binding_id": BINDING_ID,
+        "config": config,
+    });
+    let (resp,) = invoke
+        .call(&mut store, ("send".to_string(), serde_json::to_vec(&send)?))
+        .context("call invoke send")?;
+    invoke.post_return(&mut store)?;
+    let sent: Value = serde_json::from_slice(&resp).context("parse send output")?;
+    let session_id = sent
+        .get("session_id")
+        .and_then(Value::as_str)
+        .context("send should report a session")?
+        .to_string();
+
+    // No reply_to_id: the last message of the session is answered via createReply.
+    let reply = json!({
+        "to": "user@example.com",
+        "body": "follow up",
+        "session_id": session_id,
+        "binding_id": BINDING_ID,
+        "config": config,
+    });
+    let (resp,) = invoke
+        .call(
+            &mut store,
+            ("reply".to_string(), serde_json::to_vec(&reply)?),
+        )
+        .context("call invoke reply")?;
+    let replied: Value = serde_json::from_slice(&resp).context("parse reply output")?;
+    assert_eq!(replied.get("ok"), Some(&Value::Bool(true)));
+    assert_eq!(
+        replied.get("message_id"),
+        Some(&Value::String("graph-reply-1".into()))
+    );
+    assert_eq!(replied.get("session_id"), sent.get("session_id"));
+    assert!(replied.get("thread_error").is_none());
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
@@ -98,6 +99,7 @@ struct HostState {
     wasi_ctx: WasiCtx,
     last_request: RefCell<Option<bindings::greentic::http::client::Request>>,
     secret_value: String,
+    state: HashMap<String, Vec<u8>>,
 }
 
 impl HostState {
@@ -107,6 +109,7 @@ impl HostState {
             wasi_ctx: WasiCtxBuilder::new().inherit_stdio().build(),
             last_request: RefCell::new(None),
             secret_value: secret.to_string(),
+            state: HashMap::new(),
         }
     }
 }
@@ -155,6 +158,46 @@ impl bindings::greentic::secrets_store::secrets_store::Host for HostState {
     }
 }
 
+impl bindings::greentic::state::state_store::Host for HostState {
+    fn read(
+        &mut self,
+        key: bindings::greentic::interfaces_types::types::StateKey,
+        _ctx: Option<bindings::greentic::interfaces_types::types::TenantCtx>,
+    ) -> Result<Vec<u8>, bindings::greentic::state::state_store::HostError> {
+        self.state.get(&key).cloned().ok_or_else(|| {
+            bindings::greentic::state::state_store::HostError {
+                code: "not_found".into(),
+                message: format!("missing key {key}"),
+            }
+        })
+    }
+
+    fn write(
+        &mut self,
+        key: bindings::greentic::interfaces_types::types::StateKey,
+        bytes: Vec<u8>,
+        _ctx: Option<bindings::greentic::interfaces_types::types::TenantCtx>,
+    ) -> Result<
+        bindings::greentic::state::state_store::OpAck,
+        bindings::greentic::state::state_store::HostError,
+    > {
+        self.state.insert(key, bytes);
+        Ok(bindings::greentic::state::state_store::OpAck::Ok)
+    }
+
+    fn delete(
+        &mut self,
+        key: bindings::greentic::interfaces_types::types::StateKey,
+        _ctx: Option<bindings::greentic::interfaces_types::types::TenantCtx>,
+    ) -> Result<
+        bindings::greentic::state::state_store::OpAck,
+        bindings::greentic::state::state_store::HostError,
+    > {
+        self.state.remove(&key);
+        Ok(bindings::greentic::state::state_store::OpAck::Ok)
+    }
+}
+
 impl bindings::greentic::interfaces_types::types::Host for HostState {}
 
 fn add_wasi_to_linker(linker: &mut Linker<HostState>) {
@@ -262,6 +305,11 @@ fn invoke_send_smoke_test() -> Result<()> {
         |state: &mut HostState| state,
     )
     .expect("link secrets");
+    bindings::greentic::state::state_store::add_to_linker::<HostState, HasSelf<HostState>>(
+        &mut linker,
+        |state: &mut HostState| state,
+    )
+    .expect("link state");
     bindings::greentic::interfaces_types::types::add_to_linker::<HostState, HasSelf<HostState>>(
         &mut linker,
         |state: &mut HostState| state,
@@ -382,6 +430,11 @@ fn reply_requires_context() -> Result<()> {
         |state: &mut HostState| state,
     )
     .expect("link secrets");
+    bindings::greentic::state::state_store::add_to_linker::<HostState, HasSelf<HostState>>(
+        &mut linker,
+        |state: &mut HostState| state,
+    )
+    .expect("link state");
     bindings::greentic::interfaces_types::types::add_to_linker::<HostState, HasSelf<HostState>>(
         &mut linker,
         |state: &mut HostState| state,
@@ -443,6 +496,11 @@ fn invoke_reply_smoke_test() -> Result<()> {
         |state: &mut HostState| state,
     )
     .expect("link secrets");
+    bindings::greentic::state::state_store::add_to_linker::<HostState, HasSelf<HostState>>(
+        &mut linker,
+        |state: &mut HostState| state,
+    )
+    .expect("link state");
     bindings::greentic::interfaces_types::types::add_to_linker::<HostState, HasSelf<HostState>>(
         &mut linker,
         |state: &mut HostState| state,
@@ -388,6 +388,30 @@ fn http_input_from_fixture(fixture: HttpFixture) -> HttpInV1 {
     }
 }
 
+/// Adds the `X-Hub-Signature-256` header the provider checks against the
+/// harness's `WHATSAPP_APP_SECRET`.
+fn sign_whatsapp_request(http_in: &mut HttpInV1) -> Result<()> {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    let secret = default_secret_values()
+        .remove("WHATSAPP_APP_SECRET")
+        .context("harness app secret")?;
+    let body = STANDARD.decode(&http_in.body_b64)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(&secret).expect("hmac");
+    mac.update(&body);
+    let digest = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    http_in.headers.push(Header {
+        name: "X-Hub-Signature-256".to_string(),
+        value: format!("sha256={digest}"),
+    });
+    Ok(())
+}
+
 fn build_envelope(id: ProviderId) -> ChannelMessageEnvelope {
     let env = EnvId::try_from("default").expect("default env");
     let tenant = TenantId::try_from("default").expect("default tenant");
@@ -527,7 +551,10 @@ fn run_provider_checks(spec: &ProviderSpec) -> Result<()> {
     let mut harness = ProviderHarness::new(spec)?;
     if spec.ingest_supported {
         let fixture = load_http_fixture(spec.fixture)?;
-        let http_in = http_input_from_fixture(fixture);
+        let mut http_in = http_input_from_fixture(fixture);
+        if spec.id == ProviderId::Whatsapp {
+            sign_whatsapp_request(&mut http_in)?;
+        }
         let ingest_bytes = serde_json::to_vec(&http_in)?;
         let ingest_out = harness.call("ingest_http", ingest_bytes)?;
         let http_out: HttpOutV1 = serde_json::from_slice(&ingest_out)?;
@@ -777,14 +804,36 @@ fn email_ingest_graph_notifications() -> Result<()> {
         if req.url.contains("/oauth2/v2.0/token") {
             return Ok(graph_token_response("token-ingest"));
         }
+        if req.method.eq_ignore_ascii_case("GET") && req.url.contains("/attachments") {
+            return Ok(graph_json_response(json!({
+                "value": [
+                    {
+                        "@odata.type": "#microsoft.graph.fileAttachment",
+                        "name": "report.txt",
+                        "contentType": "text/plain",
+                        "size": 2,
+                        "isInline": false,
+                        "contentBytes": "aGk="
+                    }
+                ]
+            })));
+        }
         if req.method.eq_ignore_ascii_case("GET") && req.url.contains("/me/messages/") {
             return Ok(graph_json_response(json!({
                 "id": "msg-123",
                 "subject": "Hello",
                 "bodyPreview": "This is a preview",
+                "body": {
+                    "contentType": "html",
+                    "content": "<div>See the report&nbsp;attached.</div><div class=\"gmail_quote\">On Mon, Bob wrote:<blockquote>older</blockquote></div>"
+                },
                 "receivedDateTime": "2025-01-01T12:00:00Z",
                 "from": {"emailAddress": {"address": "sender@example.com"}},
+                "toRecipients": [{"emailAddress": {"address": "alice@example.com"}}],
+                "ccRecipients": [{"emailAddress": {"address": "carol@example.com"}}],
                 "internetMessageId": "<msg-123@example.com>",
+                "conversationId": "conv-123",
+                "hasAttachments": true,
                 "webLink": "https://graph.microsoft.com/message"
             })));
         }
@@ -846,5 +895,26 @@ fn email_ingest_graph_notifications() -> Result<()> {
         envelope.metadata.get("graph_message_id").unwrap(),
         "msg-123"
     );
+    assert_eq!(envelope.text.as_deref(), Some("See the report attached."));
+    assert_eq!(
+        envelope.metadata.get("inbound.to").unwrap(),
+        "alice@example.com"
+    );
+    assert_eq!(
+        envelope.metadata.get("inbound.cc").unwrap(),
+        "carol@example.com"
+    );
+    assert!(!envelope.metadata.contains_key("cc"));
+    assert_eq!(
+        envelope.metadata.get("internet_message_id").unwrap(),
+        "<msg-123@example.com>"
+    );
+    assert_eq!(
+        envelope.metadata.get("conversation_id").unwrap(),
+        "conv-123"
+    );
+    assert_eq!(envelope.session_id, "email:alice:conv:conv-123");
+    assert_eq!(envelope.attachments.len(), 1);
+    assert_eq!(envelope.attachments[0].url, "data:text/plain;base64,aGk=");
     Ok(())
 }